@@ -111,7 +111,7 @@ pub struct AvailBlobTransaction {
 }
 
 impl AvailBlobTransaction {
-    fn sender(&self) -> AvailAddress {
+    pub fn sender(&self) -> AvailAddress {
         self.address.clone()
     }
 
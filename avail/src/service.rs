@@ -16,6 +16,7 @@ use sp_core::crypto::Pair as PairTrait;
 use sp_keyring::sr25519::sr25519::Pair;
 use subxt::tx::PairSigner;
 use subxt::OnlineClient;
+use thiserror::Error as ThisError;
 
 /// Runtime configuration for the DA service
 #[derive(Clone, PartialEq, serde::Deserialize, serde::Serialize)]
@@ -35,6 +36,11 @@ pub struct DaProvider {
     signer: PairSigner<AvailConfig, Pair>,
 }
 
+//Block at the height is not produced yet, so callers following DA can wait for it.
+#[derive(Debug, ThisError)]
+#[error("Block at height {0} not found.")]
+pub struct BlockNotFound(pub u64);
+
 enum HeightOrHash {
     Hash([u8; 32]),
     Height(u64),
@@ -141,7 +147,7 @@ impl DaProvider {
             HeightOrHash::Height(i) => {
                 let hash = match node_client.rpc().block_hash(Some(i.into())).await? {
                     Some(i) => i,
-                    None => return Err(anyhow!("Hash for height {} not found.", i)),
+                    None => return Err(BlockNotFound(i).into()),
                 };
 
                 let header = match node_client.rpc().header(Some(hash)).await? {
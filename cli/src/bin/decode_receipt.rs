@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Error};
+use nft_core::{
+    receipts::{decode_tagged, ReceiptWithProof},
+    types::TransactionReceipt,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let nexus_receipt_url = "http://127.0.0.1:8080/receipt";
+    let key = match std::env::args().nth(1) {
+        Some(i) => i,
+        None => return Err(anyhow!("Usage: decode_receipt <receipt hash>")),
+    };
+
+    let url = reqwest::Url::parse_with_params(nexus_receipt_url, &[("key", &key)])?;
+    let response = reqwest::get(url.as_str()).await?;
+    let receipt_with_proof: ReceiptWithProof = response.json().await?;
+
+    if receipt_with_proof.receipt == TransactionReceipt::zero() {
+        println!("Receipt not aggregated yet.");
+
+        return Ok(());
+    }
+
+    //Decode locally as well, so receipts can be read even if nexus has no schema for them.
+    let decoded = match receipt_with_proof.decoded {
+        Some(i) => i,
+        None => decode_tagged(&receipt_with_proof.receipt.data)?,
+    };
+
+    println!("Chain: {}", receipt_with_proof.receipt.chain_id);
+    println!("{}", serde_json::to_string_pretty(&decoded)?);
+
+    Ok(())
+}
@@ -50,6 +50,7 @@ async fn main() -> Result<(), Error> {
         to: Address(signing_key.verification_key().to_bytes()),
         data: None,
         future_commitment: None,
+        future_deadline: 0,
         metadata,
     };
     let nft_tx = NftTransactionMessage::Mint(mint.clone());
@@ -0,0 +1,420 @@
+use crate::{
+    traits::Leaf,
+    types::{
+        AggregatedBatch, BatchHeader, ChainStateLeaf, ReceiptLeaf, ReceiptOrigin, ShaHasher,
+        StateUpdate, TransactionReceipt,
+    },
+};
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use sparse_merkle_tree::{traits::Value, H256};
+
+//Aggregation of verified app chain batches into the nexus receipts tree. Run
+//natively by nexus to build the tree update, and in the aggregation guest to
+//prove the transition from the previous aggregated batch to the new one.
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VerifiedBatch {
+    pub header: BatchHeader,
+    pub receipts: Vec<TransactionReceipt>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChainBatches {
+    pub chain_id: u64,
+    //Last header of the chain included in the previous aggregated batch.
+    pub last_header: BatchHeader,
+    pub batches: Vec<VerifiedBatch>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AggregationInput {
+    pub previous: AggregatedBatch,
+    pub chains: Vec<ChainBatches>,
+    pub state_update: StateUpdate<ReceiptLeaf>,
+    pub chain_state_update: StateUpdate<ChainStateLeaf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AggregationJournal {
+    pub previous_batch_hash: H256,
+    pub aggregated_batch: AggregatedBatch,
+    //Chain ID and header hash of every batch aggregated, so the app chain proofs
+    //can be matched to this aggregation.
+    pub batch_header_hashes: Vec<(u64, H256)>,
+}
+
+//Prefix of the aggregated batch records nexus posts to DA.
+pub const AGGREGATED_RECORD_MAGIC: &[u8; 8] = b"zknftagg";
+
+//Public record of an aggregated batch, posted by nexus to DA so the history of
+//receipts roots, and the app chain batches in each, can be audited.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AggregatedBatchRecord {
+    pub aggregated_batch: AggregatedBatch,
+    //Chain ID, batch number and header hash of every batch aggregated.
+    pub batches: Vec<(u64, u64, H256)>,
+}
+
+impl AggregatedBatchRecord {
+    pub fn new(aggregated_batch: AggregatedBatch, chains: &[ChainBatches]) -> Self {
+        let batches = chains
+            .iter()
+            .flat_map(|chain| {
+                chain.batches.iter().map(move |batch| {
+                    (
+                        chain.chain_id,
+                        batch.header.batch_number,
+                        batch.header.hash(),
+                    )
+                })
+            })
+            .collect();
+
+        AggregatedBatchRecord {
+            aggregated_batch,
+            batches,
+        }
+    }
+
+    pub fn to_blob(&self) -> Result<Vec<u8>, Error> {
+        let mut blob = AGGREGATED_RECORD_MAGIC.to_vec();
+
+        blob.extend(bincode::serialize(self)?);
+
+        Ok(blob)
+    }
+
+    //Returns None if the blob is not an aggregated batch record.
+    pub fn from_blob(blob: &[u8]) -> Result<Option<Self>, Error> {
+        if !blob.starts_with(AGGREGATED_RECORD_MAGIC) {
+            return Ok(None);
+        }
+
+        match bincode::deserialize(&blob[AGGREGATED_RECORD_MAGIC.len()..]) {
+            Ok(i) => Ok(Some(i)),
+            Err(e) => Err(anyhow!("Invalid aggregated batch record. {:?}", e)),
+        }
+    }
+}
+
+//Whether the receipts are the ones committed to by the batch header. App chain
+//batches hold a single transaction, whose receipt hash is the receipts root, so
+//any other receipt would be attested without being proven by the chain.
+pub fn receipts_match(header: &BatchHeader, receipts: &[TransactionReceipt]) -> bool {
+    match receipts {
+        [receipt] => receipt.to_h256() == header.receipts_root,
+        _ => false,
+    }
+}
+
+//Checks the batches of each chain follow on from its last aggregated header.
+pub fn check_continuity(chains: &[ChainBatches]) -> Result<(), Error> {
+    for chain in chains {
+        let mut last_header = &chain.last_header;
+
+        for batch in &chain.batches {
+            if batch.header.pre_state_root != last_header.state_root
+                || batch.header.batch_number != last_header.batch_number + 1
+            {
+                return Err(anyhow!(
+                    "Batch {} of chain {} does not follow batch {}",
+                    batch.header.batch_number,
+                    chain.chain_id,
+                    last_header.batch_number
+                ));
+            }
+
+            if !receipts_match(&batch.header, &batch.receipts) {
+                return Err(anyhow!(
+                    "Receipts of batch {} of chain {} do not match its header.",
+                    batch.header.batch_number,
+                    chain.chain_id
+                ));
+            }
+
+            if batch.receipts.iter().any(|r| r.chain_id != chain.chain_id) {
+                return Err(anyhow!("Receipt not from chain {}", chain.chain_id));
+            }
+
+            last_header = &batch.header;
+        }
+    }
+
+    Ok(())
+}
+
+//Leaves to be written to the receipts tree for the given batches. `indexed`
+//returns the leaf currently stored under a receipt hash. Hash leaves which
+//already exist are written unchanged, so the update proofs cover every key.
+pub fn receipt_leaves<F: FnMut(&H256) -> Result<ReceiptLeaf, Error>>(
+    chains: &[ChainBatches],
+    mut indexed: F,
+) -> Result<Vec<ReceiptLeaf>, Error> {
+    let mut leaves: Vec<ReceiptLeaf> = vec![];
+
+    for chain in chains {
+        for batch in &chain.batches {
+            for (index, receipt) in batch.receipts.iter().enumerate() {
+                let origin = ReceiptOrigin {
+                    chain_id: chain.chain_id,
+                    batch_number: batch.header.batch_number,
+                    index: index as u64,
+                };
+                let receipt_hash = receipt.to_h256();
+
+                leaves.push(ReceiptLeaf {
+                    key: origin.key(),
+                    origin: origin.clone(),
+                    receipt: receipt.clone(),
+                });
+
+                if leaves.iter().any(|leaf| leaf.key == receipt_hash) {
+                    continue;
+                }
+
+                let existing = indexed(&receipt_hash)?;
+
+                //Only the first occurrence of a receipt is indexed by its hash.
+                if existing.to_h256() != H256::zero() {
+                    leaves.push(existing);
+                } else {
+                    leaves.push(ReceiptLeaf {
+                        key: receipt_hash,
+                        origin,
+                        receipt: receipt.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(leaves)
+}
+
+//Chain state leaves to be written for the given batches, holding the state root
+//of the last batch of every chain with batches.
+pub fn chain_state_leaves(chains: &[ChainBatches]) -> Vec<ChainStateLeaf> {
+    chains
+        .iter()
+        .filter_map(|chain| {
+            chain.batches.last().map(|batch| ChainStateLeaf {
+                chain_id: chain.chain_id,
+                state_root: batch.header.state_root,
+            })
+        })
+        .collect()
+}
+
+//Checks the update of the chain states tree sets the state root of every chain
+//to that of its last batch, and returns the new chain states root.
+fn verify_chain_states(
+    previous: &AggregatedBatch,
+    chains: &[ChainBatches],
+    update: StateUpdate<ChainStateLeaf>,
+) -> Result<H256, Error> {
+    let (pre_set, pre_proof) = update.pre_state_with_proof;
+
+    match pre_proof.verify::<ShaHasher>(
+        &previous.chain_states_root,
+        pre_set.iter().map(|(k, v)| (*k, v.to_h256())).collect(),
+    ) {
+        Ok(true) => (),
+        Ok(false) => return Err(anyhow!("Invalid chain states pre merkle proof.")),
+        Err(_e) => return Err(anyhow!("Error while verifying merkle")),
+    }
+
+    let leaves = chain_state_leaves(chains);
+
+    if leaves.len() != pre_set.len()
+        || leaves
+            .iter()
+            .zip(pre_set.iter())
+            .any(|(leaf, (k, _))| leaf.get_key() != *k)
+    {
+        return Err(anyhow!("Chain states pre state does not match batches."));
+    }
+
+    //Chains aggregated before chain states were committed have no leaf yet.
+    for (leaf, (_, pre_leaf)) in leaves.iter().zip(pre_set.iter()) {
+        let last_header = match chains.iter().find(|i| i.chain_id == leaf.chain_id) {
+            Some(i) => &i.last_header,
+            None => continue,
+        };
+
+        if pre_leaf.to_h256() != H256::zero() && pre_leaf.state_root != last_header.state_root {
+            return Err(anyhow!(
+                "Last header of chain {} does not match chain states.",
+                leaf.chain_id
+            ));
+        }
+    }
+
+    let (_, post_proof) = update.post_state_with_proof;
+
+    match post_proof.verify::<ShaHasher>(
+        &update.post_state_root,
+        leaves
+            .iter()
+            .map(|leaf| (leaf.get_key(), leaf.to_h256()))
+            .collect(),
+    ) {
+        Ok(true) => (),
+        Ok(false) => return Err(anyhow!("Invalid chain states post merkle proof.")),
+        Err(_e) => return Err(anyhow!("Error while verifying merkle")),
+    }
+
+    Ok(update.post_state_root)
+}
+
+pub fn aggregate(input: AggregationInput) -> Result<AggregationJournal, Error> {
+    check_continuity(&input.chains)?;
+
+    let (pre_set, pre_proof) = input.state_update.pre_state_with_proof;
+
+    match pre_proof.verify::<ShaHasher>(
+        &input.previous.receipts_root,
+        pre_set.iter().map(|(k, v)| (*k, v.to_h256())).collect(),
+    ) {
+        Ok(true) => (),
+        Ok(false) => return Err(anyhow!("Invalid pre state merkle proof.")),
+        Err(_e) => return Err(anyhow!("Error while verifying merkle")),
+    }
+
+    let leaves = receipt_leaves(&input.chains, |key| {
+        match pre_set.iter().find(|(k, _)| k == key) {
+            Some((_, v)) => Ok(v.clone()),
+            None => Err(anyhow!("Receipt hash missing from pre state.")),
+        }
+    })?;
+
+    if leaves.len() != pre_set.len()
+        || leaves
+            .iter()
+            .zip(pre_set.iter())
+            .any(|(leaf, (k, _))| leaf.key != *k)
+    {
+        return Err(anyhow!("Pre state does not match aggregated receipts."));
+    }
+
+    //Origin keys are unique, so their leaves must not exist yet.
+    for (leaf, (_, pre_leaf)) in leaves.iter().zip(pre_set.iter()) {
+        if leaf.key == leaf.origin.key() && pre_leaf.to_h256() != H256::zero() {
+            return Err(anyhow!("Receipt origin already aggregated."));
+        }
+    }
+
+    let (_, post_proof) = input.state_update.post_state_with_proof;
+
+    match post_proof.verify::<ShaHasher>(
+        &input.state_update.post_state_root,
+        leaves
+            .iter()
+            .map(|leaf| (leaf.key, leaf.to_h256()))
+            .collect(),
+    ) {
+        Ok(true) => (),
+        Ok(false) => return Err(anyhow!("Invalid post state merkle proof.")),
+        Err(_e) => return Err(anyhow!("Error while verifying merkle")),
+    }
+
+    let batch_header_hashes = input
+        .chains
+        .iter()
+        .flat_map(|chain| {
+            chain
+                .batches
+                .iter()
+                .map(move |batch| (chain.chain_id, batch.header.hash()))
+        })
+        .collect();
+
+    let chain_states_root =
+        verify_chain_states(&input.previous, &input.chains, input.chain_state_update)?;
+
+    Ok(AggregationJournal {
+        previous_batch_hash: input.previous.hash(),
+        aggregated_batch: input
+            .previous
+            .next(input.state_update.post_state_root, chain_states_root),
+        batch_header_hashes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAIN_ID: u64 = 7000;
+
+    fn receipt(chain_id: u64, batch_number: u64) -> TransactionReceipt {
+        TransactionReceipt {
+            chain_id,
+            data: batch_number.to_be_bytes().to_vec(),
+        }
+    }
+
+    fn header(batch_number: u64) -> BatchHeader {
+        BatchHeader {
+            pre_state_root: H256::from([batch_number as u8; 32]),
+            state_root: H256::from([batch_number as u8 + 1; 32]),
+            receipts_root: receipt(CHAIN_ID, batch_number).to_h256(),
+            batch_number,
+            ..BatchHeader::default()
+        }
+    }
+
+    fn batch(batch_number: u64) -> VerifiedBatch {
+        VerifiedBatch {
+            header: header(batch_number),
+            receipts: vec![receipt(CHAIN_ID, batch_number)],
+        }
+    }
+
+    fn chain(batches: Vec<VerifiedBatch>) -> Vec<ChainBatches> {
+        vec![ChainBatches {
+            chain_id: CHAIN_ID,
+            last_header: header(1),
+            batches,
+        }]
+    }
+
+    #[test]
+    fn accepts_consecutive_batches() {
+        assert!(check_continuity(&chain(vec![batch(2), batch(3)])).is_ok());
+    }
+
+    #[test]
+    fn rejects_gap_in_batch_numbers() {
+        assert!(check_continuity(&chain(vec![batch(2), batch(4)])).is_err());
+    }
+
+    #[test]
+    fn rejects_pre_state_root_mismatch() {
+        let mut next = batch(2);
+        next.header.pre_state_root = H256::zero();
+
+        assert!(check_continuity(&chain(vec![next])).is_err());
+    }
+
+    #[test]
+    fn rejects_receipts_not_matching_header() {
+        let mut extra = batch(2);
+        extra.receipts.push(receipt(CHAIN_ID, 3));
+
+        let mut empty = batch(2);
+        empty.receipts.clear();
+
+        assert!(check_continuity(&chain(vec![extra])).is_err());
+        assert!(check_continuity(&chain(vec![empty])).is_err());
+    }
+
+    #[test]
+    fn rejects_receipt_of_other_chain() {
+        let mut other = batch(2);
+        other.receipts = vec![receipt(7001, 2)];
+        other.header.receipts_root = receipt(7001, 2).to_h256();
+
+        assert!(check_continuity(&chain(vec![other])).is_err());
+    }
+}
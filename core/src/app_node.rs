@@ -1,13 +1,21 @@
+use crate::aggregation::AggregatedBatchRecord;
 use crate::db::NodeDB;
+use crate::errors::{StateError, StateMachineError};
+use crate::events::{subscribe, NexusEvent};
 use crate::traits::StateMachine;
 use crate::traits::TxHasher;
 use crate::types::AggregatedBatch;
 use crate::types::BatchHeader;
+use crate::types::BatchRecord;
 use crate::types::BatchWithProof;
 use crate::types::ClientReply;
 use crate::types::DABatch;
+use crate::receipts::{ChainKind, ReceiptRegistry};
+use crate::types::HistoryEntry;
 use crate::types::TransactionWithReceipt;
-use crate::types::{AppChain, DaTxPointer, SubmitProofParam};
+use crate::types::{ChainHeads, ErrorReply, TxRecord, TxStatus};
+use crate::types::{DaTxPointer, NexusErrorReply, ProofEnvelope, SubmitProofParam};
+use crate::types::{SimulationResult, StateChange, StateUpdate};
 use crate::utils::hex_string_to_u8_array;
 use anyhow::Context;
 use anyhow::{anyhow, Error};
@@ -17,7 +25,7 @@ use parity_scale_codec::{Decode, Encode};
 use risc0_zkp::core::digest::Digest;
 use risc0_zkvm::{
     serde::{from_slice, to_vec},
-    Executor, ExecutorEnv,
+    Executor, ExecutorEnv, Receipt, Session,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sparse_merkle_tree::MerkleProof;
@@ -27,14 +35,36 @@ use std::net::SocketAddr;
 use std::str::FromStr;
 use std::time::Duration;
 use std::time::SystemTime;
+use thiserror::Error as ThisError;
 //Below imports for HTTP server.
 use reqwest;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::{Mutex, RwLock};
 use warp::{reply::Reply, Filter, Rejection};
 
+//Nexus rejected or could not process a batch submission.
+#[derive(Debug, ThisError)]
+pub enum SubmitBatchError {
+    //Nexus or DA failed, the same batch is submitted again.
+    #[error("Batch submission failed, will retry. {0}")]
+    Retry(String),
+    //Nexus rejected the batch itself.
+    #[error("Batch rejected by nexus. {0}")]
+    Invalid(String),
+}
+
+const SUBMIT_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const PENDING_SUBMISSION_KEY: &[u8] = b"pending_submission";
+//Longer than nexus keeps buffered batches, so a buffered batch is verified or
+//dropped by then.
+const BUFFERED_BATCH_TIMEOUT: Duration = Duration::from_secs(660);
+//Nexus events a batch submission waiting on them can fall behind by.
+const NEXUS_EVENTS_CAPACITY: usize = 100;
 const NEXUS_SUBMIT_BATCH_URL: &str = "http://127.0.0.1:8080/submit-batch";
 const NEXUS_LATEST_BATCH_URL: &str = "http://127.0.0.1:8080/current-batch";
+const NEXUS_EVENTS_URL: &str = "http://127.0.0.1:8080/events";
+const NEXUS_AGGREGATED_URL: &str = "http://127.0.0.1:8080/aggregated";
 
 #[derive(Clone)]
 pub struct AppNodeConfig {
@@ -45,6 +75,20 @@ pub struct AppNodeConfig {
     //TODO: Safer strategy to load seed so it is not accidentally revealed.
     pub seed: String,
     pub app_id: u32,
+    //Posts each batch proof to DA as well, so the chain can be verified from DA alone.
+    pub post_proofs_to_da: bool,
+    //Decides the receipt schemas decoded for the node's chain.
+    pub chain_kind: ChainKind,
+}
+
+//Batch proven and posted to DA, kept in the node db until it is saved.
+#[derive(Deserialize, Serialize)]
+struct PendingSubmission<T> {
+    batch: BatchWithProof<T>,
+    param: SubmitProofParam,
+    //Aggregated batch the transactions were executed against, to execute them
+    //again when the batch is resumed after a restart.
+    aggregated_batch: AggregatedBatch,
 }
 
 pub struct AppNode<
@@ -53,13 +97,22 @@ pub struct AppNode<
     S: StateMachine<V, T>,
 > {
     pub state_machine: Arc<Mutex<S>>,
+    //Committed state served to RPCs, replaced after every commit.
+    snapshot: Arc<RwLock<S::Snapshot>>,
     db: Arc<Mutex<NodeDB>>,
     da_service: AvailDaProvider,
-    chain: AppChain,
+    chain_id: u64,
     zkvm_elf: Box<[u8]>,
     zkvm_id: Digest,
     phantom_v: PhantomData<V>,
     tx_pool: Arc<Mutex<Vec<T>>>,
+    post_proofs_to_da: bool,
+    //Latest aggregated batch streamed by nexus.
+    latest_aggregated_batch: Arc<Mutex<Option<AggregatedBatch>>>,
+    //Events streamed by nexus, for batch submissions waiting on them.
+    nexus_events: broadcast::Sender<NexusEvent>,
+    //Receipt schemas of the node's own chain.
+    receipt_registry: ReceiptRegistry,
 }
 
 impl<
@@ -71,13 +124,18 @@ impl<
     pub fn clone(&self) -> Self {
         Self {
             state_machine: self.state_machine.clone(),
+            snapshot: self.snapshot.clone(),
             db: self.db.clone(),
             da_service: self.da_service.clone(),
-            chain: self.chain.clone(),
+            chain_id: self.chain_id,
             zkvm_elf: self.zkvm_elf.clone(),
             zkvm_id: self.zkvm_id,
             phantom_v: PhantomData,
             tx_pool: self.tx_pool.clone(),
+            post_proofs_to_da: self.post_proofs_to_da,
+            latest_aggregated_batch: self.latest_aggregated_batch.clone(),
+            nexus_events: self.nexus_events.clone(),
+            receipt_registry: self.receipt_registry.clone(),
         }
     }
 
@@ -85,7 +143,7 @@ impl<
         config: AppNodeConfig,
         zkvm_elf: &[u8],
         zkvm_id: impl Into<Digest>,
-        chain: AppChain,
+        chain_id: u64,
     ) -> Self {
         let node_db = NodeDB::from_path(String::from("./node_db"));
         let last_state_root: H256 = match node_db.get::<BatchHeader>(b"last_batch_header") {
@@ -93,7 +151,15 @@ impl<
             Ok(None) => H256::zero(),
             Err(e) => panic!("Could not start node. {:?}", e),
         };
-        let state_machine = Arc::new(Mutex::new(S::new(last_state_root.clone())));
+        let state_machine = S::new(last_state_root.clone(), chain_id);
+        let snapshot = match state_machine.snapshot() {
+            Ok(i) => i,
+            Err(e) => panic!("Could not start node. {:?}", e),
+        };
+        let mut receipt_registry = ReceiptRegistry::new();
+
+        receipt_registry.register_chain(chain_id, config.chain_kind);
+
         let da_service = AvailDaProvider::new(DaServiceConfig {
             node_client_url: config.node_client_url,
             light_client_url: config.light_client_url,
@@ -108,14 +174,19 @@ impl<
         );
 
         Self {
-            state_machine,
+            state_machine: Arc::new(Mutex::new(state_machine)),
+            snapshot: Arc::new(RwLock::new(snapshot)),
             db: Arc::new(Mutex::new(node_db)),
             da_service,
-            chain,
+            chain_id,
             zkvm_elf: zkvm_elf.into(),
             zkvm_id: zkvm_id.into(),
             phantom_v: PhantomData,
             tx_pool: Arc::new(Mutex::new(vec![])),
+            post_proofs_to_da: config.post_proofs_to_da,
+            latest_aggregated_batch: Arc::new(Mutex::new(None)),
+            nexus_events: broadcast::channel(NEXUS_EVENTS_CAPACITY).0,
+            receipt_registry,
         }
     }
 
@@ -138,6 +209,10 @@ impl<
             };
         }
 
+        //Batches executed but not saved before a restart are reverted.
+        self.update_heads(|heads| heads.soft = heads.proven).await;
+        self.resume_submission().await;
+
         loop {
             {
                 while {
@@ -159,46 +234,44 @@ impl<
                         }
                     };
 
-                    match self.execute_batch(tx).await {
-                        Ok(i) => {
-                            //TODO: Handle these cases better as submissions are already done and aggregated,
-                            //but below errors will create a mismatch.
-                            let mut state_machine = self.state_machine.lock().await;
-
-                            println!("Got statemachine lock.");
-
-                            match self.save_batch(i).await {
-                                Ok(i) => (),
-                                //TODO: Handle this case, as only batch details is not saved but everything else is done.
-                                Err(e) => panic!("{:?}. Critical need to restart node", e),
-                            }
-
-                            match state_machine.commit() {
-                                Ok(()) => (),
-                                Err(e) => {
-                                    panic!("Committing updates failed. Need to restart node.")
-                                }
-                            }
+                    let tx_hash = tx.to_h256();
 
-                            println!("Saved batch.");
-                        }
+                    match self.execute_batch(tx).await {
+                        Ok(i) => self.commit_batch(i).await,
                         Err(e) => {
                             println!(
                                 "Reverting state machine to root: {:?} due to error: {:?}",
                                 &last_state_root, e
                             );
-                            let mut state_machine = self.state_machine.lock().await;
-
-                            match state_machine.revert() {
-                                Ok(()) => (),
-                                //TODO: Need to restart the service on this error.
-                                Err(e) => panic!("Reverting state failed. Need to restart node."),
-                            };
+                            {
+                                let mut state_machine = self.state_machine.lock().await;
+
+                                match state_machine.revert() {
+                                    Ok(()) => (),
+                                    //TODO: Need to restart the service on this error.
+                                    Err(e) => {
+                                        panic!("Reverting state failed. Need to restart node.")
+                                    }
+                                };
+                            }
+                            self.update_heads(|heads| heads.soft = heads.proven).await;
+                            self.delete_pending_submission().await;
+
+                            self.set_tx_record(TxRecord {
+                                tx_hash,
+                                status: TxStatus::Failed {
+                                    reason: ErrorReply::from_error(&e),
+                                },
+                                batch_number: None,
+                                receipt: None,
+                                decoded: None,
+                            })
+                            .await;
                         }
                     }
 
-                    //TODO: Only remove if the transaction failed
-                    //due to state transition error. (or was successful)
+                    //Removed once successful, or if the transaction or its batch
+                    //was rejected.
                     {
                         let mut tx_pool = self.tx_pool.lock().await;
 
@@ -213,6 +286,335 @@ impl<
         }
     }
 
+    //Submits the batch proven before a restart again, before anything new is
+    //executed, so a different batch with its number is never built. Once nexus
+    //accepts it, its transactions are executed again to restore its state.
+    async fn resume_submission(&self) {
+        let pending: PendingSubmission<T> = {
+            let db = self.db.lock().await;
+
+            match db.get::<PendingSubmission<T>>(PENDING_SUBMISSION_KEY) {
+                Ok(Some(i)) => i,
+                Ok(None) => return,
+                Err(e) => panic!("Could not start node. {:?}", e),
+            }
+        };
+        let batch_number = pending.batch.header.batch_number;
+
+        println!("Submitting batch {} proven before restart.", batch_number);
+
+        if let Err(e) = self
+            .submit_until_accepted(&pending.param, batch_number)
+            .await
+        {
+            println!("Batch {} rejected by nexus. {:?}", batch_number, e);
+
+            for tx in &pending.batch.transaction_with_receipts {
+                self.set_tx_record(TxRecord {
+                    tx_hash: tx.transaction.to_h256(),
+                    status: TxStatus::Failed {
+                        reason: ErrorReply::from_error(&e),
+                    },
+                    batch_number: None,
+                    receipt: None,
+                    decoded: None,
+                })
+                .await;
+            }
+            self.delete_pending_submission().await;
+
+            return;
+        }
+
+        {
+            let mut state_machine = self.state_machine.lock().await;
+
+            for tx in &pending.batch.transaction_with_receipts {
+                match state_machine
+                    .execute_tx(tx.transaction.clone(), pending.aggregated_batch.clone())
+                {
+                    Ok(_i) => (),
+                    Err(e) => panic!(
+                        "Restoring batch {} failed. Need to restart node. {:?}",
+                        batch_number, e
+                    ),
+                }
+            }
+
+            match state_machine.get_root() {
+                Ok(i) if i == pending.batch.header.state_root => (),
+                Ok(_i) => panic!(
+                    "Restored state does not match batch {}. Need to restart node.",
+                    batch_number
+                ),
+                Err(e) => panic!(
+                    "Restoring batch {} failed. Need to restart node. {:?}",
+                    batch_number, e
+                ),
+            }
+        }
+
+        self.update_heads(|heads| heads.soft = batch_number).await;
+
+        for tx in &pending.batch.transaction_with_receipts {
+            self.update_tx_status(&tx.transaction.to_h256(), TxStatus::Verified)
+                .await;
+        }
+
+        self.commit_batch(pending.batch).await;
+    }
+
+    //Failing to delete is only logged, as the batch is then submitted again on
+    //restart and rejected by nexus.
+    async fn delete_pending_submission(&self) {
+        let db = self.db.lock().await;
+
+        match db.delete(PENDING_SUBMISSION_KEY) {
+            Ok(()) => (),
+            Err(e) => println!("Could not delete pending submission. {:?}", e),
+        }
+    }
+
+    //Saves a batch accepted by nexus and commits its state.
+    async fn commit_batch(&self, batch: BatchWithProof<T>) {
+        let batch_number = batch.header.batch_number;
+        //TODO: Handle these cases better as submissions are already done and aggregated,
+        //but below errors will create a mismatch.
+        let mut state_machine = self.state_machine.lock().await;
+
+        println!("Got statemachine lock.");
+
+        match self.save_batch(batch).await {
+            Ok(()) => (),
+            //TODO: Handle this case, as only batch details is not saved but everything else is done.
+            Err(e) => panic!("{:?}. Critical need to restart node", e),
+        }
+
+        {
+            //Nodes of the previous root can be removed by the commit,
+            //so reads of the snapshot wait until it is replaced.
+            let mut snapshot = self.snapshot.write().await;
+
+            match state_machine.commit() {
+                Ok(()) => (),
+                Err(e) => panic!("Committing updates failed. Need to restart node."),
+            }
+
+            *snapshot = match state_machine.snapshot() {
+                Ok(i) => i,
+                Err(e) => panic!("Snapshot of committed state failed. Need to restart node. {e}"),
+            };
+        }
+
+        println!("Saved batch.");
+        drop(state_machine);
+
+        //Nexus can aggregate the batch before it is saved, in which case it is
+        //marked aggregated here.
+        let latest_proof_number = self
+            .latest_aggregated_batch
+            .lock()
+            .await
+            .as_ref()
+            .map(|i| i.proof_number);
+
+        if let Some(i) = latest_proof_number {
+            if let Err(e) = self.sync_aggregated(i).await {
+                println!("Could not sync aggregated batches. {:?}", e);
+            }
+        }
+
+        //Aggregated before it was saved, in an aggregated batch already synced.
+        if let Err(e) = self.mark_unsaved_aggregated(batch_number).await {
+            println!("Could not mark batch {} aggregated. {:?}", batch_number, e);
+        }
+    }
+
+    async fn mark_unsaved_aggregated(&self, batch_number: u64) -> Result<(), Error> {
+        let unsaved: Option<(H256, u64)> = {
+            let db = self.db.lock().await;
+            let unsaved = db.get::<(H256, u64)>(&unsaved_aggregated_key(batch_number))?;
+
+            db.delete(&unsaved_aggregated_key(batch_number))?;
+
+            unsaved
+        };
+
+        match unsaved {
+            Some((hash, proof_number)) => {
+                self.mark_batch_aggregated(batch_number, hash, proof_number)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    //Keeps the latest aggregated batch from the nexus event stream, so it is not
+    //fetched from nexus before every batch.
+    pub async fn follow_nexus(&self) {
+        let mut events = subscribe(String::from(NEXUS_EVENTS_URL));
+
+        while let Some(event) = events.recv().await {
+            //Sending only fails if no submission is waiting.
+            let _ = self.nexus_events.send(event.clone());
+
+            match event {
+                //Sent on every connect as well, so aggregated batches missed while
+                //disconnected are caught up on from their records.
+                NexusEvent::AggregatedBatch(i) => {
+                    let proof_number = i.proof_number;
+
+                    {
+                        let mut latest_aggregated_batch = self.latest_aggregated_batch.lock().await;
+
+                        *latest_aggregated_batch = Some(i);
+                    }
+
+                    if let Err(e) = self.sync_aggregated(proof_number).await {
+                        println!("Could not sync aggregated batches. {:?}", e);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    //Finds the batches of this chain in every aggregated batch up to the given one.
+    //A node without batches has nothing aggregated yet, so it starts from the
+    //given one.
+    async fn sync_aggregated(&self, proof_number: u64) -> Result<(), Error> {
+        let last_synced: u64 = {
+            let db = self.db.lock().await;
+
+            match db.get::<u64>(b"last_synced_proof")? {
+                Some(i) => i,
+                None => proof_number.saturating_sub(1),
+            }
+        };
+
+        for n in last_synced + 1..=proof_number {
+            let response = reqwest::get(format!("{}/{}/record", NEXUS_AGGREGATED_URL, n)).await?;
+
+            //Batches aggregated before nexus kept records are skipped.
+            if response.status().as_u16() != 404 {
+                let record: AggregatedBatchRecord = response.error_for_status()?.json().await?;
+
+                for (chain_id, batch_number, hash) in record.batches {
+                    if chain_id == self.chain_id {
+                        self.mark_batch_aggregated(batch_number, hash, n).await?;
+                    }
+                }
+            }
+
+            let db = self.db.lock().await;
+
+            db.put(b"last_synced_proof", &n)?;
+        }
+
+        Ok(())
+    }
+
+    async fn mark_batch_aggregated(
+        &self,
+        batch_number: u64,
+        hash: H256,
+        proof_number: u64,
+    ) -> Result<(), Error> {
+        let tx_hashes: Vec<H256> = {
+            let db = self.db.lock().await;
+
+            match db.get::<BatchHeader>(&batch_number.to_be_bytes())? {
+                Some(i) if i.hash() == hash => (),
+                Some(_) => {
+                    println!(
+                        "Aggregated batch {} does not match saved batch, skipping.",
+                        batch_number
+                    );
+
+                    return Ok(());
+                }
+                //Kept to be marked once the batch is saved, so the sync moves on if
+                //the batch never is, for example after a resync.
+                None => {
+                    println!(
+                        "Aggregated batch {} not saved yet, marking it once saved.",
+                        batch_number
+                    );
+
+                    db.put(&unsaved_aggregated_key(batch_number), &(hash, proof_number))?;
+
+                    return Ok(());
+                }
+            }
+
+            db.put(&batch_aggregated_key(batch_number), &proof_number)?;
+
+            let mut heads = load_heads(&db)?;
+
+            if batch_number > heads.aggregated {
+                heads.aggregated = batch_number;
+                heads.aggregated_proof_number = proof_number;
+            }
+
+            db.put(b"heads", &heads)?;
+
+            match db.get::<Vec<H256>>(&batch_txs_key(batch_number))? {
+                Some(i) => i,
+                None => vec![],
+            }
+        };
+
+        println!("Batch {} aggregated in {}.", batch_number, proof_number);
+
+        for tx_hash in tx_hashes {
+            self.update_tx_status(&tx_hash, TxStatus::Aggregated { proof_number })
+                .await;
+        }
+
+        Ok(())
+    }
+
+    //Failing to update heads is only logged, as they are derived from saved batches.
+    async fn update_heads<F: FnOnce(&mut ChainHeads)>(&self, update: F) {
+        let db = self.db.lock().await;
+
+        let mut heads = match load_heads(&db) {
+            Ok(i) => i,
+            Err(e) => {
+                println!("Could not get heads. {:?}", e);
+
+                return;
+            }
+        };
+
+        update(&mut heads);
+
+        match db.put(b"heads", &heads) {
+            Ok(()) => (),
+            Err(e) => println!("Could not save heads. {:?}", e),
+        }
+    }
+
+    pub async fn get_heads(&self) -> Result<ChainHeads, Error> {
+        let db = self.db.lock().await;
+
+        Ok(load_heads(&db)?)
+    }
+
+    async fn latest_aggregated_batch(&self) -> Result<AggregatedBatch, Error> {
+        let latest_aggregated_batch = self.latest_aggregated_batch.lock().await.clone();
+
+        match latest_aggregated_batch {
+            Some(i) => Ok(i),
+            //Fetched from nexus until the event stream is connected.
+            None => {
+                let response = reqwest::get(NEXUS_LATEST_BATCH_URL).await?;
+
+                Ok(response.json().await?)
+            }
+        }
+    }
+
     pub async fn execute_batch(&self, call_params: T) -> Result<BatchWithProof<T>, Error> {
         let _now = SystemTime::now();
         let last_batch_number: u64 = {
@@ -224,64 +626,52 @@ impl<
                 Err(e) => return Err(anyhow!("Could not start node. {:?}", e)),
             }
         };
-        //TODO: Add proper error handling below by removing unwrap and store last
-        //batch in memory.
-        let response = reqwest::get(NEXUS_LATEST_BATCH_URL).await?;
-        let aggregated_proof: AggregatedBatch = response.json().await?;
+        let aggregated_proof = self.latest_aggregated_batch().await?;
 
         //TODO: Below should be replaced with a loop to execute a list of transactions.
         let (state_update, receipt) = {
             let mut state_machine = self.state_machine.lock().await;
             state_machine.execute_tx(call_params.clone(), aggregated_proof.clone())?
         };
+        let tx_hash = call_params.to_h256();
+        let batch_number = last_batch_number + 1;
 
-        //Note: Have to do this weird construction as tokio spawn complains that
-        //env is not dropped before an async operation below so is not thread safe.
-        let (batch, proof) = {
-            let mut exec = {
-                let env = ExecutorEnv::builder()
-                    .add_input(&to_vec(&call_params)?)
-                    .add_input(&to_vec(&state_update)?)
-                    .add_input(&to_vec(&(last_batch_number + 1))?)
-                    .add_input(&to_vec(&aggregated_proof)?)
-                    .build()?;
-
-                Executor::from_elf(env, &self.zkvm_elf)?
-            };
-
-            // Run the executor to produce a session.
-            let session = exec.run()?;
-            let segments = session.resolve()?;
+        self.update_heads(|heads| heads.soft = batch_number).await;
 
-            let cycles = segments
-                .iter()
-                .fold(0, |acc, segment| acc + (1 << segment.po2));
+        self.set_tx_record(TxRecord {
+            tx_hash,
+            status: TxStatus::Executed,
+            batch_number: Some(batch_number),
+            receipt: Some(receipt.clone()),
+            decoded: None,
+        })
+        .await;
 
-            println!("Executed, cycles: {}k", cycles / 1024);
-            let session_receipt = match session.prove() {
-                Ok(i) => i,
-                Err(e) => return Err(anyhow!("{:?}", e)),
-            };
+        let inputs = zkvm_inputs(
+            &call_params,
+            &state_update,
+            batch_number,
+            &aggregated_proof,
+            self.chain_id,
+        )?;
+        let zkvm_elf = self.zkvm_elf.clone();
+        let zkvm_id = self.zkvm_id;
 
-            println!("Session executed in zkvm with ID {:?}", &self.zkvm_id);
-            session_receipt.verify(self.zkvm_id)?;
+        //Proving blocks for minutes, so it is kept off the async runtime serving RPCs.
+        let proof =
+            tokio::task::spawn_blocking(move || prove(&zkvm_elf, zkvm_id, inputs)).await??;
 
-            //TODO: Might not need to be deserialized, and need to remove unwrap.
-            let batch_header: BatchHeader = from_slice(&session_receipt.journal)?;
-            let _transaction_with_receipt = TransactionWithReceipt {
-                transaction: call_params.clone(),
-                receipt: receipt.clone(),
-            };
+        println!("Session executed in zkvm with ID {:?}", &self.zkvm_id);
 
-            (
-                DABatch {
-                    header: batch_header,
-                    transactions: vec![call_params.clone()],
-                },
-                session_receipt,
-            )
+        //TODO: Might not need to be deserialized.
+        let batch_header: BatchHeader = from_slice(&proof.journal)?;
+        let batch = DABatch {
+            header: batch_header,
+            transactions: vec![call_params.clone()],
         };
 
+        self.update_tx_status(&tx_hash, TxStatus::Proven).await;
+
         let serialized = bincode::serialize(&batch)?;
 
         println!("Non compressed length: {},", serialized.len());
@@ -295,9 +685,30 @@ impl<
             }
         };
 
+        //Transactions of the batch are kept so they can be marked aggregated when
+        //nexus aggregates the batch.
+        {
+            let db = self.db.lock().await;
+
+            db.put(&batch_txs_key(batch_number), &vec![tx_hash])?;
+        }
+        self.update_tx_status(
+            &tx_hash,
+            TxStatus::SubmittedToDa {
+                block_hash: H256::from(block_hash.to_fixed_bytes()),
+            },
+        )
+        .await;
+
         let transaction_with_receipts = vec![TransactionWithReceipt {
             transaction: call_params.clone(),
             receipt: receipt.clone(),
+            state_keys: state_update
+                .post_state_with_proof
+                .0
+                .iter()
+                .map(|(key, _)| *key)
+                .collect(),
         }];
 
         let serialized_receipt = match bincode::serialize(&proof) {
@@ -308,55 +719,241 @@ impl<
         let data = SubmitProofParam {
             session_receipt: serialized_receipt,
             receipts: vec![receipt.clone()],
-            chain: self.chain.clone(),
+            chain_id: self.chain_id,
             da_tx_pointer: DaTxPointer {
                 block_hash: block_hash.to_fixed_bytes(),
                 hash: hash.to_fixed_bytes(),
-                chain: self.chain.clone(),
+                chain_id: self.chain_id,
+            },
+        };
+
+        let pending = PendingSubmission {
+            batch: BatchWithProof {
+                header: batch.header,
+                transaction_with_receipts,
+                proof,
+                da_tx_pointer: data.da_tx_pointer.clone(),
             },
+            param: data,
+            aggregated_batch: aggregated_proof,
         };
 
+        //Kept until the batch is saved, so it is submitted again if the node
+        //restarts first.
+        {
+            let db = self.db.lock().await;
+
+            db.put(PENDING_SUBMISSION_KEY, &pending)?;
+        }
+
+        self.submit_until_accepted(&pending.param, batch_number)
+            .await?;
+        self.update_tx_status(&tx_hash, TxStatus::Verified).await;
+
+        //Posted only once nexus accepted the batch, so nexus does not find it on DA
+        //first and reject the submission as a duplicate.
+        if self.post_proofs_to_da {
+            let envelope = ProofEnvelope {
+                chain_id: self.chain_id,
+                session_receipt: pending.param.session_receipt.clone(),
+                receipts: pending.param.receipts.clone(),
+            };
+
+            //Batch is already accepted by nexus, so failing to post the proof is not
+            //reverted.
+            match self.post_proof(&envelope).await {
+                Ok(()) => (),
+                Err(e) => println!("Posting proof to DA failed. {:?}", e),
+            }
+        }
+
+        Ok(pending.batch)
+    }
+
+    //The batch is proven and on DA, so it is submitted again as is when nexus or
+    //DA fail. Executing the transaction again would post a different batch with
+    //the same number.
+    async fn submit_until_accepted(
+        &self,
+        data: &SubmitProofParam,
+        batch_number: u64,
+    ) -> Result<(), Error> {
+        loop {
+            match self.submit_to_nexus(data, batch_number).await {
+                Ok(()) => return Ok(()),
+                Err(e) => match e.downcast_ref::<SubmitBatchError>() {
+                    Some(SubmitBatchError::Retry(_)) => {
+                        println!("{} Submitting batch {} again.", e, batch_number);
+
+                        tokio::time::sleep(SUBMIT_RETRY_INTERVAL).await;
+                    }
+                    _ => return Err(e),
+                },
+            }
+        }
+    }
+
+    //Submits a proven batch to nexus, returning once nexus verified it.
+    async fn submit_to_nexus(
+        &self,
+        data: &SubmitProofParam,
+        batch_number: u64,
+    ) -> Result<(), Error> {
+        //Subscribed before submitting, so events about a buffered batch are not missed.
+        let mut nexus_events = self.nexus_events.subscribe();
         let client = reqwest::Client::new();
         let response = match client
             .post(NEXUS_SUBMIT_BATCH_URL)
-            .json(&data) // Serialize the data as JSON
+            .json(data) // Serialize the data as JSON
             .send()
             .await
         {
             Ok(i) => i,
-            Err(e) => return Err(anyhow!("Batch submission failed. {:?}", e.to_string())),
+            Err(e) => return Err(Error::new(SubmitBatchError::Retry(e.to_string()))),
         };
 
-        //TODO: Need to reload previous state if call failed.
         match response.status().as_u16() {
             200 => {
-                // Request was successful, handle the response here
-                let response_text = response.text().await?;
-                println!("Batch submission successful. Response: {}", response_text);
+                //Batch is accepted, so failing to read the reply is not an error.
+                match response.text().await {
+                    Ok(i) => println!("Batch submission successful. Response: {}", i),
+                    Err(e) => println!("Batch submission successful. {:?}", e),
+                }
             }
-            _ => {
-                // Request failed
+            //Batch is buffered until the batch before it is verified, so it is not
+            //accepted yet.
+            202 => {
                 println!(
-                    "Batch submission failed with status code: {}",
-                    response.status()
+                    "Batch {} buffered by nexus, waiting for it to be verified.",
+                    batch_number
                 );
 
-                return Err(anyhow!("Submit batch failed, will try to execute again."));
+                self.wait_for_buffered_batch(&mut nexus_events, batch_number)
+                    .await?;
+            }
+            status => {
+                // Request failed
+                println!("Batch submission failed with status code: {}", status);
+
+                let reply: NexusErrorReply = match response.json().await {
+                    Ok(i) => i,
+                    Err(e) => {
+                        return Err(Error::new(SubmitBatchError::Retry(format!(
+                            "Unexpected nexus response with status {}. {}",
+                            status, e
+                        ))))
+                    }
+                };
+
+                println!("Nexus error {}: {}", &reply.code, &reply.message);
+
+                return Err(Error::new(match reply.retry {
+                    true => SubmitBatchError::Retry(reply.message),
+                    false => SubmitBatchError::Invalid(reply.message),
+                }));
             }
         }
 
-        Ok(BatchWithProof {
-            header: batch.header,
-            transaction_with_receipts,
-            proof,
-        })
+        Ok(())
+    }
+
+    //Waits for nexus to verify a buffered batch. Fails with a retry if nexus drops
+    //it, or if its verification could have been missed, so the batch is submitted
+    //again and nexus replies with its final status.
+    async fn wait_for_buffered_batch(
+        &self,
+        events: &mut broadcast::Receiver<NexusEvent>,
+        batch_number: u64,
+    ) -> Result<(), Error> {
+        let chain_id = self.chain_id;
+        let wait = async {
+            loop {
+                match events.recv().await {
+                    Ok(NexusEvent::BatchVerified {
+                        chain_id: i,
+                        batch_number: n,
+                    }) if i == chain_id && n == batch_number => return Ok(()),
+                    Ok(NexusEvent::BatchDropped {
+                        chain_id: i,
+                        batch_number: n,
+                        reason,
+                    }) if i == chain_id && n == batch_number => {
+                        return Err(SubmitBatchError::Retry(format!(
+                            "Buffered batch dropped by nexus. {}",
+                            reason
+                        )))
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(_)) => {
+                        return Err(SubmitBatchError::Retry(String::from(
+                            "Nexus events about the buffered batch were skipped.",
+                        )))
+                    }
+                    Err(RecvError::Closed) => {
+                        return Err(SubmitBatchError::Retry(String::from(
+                            "Nexus event stream closed.",
+                        )))
+                    }
+                }
+            }
+        };
+
+        match tokio::time::timeout(BUFFERED_BATCH_TIMEOUT, wait).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(Error::new(e)),
+            Err(_e) => Err(Error::new(SubmitBatchError::Retry(String::from(
+                "Buffered batch not verified in time.",
+            )))),
+        }
+    }
+
+    async fn post_proof(&self, envelope: &ProofEnvelope) -> Result<(), Error> {
+        let blob = envelope.to_blob()?;
+
+        println!("Posting proof to DA, compressed length: {}", blob.len());
+
+        match self.da_service.send_transaction(&blob).await {
+            Ok(_i) => Ok(()),
+            Err(e) => Err(anyhow!("DA proof submit tx failed. {:?}", e.to_string())),
+        }
     }
 
     pub async fn save_batch(&self, batch_with_proof: BatchWithProof<T>) -> Result<(), Error> {
         let db = self.db.lock().await;
+        let record = BatchRecord {
+            header: batch_with_proof.header.clone(),
+            tx_hashes: batch_with_proof
+                .transaction_with_receipts
+                .iter()
+                .map(|i| i.transaction.to_h256())
+                .collect(),
+            receipts: batch_with_proof
+                .transaction_with_receipts
+                .iter()
+                .map(|i| i.receipt.clone())
+                .collect(),
+            da_tx_pointer: batch_with_proof.da_tx_pointer,
+            proof: batch_with_proof.proof,
+            included_in: None,
+            decoded: vec![],
+        };
 
         for tx in batch_with_proof.transaction_with_receipts {
-            db.put(tx.transaction.to_h256().as_slice(), &tx)?;
+            let tx_hash = tx.transaction.to_h256();
+
+            for key in &tx.state_keys {
+                db.append_history(
+                    key,
+                    HistoryEntry {
+                        batch_number: batch_with_proof.header.batch_number,
+                        tx_hash,
+                        receipt: tx.receipt.clone(),
+                        decoded: None,
+                    },
+                )?;
+            }
+
+            db.put(tx_hash.as_slice(), &tx)?;
         }
 
         db.put(b"last_batch_header", &batch_with_proof.header)?;
@@ -365,57 +962,363 @@ impl<
             &batch_with_proof.header.batch_number.to_be_bytes(),
             &batch_with_proof.header,
         )?;
+        db.put(&batch_record_key(record.header.batch_number), &record)?;
+
+        let mut heads = load_heads(&db)?;
+
+        heads.proven = batch_with_proof.header.batch_number;
+        db.put(b"heads", &heads)?;
+        db.delete(PENDING_SUBMISSION_KEY)?;
 
         Ok(())
     }
 
+    pub async fn get_batch(&self, batch_number: u64) -> Result<Option<BatchRecord>, Error> {
+        let db = self.db.lock().await;
+
+        get_batch_record(&db, &self.receipt_registry, batch_number)
+    }
+
+    pub async fn get_latest_batch(&self) -> Result<Option<BatchRecord>, Error> {
+        let db = self.db.lock().await;
+
+        match db.get::<BatchHeader>(b"last_batch_header")? {
+            Some(i) => get_batch_record(&db, &self.receipt_registry, i.batch_number),
+            None => Ok(None),
+        }
+    }
+
+    //Batches in the inclusive range, skipping batches not saved.
+    pub async fn get_batches(&self, from: u64, to: u64) -> Result<Vec<BatchRecord>, Error> {
+        let db = self.db.lock().await;
+        let mut batches: Vec<BatchRecord> = vec![];
+
+        for batch_number in from..=to {
+            if let Some(i) = get_batch_record(&db, &self.receipt_registry, batch_number)? {
+                batches.push(i);
+            }
+        }
+
+        Ok(batches)
+    }
+
     pub async fn add_to_tx_pool(&self, tx: T) {
         println!("Adding tx hash to pool: {:?}", tx.to_h256());
         let mut tx_pool = self.tx_pool.lock().await;
 
+        //Recorded while holding the pool, so the status is never read as pending
+        //for a transaction not in the pool yet.
+        self.set_tx_record(TxRecord {
+            tx_hash: tx.to_h256(),
+            status: TxStatus::Pending,
+            batch_number: None,
+            receipt: None,
+            decoded: None,
+        })
+        .await;
+
         tx_pool.push(tx)
     }
 
-    pub async fn get_tx_status(&self, hash: H256) -> Result<String, Error> {
-        let mut tx_pool = self.tx_pool.lock().await;
+    pub async fn get_tx_status(&self, hash: H256) -> Result<Option<TxRecord>, Error> {
+        let in_pool = {
+            let tx_pool = self.tx_pool.lock().await;
 
-        for tx in tx_pool.iter() {
-            // Check if the hash of the current item matches the target hash
-            if tx.to_h256() == hash {
-                return Ok(String::from("tx_pool"));
-            }
+            tx_pool.iter().any(|tx| tx.to_h256() == hash)
+        };
+        let db = self.db.lock().await;
+
+        let mut record = match db.get::<TxRecord>(&tx_status_key(&hash))? {
+            Some(i) => i,
+            //Transactions saved before statuses were recorded.
+            None => match db.get::<TransactionWithReceipt<T>>(hash.as_slice())? {
+                Some(i) => TxRecord {
+                    tx_hash: hash,
+                    status: TxStatus::Verified,
+                    batch_number: None,
+                    receipt: Some(i.receipt),
+                    decoded: None,
+                },
+                None => return Ok(None),
+            },
+        };
+
+        if !in_pool && record.status.in_progress() {
+            record.status = TxStatus::Dropped;
         }
 
+        record.decoded = match &record.receipt {
+            Some(i) => self.receipt_registry.decode(i).ok(),
+            None => None,
+        };
+
+        Ok(Some(record))
+    }
+
+    //Failing to record a status is only logged, as the transaction itself is
+    //not affected.
+    async fn set_tx_record(&self, record: TxRecord) {
         let db = self.db.lock().await;
 
-        match db.get::<T>(hash.as_slice()) {
-            Ok(Some(_i)) => return Ok(String::from("finalized.")),
-            Ok(None) => return Ok(String::from("dropped")),
-            Err(e) => return Err(anyhow!("{:?}", e)),
+        match db.put(&tx_status_key(&record.tx_hash), &record) {
+            Ok(()) => (),
+            Err(e) => println!("Could not save transaction status. {:?}", e),
         }
     }
 
+    async fn update_tx_status(&self, tx_hash: &H256, status: TxStatus) {
+        let record = {
+            let db = self.db.lock().await;
+
+            match db.get::<TxRecord>(&tx_status_key(tx_hash)) {
+                Ok(Some(i)) => TxRecord { status, ..i },
+                Ok(None) => TxRecord {
+                    tx_hash: *tx_hash,
+                    status,
+                    batch_number: None,
+                    receipt: None,
+                    decoded: None,
+                },
+                Err(e) => {
+                    println!("Could not get transaction status. {:?}", e);
+
+                    return;
+                }
+            }
+        };
+
+        self.set_tx_record(record).await;
+    }
+
     pub fn get_tx_pool(&self) -> &Arc<Mutex<Vec<T>>> {
         return &self.tx_pool;
     }
 
+    //Reads below are from the last committed state, so they do not wait for the
+    //batch being executed.
     pub async fn get_state_with_proof(&self, key: &H256) -> Result<(V, MerkleProof), Error> {
-        let state_machine = self.state_machine.lock().await;
+        let snapshot = self.snapshot.read().await;
 
-        state_machine.get_state_with_proof(key)
+        Ok(snapshot.get_state_with_proof(key)?)
     }
 
     pub async fn get_state(&self, key: &H256) -> Result<Option<V>, Error> {
-        let state_machine = self.state_machine.lock().await;
+        let snapshot = self.snapshot.read().await;
 
-        state_machine.get_state(key)
+        Ok(snapshot.get_state(key)?)
     }
 
     pub async fn get_root(&self) -> Result<H256, Error> {
-        let state_machine = self.state_machine.lock().await;
+        let snapshot = self.snapshot.read().await;
+
+        Ok(snapshot.get_root()?)
+    }
+
+    //Executes the call against the committed state, leaving the pool and the
+    //state machine untouched. Rejections by the state transition are returned
+    //in the result rather than as an error.
+    pub async fn simulate(&self, call: T, with_cycles: bool) -> Result<SimulationResult<V>, Error> {
+        let aggregated_proof = self.latest_aggregated_batch().await?;
+        let simulated = {
+            let state_machine = self.state_machine.lock().await;
+
+            state_machine.simulate_tx(call.clone(), aggregated_proof.clone())
+        };
+
+        let (state_update, receipt) = match simulated {
+            Ok(i) => i,
+            Err(StateMachineError::Transition(e)) => {
+                return Ok(SimulationResult {
+                    receipt: None,
+                    state_diff: vec![],
+                    error: Some(ErrorReply::from_error(&Error::new(e))),
+                    cycles: None,
+                })
+            }
+            Err(e) => return Err(Error::new(e)),
+        };
+
+        let cycles = if with_cycles {
+            let batch_number = {
+                let db = self.db.lock().await;
+
+                match db.get::<BatchHeader>(b"last_batch_header")? {
+                    Some(i) => i.batch_number + 1,
+                    None => 1,
+                }
+            };
+
+            let inputs = zkvm_inputs(
+                &call,
+                &state_update,
+                batch_number,
+                &aggregated_proof,
+                self.chain_id,
+            )?;
+            let zkvm_elf = self.zkvm_elf.clone();
+
+            Some(tokio::task::spawn_blocking(move || count_cycles(&zkvm_elf, inputs)).await??)
+        } else {
+            None
+        };
+
+        let state_diff = state_update
+            .pre_state_with_proof
+            .0
+            .iter()
+            .zip(state_update.post_state_with_proof.0.iter())
+            .map(|((key, before), (_, after))| StateChange {
+                key: *key,
+                before: before.clone(),
+                after: after.clone(),
+            })
+            .collect();
+
+        Ok(SimulationResult {
+            receipt: Some(receipt),
+            state_diff,
+            error: None,
+            cycles,
+        })
+    }
+
+    pub async fn get_history(&self, key: &H256) -> Result<Vec<HistoryEntry>, Error> {
+        let db = self.db.lock().await;
+
+        let history = db
+            .get_history(key)?
+            .into_iter()
+            .map(|mut entry| {
+                entry.decoded = self.receipt_registry.decode(&entry.receipt).ok();
+
+                entry
+            })
+            .collect();
+
+        Ok(history)
+    }
+}
+
+//Inputs of the zkvm guest for a batch, in the order the guest reads them.
+fn zkvm_inputs<V: Serialize, T: Serialize>(
+    call: &T,
+    state_update: &StateUpdate<V>,
+    batch_number: u64,
+    aggregated_proof: &AggregatedBatch,
+    chain_id: u64,
+) -> Result<Vec<Vec<u32>>, Error> {
+    Ok(vec![
+        to_vec(call)?,
+        to_vec(state_update)?,
+        to_vec(&batch_number)?,
+        to_vec(aggregated_proof)?,
+        to_vec(&chain_id)?,
+    ])
+}
+
+//Runs the guest without proving, returning the session and its cycle count.
+fn run_zkvm(zkvm_elf: &[u8], inputs: Vec<Vec<u32>>) -> Result<(Session, u64), Error> {
+    let mut builder = ExecutorEnv::builder();
 
-        state_machine.get_root()
+    for input in &inputs {
+        builder.add_input(input);
     }
+
+    let mut exec = Executor::from_elf(builder.build()?, zkvm_elf)?;
+    let session = exec.run()?;
+    let segments = session.resolve()?;
+
+    let cycles = segments
+        .iter()
+        .fold(0, |acc, segment| acc + (1 << segment.po2));
+
+    Ok((session, cycles))
+}
+
+fn count_cycles(zkvm_elf: &[u8], inputs: Vec<Vec<u32>>) -> Result<u64, Error> {
+    let (_, cycles) = run_zkvm(zkvm_elf, inputs)?;
+
+    Ok(cycles)
+}
+
+//Blocks until the batch is proven, so needs to be run on a blocking thread.
+fn prove(zkvm_elf: &[u8], zkvm_id: Digest, inputs: Vec<Vec<u32>>) -> Result<Receipt, Error> {
+    let (session, cycles) = run_zkvm(zkvm_elf, inputs)?;
+
+    println!("Executed, cycles: {}k", cycles / 1024);
+    let session_receipt = match session.prove() {
+        Ok(i) => i,
+        Err(e) => return Err(anyhow!("{:?}", e)),
+    };
+
+    session_receipt.verify(zkvm_id)?;
+
+    Ok(session_receipt)
+}
+
+//Nodes started before heads were tracked start from their last saved batch.
+fn load_heads(db: &NodeDB) -> Result<ChainHeads, StateError> {
+    match db.get::<ChainHeads>(b"heads")? {
+        Some(i) => Ok(i),
+        None => {
+            let proven = match db.get::<BatchHeader>(b"last_batch_header")? {
+                Some(i) => i.batch_number,
+                None => 0,
+            };
+
+            Ok(ChainHeads {
+                soft: proven,
+                proven,
+                ..ChainHeads::default()
+            })
+        }
+    }
+}
+
+//Aggregation is recorded separately, so the batch record is written once.
+fn get_batch_record(
+    db: &NodeDB,
+    registry: &ReceiptRegistry,
+    batch_number: u64,
+) -> Result<Option<BatchRecord>, Error> {
+    let mut record = match db.get::<BatchRecord>(&batch_record_key(batch_number))? {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+
+    record.included_in = db.get::<u64>(&batch_aggregated_key(batch_number))?;
+
+    record.decoded = record
+        .receipts
+        .iter()
+        .map(|i| registry.decode(i).ok())
+        .collect();
+
+    Ok(Some(record))
+}
+
+fn batch_record_key(batch_number: u64) -> Vec<u8> {
+    [b"batch-".as_slice(), &batch_number.to_be_bytes()].concat()
+}
+
+fn unsaved_aggregated_key(batch_number: u64) -> Vec<u8> {
+    [
+        b"unsaved-aggregated-".as_slice(),
+        &batch_number.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn batch_aggregated_key(batch_number: u64) -> Vec<u8> {
+    [b"batch-aggregated-".as_slice(), &batch_number.to_be_bytes()].concat()
+}
+
+fn tx_status_key(tx_hash: &H256) -> Vec<u8> {
+    [b"tx-status-".as_slice(), tx_hash.as_slice()].concat()
+}
+
+fn batch_txs_key(batch_number: u64) -> Vec<u8> {
+    [b"batch-txs-".as_slice(), &batch_number.to_be_bytes()].concat()
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
@@ -423,6 +1326,22 @@ pub struct StateQuery {
     key: String,
 }
 
+//Inclusive range of batch numbers.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
+pub struct BatchRangeQuery {
+    from: u64,
+    to: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
+pub struct SimulateQuery {
+    #[serde(default)]
+    cycles: bool,
+}
+
+//Batches carry their proofs, so ranges are limited.
+const MAX_BATCH_RANGE: u64 = 100;
+
 pub async fn get_state_with_proof<V, T, S>(
     service: Arc<Mutex<AppNode<V, T, S>>>,
     query: String,
@@ -460,6 +1379,40 @@ where
     Ok(ClientReply::Ok(state_with_proof))
 }
 
+pub async fn get_history<V, T, S>(
+    service: Arc<Mutex<AppNode<V, T, S>>>,
+    query: String,
+) -> Result<ClientReply<Vec<HistoryEntry>>, Infallible>
+where
+    V: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + Clone
+        + std::marker::Sync
+        + Encode
+        + Decode,
+    T: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + 'static
+        + Clone
+        + TxHasher
+        + Encode
+        + Decode,
+    S: StateMachine<V, T> + std::marker::Send,
+{
+    let app = service.lock().await;
+    let key: H256 = H256::from(match hex_string_to_u8_array(&query) {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    });
+
+    match app.get_history(&key).await {
+        Ok(i) => Ok(ClientReply::Ok(i)),
+        Err(e) => Ok(ClientReply::Error(e)),
+    }
+}
+
 pub async fn api_handler<V, T, S>(
     service: Arc<Mutex<AppNode<V, T, S>>>,
     call: T,
@@ -490,10 +1443,42 @@ where
     Ok(ClientReply::Ok(String::from("Transaction added to batch.")))
 }
 
+pub async fn simulate<V, T, S>(
+    service: Arc<Mutex<AppNode<V, T, S>>>,
+    query: SimulateQuery,
+    call: T,
+) -> Result<ClientReply<SimulationResult<V>>, Infallible>
+where
+    V: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + Clone
+        + std::marker::Sync
+        + Encode
+        + Decode,
+    T: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + 'static
+        + Clone
+        + TxHasher
+        + Encode
+        + Decode,
+    S: StateMachine<V, T> + std::marker::Send,
+{
+    //Not held while simulating, as counting cycles runs the zkvm executor.
+    let app = service.lock().await.clone();
+
+    match app.simulate(call, query.cycles).await {
+        Ok(i) => Ok(ClientReply::Ok(i)),
+        Err(e) => Ok(ClientReply::Error(e)),
+    }
+}
+
 pub async fn get_tx_status<V, T, S>(
     service: Arc<Mutex<AppNode<V, T, S>>>,
     call: H256,
-) -> Result<ClientReply<String>, Infallible>
+) -> Result<ClientReply<TxRecord>, Infallible>
 where
     V: Serialize
         + DeserializeOwned
@@ -515,6 +1500,131 @@ where
     let app = service.lock().await;
 
     match app.get_tx_status(call).await {
+        Ok(Some(i)) => Ok(ClientReply::Ok(i)),
+        Ok(None) => Ok(ClientReply::NotFound),
+        Err(e) => Ok(ClientReply::Error(e)),
+    }
+}
+
+pub async fn get_heads<V, T, S>(
+    service: Arc<Mutex<AppNode<V, T, S>>>,
+) -> Result<ClientReply<ChainHeads>, Infallible>
+where
+    V: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + Clone
+        + std::marker::Sync
+        + Encode
+        + Decode,
+    T: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + 'static
+        + Clone
+        + TxHasher
+        + Encode
+        + Decode,
+    S: StateMachine<V, T> + std::marker::Send,
+{
+    let app = service.lock().await;
+
+    match app.get_heads().await {
+        Ok(i) => Ok(ClientReply::Ok(i)),
+        Err(e) => Ok(ClientReply::Error(e)),
+    }
+}
+
+pub async fn get_batch<V, T, S>(
+    service: Arc<Mutex<AppNode<V, T, S>>>,
+    batch_number: u64,
+) -> Result<ClientReply<BatchRecord>, Infallible>
+where
+    V: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + Clone
+        + std::marker::Sync
+        + Encode
+        + Decode,
+    T: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + 'static
+        + Clone
+        + TxHasher
+        + Encode
+        + Decode,
+    S: StateMachine<V, T> + std::marker::Send,
+{
+    let app = service.lock().await;
+
+    match app.get_batch(batch_number).await {
+        Ok(Some(i)) => Ok(ClientReply::Ok(i)),
+        Ok(None) => Ok(ClientReply::NotFound),
+        Err(e) => Ok(ClientReply::Error(e)),
+    }
+}
+
+pub async fn get_latest_batch<V, T, S>(
+    service: Arc<Mutex<AppNode<V, T, S>>>,
+) -> Result<ClientReply<BatchRecord>, Infallible>
+where
+    V: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + Clone
+        + std::marker::Sync
+        + Encode
+        + Decode,
+    T: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + 'static
+        + Clone
+        + TxHasher
+        + Encode
+        + Decode,
+    S: StateMachine<V, T> + std::marker::Send,
+{
+    let app = service.lock().await;
+
+    match app.get_latest_batch().await {
+        Ok(Some(i)) => Ok(ClientReply::Ok(i)),
+        Ok(None) => Ok(ClientReply::NotFound),
+        Err(e) => Ok(ClientReply::Error(e)),
+    }
+}
+
+pub async fn get_batches<V, T, S>(
+    service: Arc<Mutex<AppNode<V, T, S>>>,
+    query: BatchRangeQuery,
+) -> Result<ClientReply<Vec<BatchRecord>>, Infallible>
+where
+    V: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + Clone
+        + std::marker::Sync
+        + Encode
+        + Decode,
+    T: Serialize
+        + DeserializeOwned
+        + std::marker::Send
+        + 'static
+        + Clone
+        + TxHasher
+        + Encode
+        + Decode,
+    S: StateMachine<V, T> + std::marker::Send,
+{
+    if query.to < query.from || query.to - query.from >= MAX_BATCH_RANGE {
+        return Ok(ClientReply::BadRequest);
+    }
+
+    let app = service.lock().await;
+
+    match app.get_batches(query.from, query.to).await {
         Ok(i) => Ok(ClientReply::Ok(i)),
         Err(e) => Ok(ClientReply::Error(e)),
     }
@@ -544,6 +1654,12 @@ where
     let send_tx_app = service.clone();
     let tx_status_app = service.clone();
     let state_app = service.clone();
+    let history_app = service.clone();
+    let heads_app = service.clone();
+    let batch_app = service.clone();
+    let latest_batch_app = service.clone();
+    let batches_app = service.clone();
+    let simulate_app = service.clone();
 
     let send_tx = warp::path!("tx")
         .and(warp::any().map(move || send_tx_app.clone()))
@@ -560,7 +1676,46 @@ where
         .and(warp::path::param::<String>())
         .and_then(get_state_with_proof::<V, T, S>);
 
-    send_tx.or(tx_status).or(state_with_proof)
+    let history = warp::path("history")
+        .and(warp::any().map(move || history_app.clone()))
+        .and(warp::path::param::<String>())
+        .and_then(get_history::<V, T, S>);
+
+    let heads = warp::path!("heads")
+        .and(warp::any().map(move || heads_app.clone()))
+        .and_then(get_heads::<V, T, S>);
+
+    let latest_batch = warp::path!("batch" / "latest")
+        .and(warp::any().map(move || latest_batch_app.clone()))
+        .and_then(get_latest_batch::<V, T, S>);
+
+    let batch = warp::path("batch")
+        .and(warp::any().map(move || batch_app.clone()))
+        .and(warp::path::param::<u64>())
+        .and(warp::path::end())
+        .and_then(get_batch::<V, T, S>);
+
+    let batches = warp::path!("batches")
+        .and(warp::any().map(move || batches_app.clone()))
+        .and(warp::query::<BatchRangeQuery>())
+        .and_then(get_batches::<V, T, S>);
+
+    let simulate = warp::path!("simulate")
+        .and(warp::post())
+        .and(warp::any().map(move || simulate_app.clone()))
+        .and(warp::query::<SimulateQuery>())
+        .and(warp::body::json())
+        .and_then(simulate::<V, T, S>);
+
+    send_tx
+        .or(tx_status)
+        .or(state_with_proof)
+        .or(history)
+        .or(heads)
+        .or(latest_batch)
+        .or(batch)
+        .or(batches)
+        .or(simulate)
 }
 
 pub struct RPCServer<V, T, S>
@@ -1,4 +1,5 @@
-use anyhow::{anyhow, Error};
+use crate::errors::StateError;
+use crate::types::HistoryEntry;
 use rocksdb::{Options, DB};
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{from_slice, to_vec};
@@ -27,9 +28,9 @@ impl NodeDB {
         &self.db
     }
 
-    pub fn get<V: DeserializeOwned>(&self, serialized_key: &[u8]) -> Result<Option<V>, Error> {
+    pub fn get<V: DeserializeOwned>(&self, serialized_key: &[u8]) -> Result<Option<V>, StateError> {
         match self.db.get(serialized_key) {
-            Err(e) => Err(anyhow!("{}", e.to_string())),
+            Err(e) => Err(StateError::from(e)),
             Ok(None) => Ok(None),
             Ok(Some(i)) => {
                 println!("got valluueeee");
@@ -39,29 +40,49 @@ impl NodeDB {
         }
     }
 
-    pub fn put<V: Serialize>(&self, serialized_key: &[u8], value: &V) -> Result<(), Error> {
+    pub fn put<V: Serialize>(&self, serialized_key: &[u8], value: &V) -> Result<(), StateError> {
         match self.db.put(serialized_key, to_vec(&value)?) {
-            Err(e) => Err(anyhow!("{}", e.to_string())),
+            Err(e) => Err(StateError::from(e)),
             _ => Ok(()),
         }
     }
 
-    pub fn delete(&self, serialized_key: &[u8]) -> Result<(), Error> {
+    pub fn delete(&self, serialized_key: &[u8]) -> Result<(), StateError> {
         match self.db.get(serialized_key) {
-            Err(e) => Err(anyhow!("{}", e.to_string())),
+            Err(e) => Err(StateError::from(e)),
             Ok(Some(_)) => match self.db.delete(serialized_key) {
-                Err(e) => Err(anyhow!("{}", e.to_string())),
+                Err(e) => Err(StateError::from(e)),
                 _ => Ok(()),
             },
             Ok(None) => Ok(()),
         }
     }
 
-    pub fn get_current_root(&self) -> Result<Option<H256>, Error> {
+    pub fn get_current_root(&self) -> Result<Option<H256>, StateError> {
         self.get(b"current-root")
     }
 
-    pub fn set_current_root(&self, root: &H256) -> Result<(), Error> {
+    pub fn set_current_root(&self, root: &H256) -> Result<(), StateError> {
         self.put(b"current-root", root)
     }
+
+    pub fn get_history(&self, key: &H256) -> Result<Vec<HistoryEntry>, StateError> {
+        match self.get::<Vec<HistoryEntry>>(&history_key(key)) {
+            Ok(Some(i)) => Ok(i),
+            Ok(None) => Ok(vec![]),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn append_history(&self, key: &H256, entry: HistoryEntry) -> Result<(), StateError> {
+        let mut history = self.get_history(key)?;
+
+        history.push(entry);
+
+        self.put(&history_key(key), &history)
+    }
+}
+
+fn history_key(key: &H256) -> Vec<u8> {
+    [b"history-".as_slice(), key.as_slice()].concat()
 }
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+//Reasons a transaction is rejected by a state transition function. These are
+//caused by the transaction itself, so executing it again gives the same result.
+#[derive(Clone, Debug, Deserialize, Eq, Error, PartialEq, Serialize)]
+pub enum StateTransitionError {
+    #[error("Invalid transaction. {0}")]
+    InvalidTransaction(String),
+    #[error("Signature verification failed.")]
+    InvalidSignature,
+    #[error("NFT not minted.")]
+    NotMinted,
+    #[error("NFT already minted.")]
+    AlreadyMinted,
+    #[error("Not owner.")]
+    NotOwner,
+    #[error("NFT under auction.")]
+    UnderAuction,
+    #[error("NFT is locked.")]
+    Locked,
+    #[error("No future registered.")]
+    NoFuture,
+    #[error("Future deadline has already passed.")]
+    FutureDeadlinePassed,
+    //Reverting earlier could race a receipt made before the deadline, which is
+    //not aggregated yet.
+    #[error("Future can not be reverted before its deadline.")]
+    FutureNotExpired,
+    #[error("Invalid merkle proof.")]
+    InvalidMerkleProof,
+    #[error("Receipt not included.")]
+    ReceiptNotIncluded,
+    #[error("Receipt already consumed.")]
+    ReceiptConsumed,
+    #[error("Nullifier leaf is not at the nullifier key of the receipt.")]
+    NullifierKeyMismatch,
+    #[error("Key is reserved for nullifiers.")]
+    ReservedKey,
+    //Pre state has to be read at the keys the transaction touches.
+    #[error("Pre state does not match the transaction keys.")]
+    StateKeyMismatch,
+    #[error("Invalid receipt. {0}")]
+    InvalidReceipt(String),
+    #[error("NFT not under auction.")]
+    NotUnderAuction,
+    #[error("Auction end has already passed.")]
+    AuctionEndPassed,
+    #[error("Auction has ended.")]
+    AuctionEnded,
+    #[error("Auction has not ended.")]
+    AuctionNotEnded,
+    #[error("Auction already settled.")]
+    AuctionSettled,
+    #[error("Bid too low.")]
+    BidTooLow,
+    #[error("Escrow expires before auction ends.")]
+    EscrowExpiresBeforeAuctionEnd,
+    //Names the part of the transaction that does not match, for example "Escrow".
+    #[error("{0} does not match offer.")]
+    OfferMismatch(String),
+    #[error("Offer not expired.")]
+    OfferNotExpired,
+    #[error("Offer expired.")]
+    OfferExpired,
+    #[error("Escrow already exists.")]
+    EscrowExists,
+    #[error("Insufficient balance.")]
+    InsufficientBalance,
+    #[error("Cannot transfer to self.")]
+    SelfTransfer,
+    #[error("Swap deadline has passed.")]
+    SwapDeadlinePassed,
+    #[error("NFT is not part of the swap.")]
+    NotInSwap,
+    #[error("Swap does not match NFT state.")]
+    SwapMismatch,
+}
+
+impl StateTransitionError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            StateTransitionError::InvalidTransaction(_) => "invalid_transaction",
+            StateTransitionError::InvalidSignature => "invalid_signature",
+            StateTransitionError::NotMinted => "not_minted",
+            StateTransitionError::AlreadyMinted => "already_minted",
+            StateTransitionError::NotOwner => "not_owner",
+            StateTransitionError::UnderAuction => "under_auction",
+            StateTransitionError::Locked => "locked",
+            StateTransitionError::NoFuture => "no_future",
+            StateTransitionError::FutureDeadlinePassed => "future_deadline_passed",
+            StateTransitionError::FutureNotExpired => "future_not_expired",
+            StateTransitionError::InvalidMerkleProof => "invalid_merkle_proof",
+            StateTransitionError::ReceiptNotIncluded => "receipt_not_included",
+            StateTransitionError::ReceiptConsumed => "receipt_consumed",
+            StateTransitionError::NullifierKeyMismatch => "nullifier_key_mismatch",
+            StateTransitionError::ReservedKey => "reserved_key",
+            StateTransitionError::StateKeyMismatch => "state_key_mismatch",
+            StateTransitionError::InvalidReceipt(_) => "invalid_receipt",
+            StateTransitionError::NotUnderAuction => "not_under_auction",
+            StateTransitionError::AuctionEndPassed => "auction_end_passed",
+            StateTransitionError::AuctionEnded => "auction_ended",
+            StateTransitionError::AuctionNotEnded => "auction_not_ended",
+            StateTransitionError::AuctionSettled => "auction_settled",
+            StateTransitionError::BidTooLow => "bid_too_low",
+            StateTransitionError::EscrowExpiresBeforeAuctionEnd => {
+                "escrow_expires_before_auction_end"
+            }
+            StateTransitionError::OfferMismatch(_) => "offer_mismatch",
+            StateTransitionError::OfferNotExpired => "offer_not_expired",
+            StateTransitionError::OfferExpired => "offer_expired",
+            StateTransitionError::EscrowExists => "escrow_exists",
+            StateTransitionError::InsufficientBalance => "insufficient_balance",
+            StateTransitionError::SelfTransfer => "self_transfer",
+            StateTransitionError::SwapDeadlinePassed => "swap_deadline_passed",
+            StateTransitionError::NotInSwap => "not_in_swap",
+            StateTransitionError::SwapMismatch => "swap_mismatch",
+        }
+    }
+}
+
+//Failures of the storage behind the state, NodeDB and the merkle store.
+#[derive(Clone, Debug, Deserialize, Eq, Error, PartialEq, Serialize)]
+pub enum StateError {
+    #[error("Store error. {0}")]
+    Store(String),
+    #[error("Serialization failed. {0}")]
+    Serialization(String),
+    //State in the store is not a valid tree, node needs to be restarted.
+    #[error("Corrupt state. {0}")]
+    CorruptState(String),
+}
+
+impl StateError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            StateError::Store(_) => "store",
+            StateError::Serialization(_) => "serialization",
+            StateError::CorruptState(_) => "corrupt_state",
+        }
+    }
+}
+
+#[cfg(any(feature = "native", feature = "native-metal"))]
+impl From<rocksdb::Error> for StateError {
+    fn from(e: rocksdb::Error) -> Self {
+        StateError::Store(e.to_string())
+    }
+}
+
+#[cfg(any(feature = "native", feature = "native-metal"))]
+impl From<sparse_merkle_tree::error::Error> for StateError {
+    fn from(e: sparse_merkle_tree::error::Error) -> Self {
+        StateError::Store(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StateError {
+    fn from(e: serde_json::Error) -> Self {
+        StateError::Serialization(e.to_string())
+    }
+}
+
+//Errors returned by a StateMachine.
+#[derive(Clone, Debug, Deserialize, Eq, Error, PartialEq, Serialize)]
+pub enum StateMachineError {
+    #[error(transparent)]
+    Transition(#[from] StateTransitionError),
+    #[error(transparent)]
+    State(#[from] StateError),
+}
+
+impl StateMachineError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            StateMachineError::Transition(e) => e.code(),
+            StateMachineError::State(e) => e.code(),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::types::AggregatedBatch;
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use sparse_merkle_tree::H256;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+//Events streamed by nexus at `/events`, as server sent events with JSON data.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum NexusEvent {
+    AggregatedBatch(AggregatedBatch),
+    BatchVerified { chain_id: u64, batch_number: u64 },
+    //Batch with a valid proof, received ahead of the batch before it. Followed by
+    //BatchVerified once the gap is filled, or BatchDropped.
+    BatchBuffered { chain_id: u64, batch_number: u64 },
+    //Buffered batch dropped without being verified, it has to be submitted again.
+    BatchDropped {
+        chain_id: u64,
+        batch_number: u64,
+        reason: String,
+    },
+    BatchAggregated {
+        chain_id: u64,
+        batch_number: u64,
+        proof_number: u64,
+    },
+    //Receipt indexed by its hash for the first time, in the given aggregated batch.
+    ReceiptIncluded { key: H256, proof_number: u64 },
+}
+
+impl NexusEvent {
+    pub fn to_sse(&self) -> Result<String, Error> {
+        Ok(format!("data: {}\n\n", serde_json::to_string(self)?))
+    }
+}
+
+//Subscribes to the nexus event stream at the given URL, reconnecting if it drops.
+//Nexus sends the latest aggregated batch on every connect, so events missed
+//while disconnected are caught up on for aggregated batches.
+pub fn subscribe(url: String) -> mpsc::Receiver<NexusEvent> {
+    let (sender, receiver) = mpsc::channel(100);
+
+    tokio::spawn(async move {
+        loop {
+            match stream_events(&url, &sender).await {
+                //Subscriber dropped the receiver.
+                Ok(()) => return,
+                Err(e) => println!("Nexus event stream dropped, reconnecting. {:?}", e),
+            }
+
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    });
+
+    receiver
+}
+
+async fn stream_events(url: &str, sender: &mpsc::Sender<NexusEvent>) -> Result<(), Error> {
+    let mut response = reqwest::get(url).await?.error_for_status()?;
+    let mut buffer: Vec<u8> = vec![];
+
+    while let Some(chunk) = response.chunk().await? {
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(end) = buffer.windows(2).position(|i| i == b"\n\n") {
+            let message: Vec<u8> = buffer.drain(..end + 2).collect();
+
+            for line in String::from_utf8_lossy(&message).lines() {
+                let data = match line.strip_prefix("data: ") {
+                    Some(i) => i,
+                    None => continue,
+                };
+
+                match serde_json::from_str::<NexusEvent>(data) {
+                    Ok(event) => {
+                        if sender.send(event).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => println!("Skipping unknown nexus event. {:?}", e),
+                }
+            }
+        }
+    }
+
+    Err(anyhow!("Nexus event stream closed."))
+}
@@ -0,0 +1,275 @@
+use crate::{
+    errors::StateTransitionError,
+    receipts::{ReceiptData, ReceiptKind},
+    traits::Leaf,
+    types::{AggregatedBatch, ShaHasher, TransactionReceipt},
+};
+use anyhow::{anyhow, Error};
+use parity_scale_codec::{Decode, Encode};
+use risc0_zkvm::sha::rust_crypto::Digest;
+use serde::{Deserialize, Serialize};
+use sparse_merkle_tree::{
+    traits::{Hasher, Value},
+    MerkleProof, H256,
+};
+
+//Cross chain messages are sent as receipts. The sending chain emits a receipt
+//with an encoded `Message`, nexus aggregates it into the receipts tree, and the
+//receiving chain consumes it with an inclusion proof against the aggregated
+//receipts root. Consumed receipts are recorded as nullifier leaves in the
+//receiving chain's own state tree, so the same receipt cannot be consumed twice.
+
+//State leaves which can mark a receipt as consumed. A nullifier leaf lives at
+//the nullifier key of the receipt, and must hash to a non zero value.
+pub trait Nullifier: Leaf<H256> + Value + Clone {
+    fn nullifier(key: H256) -> Self;
+
+    fn is_consumed(&self) -> bool {
+        self.to_h256() != H256::zero()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct Message<M> {
+    pub to_chain: u64,
+    pub payload: M,
+}
+
+impl<M: Encode + Decode> ReceiptData for Message<M> {
+    const KIND: ReceiptKind = ReceiptKind::Message;
+}
+
+impl<M: Encode + Decode> Message<M> {
+    pub fn to_receipt(&self, from_chain: u64) -> TransactionReceipt {
+        TransactionReceipt {
+            chain_id: from_chain,
+            data: self.to_encoded(),
+        }
+    }
+
+    pub fn from_receipt(receipt: &TransactionReceipt) -> Result<Self, Error> {
+        match Self::from_encoded(&receipt.data) {
+            Ok(i) => Ok(i),
+            Err(e) => Err(anyhow!("Receipt is not a message: {:?}", e)),
+        }
+    }
+}
+
+//Nullifier keys start with a reserved prefix, and chains reject transactions
+//touching other keys with it, so no other leaf can be written at a nullifier key.
+pub const NULLIFIER_KEY_PREFIX: &[u8] = b"nullifier";
+
+pub fn nullifier_key(receipt: &TransactionReceipt) -> H256 {
+    let mut hasher = ShaHasher::new();
+    hasher.0.update(b"nullifier");
+    hasher.0.update(receipt.to_h256().as_slice());
+
+    let mut key: [u8; 32] = hasher.finish().into();
+    key[..NULLIFIER_KEY_PREFIX.len()].copy_from_slice(NULLIFIER_KEY_PREFIX);
+
+    H256::from(key)
+}
+
+pub fn is_nullifier_key(key: &H256) -> bool {
+    key.as_slice().starts_with(NULLIFIER_KEY_PREFIX)
+}
+
+//Checks a key read by a transaction is not in the nullifier key domain.
+pub fn check_not_nullifier_key(key: &H256) -> Result<(), StateTransitionError> {
+    match is_nullifier_key(key) {
+        true => Err(StateTransitionError::ReservedKey),
+        false => Ok(()),
+    }
+}
+
+pub struct Inbox {
+    chain_id: u64,
+}
+
+impl Inbox {
+    pub fn new(chain_id: u64) -> Self {
+        Inbox { chain_id }
+    }
+
+    //Checks the receipt is included in any of the recent aggregated receipts roots.
+    pub fn verify_inclusion(
+        receipt: &TransactionReceipt,
+        proof: &MerkleProof,
+        aggregated_proof: &AggregatedBatch,
+    ) -> Result<(), StateTransitionError> {
+        let receipt_hash = receipt.to_h256();
+
+        if receipt_hash == H256::zero() {
+            return Err(StateTransitionError::ReceiptNotIncluded);
+        }
+
+        match aggregated_proof.verify_inclusion(proof, vec![(receipt_hash, receipt_hash)]) {
+            true => Ok(()),
+            false => Err(StateTransitionError::InvalidMerkleProof),
+        }
+    }
+
+    //Consumes a receipt, given the current leaf at its nullifier key along with the
+    //key it was read at. Returns the nullifier leaf to be written to state.
+    pub fn consume<V: Nullifier>(
+        &self,
+        receipt: &TransactionReceipt,
+        proof: &MerkleProof,
+        aggregated_proof: &AggregatedBatch,
+        nullifier: &(H256, V),
+    ) -> Result<V, StateTransitionError> {
+        let (key, nullifier) = nullifier;
+
+        if key != &nullifier_key(receipt) {
+            return Err(StateTransitionError::NullifierKeyMismatch);
+        }
+
+        if nullifier.is_consumed() {
+            return Err(StateTransitionError::ReceiptConsumed);
+        }
+
+        Self::verify_inclusion(receipt, proof, aggregated_proof)?;
+
+        Ok(V::nullifier(nullifier_key(receipt)))
+    }
+
+    //Consumes a typed message addressed to this chain.
+    pub fn consume_message<M: Encode + Decode, V: Nullifier>(
+        &self,
+        receipt: &TransactionReceipt,
+        proof: &MerkleProof,
+        aggregated_proof: &AggregatedBatch,
+        nullifier: &(H256, V),
+    ) -> Result<(M, V), StateTransitionError> {
+        let message = match Message::<M>::from_receipt(receipt) {
+            Ok(i) => i,
+            Err(e) => return Err(StateTransitionError::InvalidReceipt(e.to_string())),
+        };
+
+        if message.to_chain != self.chain_id {
+            return Err(StateTransitionError::InvalidReceipt(String::from(
+                "Message is not addressed to this chain.",
+            )));
+        }
+
+        let consumed = self.consume(receipt, proof, aggregated_proof, nullifier)?;
+
+        Ok((message.payload, consumed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{payments::types::Account, types::ReceiptLeaf};
+    use sparse_merkle_tree::{default_store::DefaultStore, SparseMerkleTree};
+
+    fn receipt() -> TransactionReceipt {
+        TransactionReceipt {
+            chain_id: 7000,
+            data: vec![1, 2, 3],
+        }
+    }
+
+    //Aggregated batch with the receipt in its receipts root, and the inclusion proof.
+    fn aggregated_with_receipt() -> (AggregatedBatch, MerkleProof) {
+        let mut tree: SparseMerkleTree<ShaHasher, ReceiptLeaf, DefaultStore<ReceiptLeaf>> =
+            SparseMerkleTree::default();
+        let key = receipt().to_h256();
+
+        tree.update(
+            key,
+            ReceiptLeaf {
+                key,
+                receipt: receipt(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let proof = tree.merkle_proof(vec![key]).unwrap();
+        let aggregated_proof = AggregatedBatch {
+            proof_number: 1,
+            receipts_root: *tree.root(),
+            ..Default::default()
+        };
+
+        (aggregated_proof, proof)
+    }
+
+    #[test]
+    fn rejects_second_consume() {
+        let inbox = Inbox::new(7001);
+        let (aggregated_proof, proof) = aggregated_with_receipt();
+        let key = nullifier_key(&receipt());
+
+        let consumed = inbox
+            .consume(
+                &receipt(),
+                &proof,
+                &aggregated_proof,
+                &(key, Account::zero()),
+            )
+            .unwrap();
+
+        assert!(consumed.is_consumed());
+        assert_eq!(
+            inbox
+                .consume(&receipt(), &proof, &aggregated_proof, &(key, consumed))
+                .unwrap_err(),
+            StateTransitionError::ReceiptConsumed
+        );
+    }
+
+    #[test]
+    fn rejects_nullifier_read_at_other_key() {
+        let inbox = Inbox::new(7001);
+        let (aggregated_proof, proof) = aggregated_with_receipt();
+
+        assert_eq!(
+            inbox
+                .consume(
+                    &receipt(),
+                    &proof,
+                    &aggregated_proof,
+                    &(H256::from([5u8; 32]), Account::zero())
+                )
+                .unwrap_err(),
+            StateTransitionError::NullifierKeyMismatch
+        );
+    }
+
+    #[test]
+    fn rejects_receipt_not_included() {
+        let inbox = Inbox::new(7001);
+        let (aggregated_proof, proof) = aggregated_with_receipt();
+        let other = TransactionReceipt {
+            chain_id: 7000,
+            data: vec![4, 5, 6],
+        };
+
+        assert_eq!(
+            inbox
+                .consume(
+                    &other,
+                    &proof,
+                    &aggregated_proof,
+                    &(nullifier_key(&other), Account::zero())
+                )
+                .unwrap_err(),
+            StateTransitionError::InvalidMerkleProof
+        );
+    }
+
+    #[test]
+    fn nullifier_keys_are_reserved() {
+        let key = nullifier_key(&receipt());
+
+        assert!(key.as_slice().starts_with(b"nullifier"));
+        assert_eq!(
+            check_not_nullifier_key(&key).unwrap_err(),
+            StateTransitionError::ReservedKey
+        );
+        assert!(check_not_nullifier_key(&receipt().to_h256()).is_ok());
+    }
+}
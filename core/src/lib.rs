@@ -1,10 +1,16 @@
 pub use primitive_types::U256;
+pub mod aggregation;
+pub mod errors;
+pub mod inbox;
 pub mod nft;
 #[cfg(any(feature = "native", feature = "native-metal"))]
 pub mod app_node;
 #[cfg(any(feature = "native", feature = "native-metal"))]
 pub mod db;
+#[cfg(any(feature = "native", feature = "native-metal"))]
+pub mod events;
 pub mod payments;
+pub mod receipts;
 #[cfg(any(feature = "native", feature = "native-metal"))]
 pub mod state;
 pub mod traits;
@@ -1,11 +1,14 @@
 use crate::db::NodeDB;
 use crate::traits::StateTransition;
 use crate::{
+    inbox::nullifier_key,
     nft::state_transition::NftStateTransition,
-    nft::types::{Nft, NftId, NftTransaction, NftTransactionMessage},
+    nft::types::{Nft, NftId, NftTransaction, NftTransactionMessage, Swap},
+    payments::types::Offer,
     state::VmState,
     traits::StateMachine,
     types::{Address, AggregatedBatch, StateUpdate, TransactionReceipt},
+    errors::{StateMachineError, StateTransitionError},
 };
 use anyhow::{anyhow, Error};
 use sparse_merkle_tree::traits::Value;
@@ -49,16 +52,61 @@ impl NftStateMachine {
 
         Ok(listed_nfts)
     }
+
+    pub fn get_offers(&self, id: &NftId) -> Result<Vec<Offer>, Error> {
+        match self.db.get(&offers_key(id)) {
+            Ok(Some(i)) => Ok(i),
+            Ok(None) => Ok(vec![]),
+            Err(e) => Err(anyhow!("Could not access db due to error: . {:?}", e)),
+        }
+    }
+
+    pub fn add_offer(&self, offer: Offer) -> Result<(), Error> {
+        let mut offers = self.get_offers(&offer.nft_id)?;
+
+        if offers.iter().any(|i| i.escrow_address() == offer.escrow_address()) {
+            return Err(anyhow!("Offer already placed."));
+        }
+
+        offers.push(offer.clone());
+
+        Ok(self.db.put(&offers_key(&offer.nft_id), &offers)?)
+    }
+
+    pub fn get_swap(&self, swap_id: &H256) -> Result<Option<Swap>, Error> {
+        match self.db.get(&swap_key(swap_id)) {
+            Ok(i) => Ok(i),
+            Err(e) => Err(anyhow!("Could not access db due to error: . {:?}", e)),
+        }
+    }
+
+    pub fn add_swap(&self, swap: &Swap) -> Result<H256, Error> {
+        let swap_id = swap.id();
+
+        self.db.put(&swap_key(&swap_id), swap)?;
+
+        Ok(swap_id)
+    }
+}
+
+fn swap_key(swap_id: &H256) -> Vec<u8> {
+    [b"swap-".as_slice(), swap_id.as_slice()].concat()
+}
+
+fn offers_key(id: &NftId) -> Vec<u8> {
+    [b"offers-".as_slice(), id.0.as_slice()].concat()
 }
 
 impl StateMachine<Nft, NftTransaction> for NftStateMachine {
-    fn new(root: H256) -> Self {
+    type Snapshot = VmState<Nft>;
+
+    fn new(root: H256, chain_id: u64) -> Self {
         let state = VmState::new(root);
         let node_db = NodeDB::from_path(String::from("./marketplace_db"));
 
         NftStateMachine {
             state: state,
-            stf: NftStateTransition::new(),
+            stf: NftStateTransition::new(chain_id),
             custodian: None,
             db: node_db,
         }
@@ -68,39 +116,9 @@ impl StateMachine<Nft, NftTransaction> for NftStateMachine {
         &mut self,
         params: NftTransaction,
         aggregated_proof: AggregatedBatch,
-    ) -> Result<(StateUpdate<Nft>, TransactionReceipt), Error> {
-        let message: NftTransactionMessage = NftTransactionMessage::try_from(params.clone())?;
-
-        let nft_id = match message {
-            NftTransactionMessage::Transfer(ref i) => i.id.clone(),
-            NftTransactionMessage::Mint(ref i) => i.id.clone(),
-            NftTransactionMessage::Burn(ref i) => i.id.clone(),
-            NftTransactionMessage::Trigger(ref i) => i.id.clone(),
-        };
-        let nft_key = nft_id.get_key();
-
-        println!("{:?}", &nft_key);
-
-        let nft = match self.state.get(&nft_key, false) {
-            Ok(Some(i)) => i,
-            Err(e) => return Err(e),
-            Ok(None) => Nft::zero(),
-        };
-
-        let result = match self
-            .stf
-            .execute_tx(vec![nft.clone()], params, aggregated_proof)
-        {
-            Ok(i) => i,
-            Err(e) => return Err(e),
-        };
-
-        let updated_set = result.0;
-
-        let (update, receipt) = match self.state.update_set(updated_set.clone()) {
-            Ok(i) => (i, result.1),
-            Err(e) => return Err(e),
-        };
+    ) -> Result<(StateUpdate<Nft>, TransactionReceipt), StateMachineError> {
+        let (update, receipt, nft, updated_set) =
+            execute_in(&mut self.state, &self.stf, params, aggregated_proof)?;
 
         match &self.custodian {
             Some(custodian) => {
@@ -133,7 +151,7 @@ impl StateMachine<Nft, NftTransaction> for NftStateMachine {
                 let mut all_listed_nfts: Vec<NftId> = match self.db.get(b"all_listed_nfts") {
                     Ok(Some(i)) => i,
                     Ok(None) => vec![],
-                    Err(e) => return Err(anyhow!("Could not access db due to error: . {:?}", e)),
+                    Err(e) => return Err(e.into()),
                 };
 
                 // Add listed_nfts to all_listed_nfts if they don't already exist
@@ -154,23 +172,89 @@ impl StateMachine<Nft, NftTransaction> for NftStateMachine {
         Ok((update, receipt))
     }
 
-    fn get_state_with_proof(&self, key: &H256) -> Result<(Nft, MerkleProof), Error> {
-        self.state.get_with_proof(key)
+    //Listings are not updated, as the execution is not committed.
+    fn simulate_tx(
+        &self,
+        params: NftTransaction,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(StateUpdate<Nft>, TransactionReceipt), StateMachineError> {
+        let mut state = self.state.scratch()?;
+        let (update, receipt, _, _) = execute_in(&mut state, &self.stf, params, aggregated_proof)?;
+
+        Ok((update, receipt))
+    }
+
+    fn get_state_with_proof(&self, key: &H256) -> Result<(Nft, MerkleProof), StateMachineError> {
+        Ok(self.state.get_with_proof(key)?)
+    }
+
+    fn get_state(&self, key: &H256) -> Result<Option<Nft>, StateMachineError> {
+        Ok(self.state.get(key, true)?)
     }
 
-    fn get_state(&self, key: &H256) -> Result<Option<Nft>, Error> {
-        self.state.get(key, true)
+    fn snapshot(&self) -> Result<VmState<Nft>, StateMachineError> {
+        Ok(self.state.scratch()?)
     }
 
-    fn revert(&mut self) -> Result<(), Error> {
-        self.state.revert()
+    fn revert(&mut self) -> Result<(), StateMachineError> {
+        Ok(self.state.revert()?)
     }
 
-    fn commit(&mut self) -> Result<(), Error> {
-        self.state.commit()
+    fn commit(&mut self) -> Result<(), StateMachineError> {
+        Ok(self.state.commit()?)
     }
 
-    fn get_root(&self) -> Result<H256, Error> {
+    fn get_root(&self) -> Result<H256, StateMachineError> {
         Ok(self.state.get_root())
     }
 }
+
+//Executes the transaction against the given state, returning the nft read before
+//execution and the updated set along with the state update.
+fn execute_in(
+    state: &mut VmState<Nft>,
+    stf: &NftStateTransition,
+    params: NftTransaction,
+    aggregated_proof: AggregatedBatch,
+) -> Result<(StateUpdate<Nft>, TransactionReceipt, Nft, Vec<Nft>), StateMachineError> {
+    let message: NftTransactionMessage = match NftTransactionMessage::try_from(params.clone()) {
+        Ok(i) => i,
+        Err(e) => return Err(StateTransitionError::InvalidTransaction(e.to_string()).into()),
+    };
+
+    let nft_id = message.id().clone();
+    let nft_key = nft_id.get_key();
+
+    println!("{:?}", &nft_key);
+
+    let nft = match state.get(&nft_key, false) {
+        Ok(Some(i)) => i,
+        Err(e) => return Err(e.into()),
+        Ok(None) => Nft::zero(),
+    };
+
+    let mut pre_state = vec![(nft_key, nft.clone())];
+
+    //Bids consume the escrow receipt, so the nullifier leaf is part of the state read.
+    if let NftTransactionMessage::PlaceBid(bid) = &message {
+        let nullifier_key = nullifier_key(&bid.escrow_receipt);
+
+        pre_state.push(match state.get(&nullifier_key, false) {
+            Ok(Some(i)) => (nullifier_key, i),
+            Err(e) => return Err(e.into()),
+            Ok(None) => (nullifier_key, Nft::zero()),
+        });
+    }
+
+    let result = match stf.execute_tx(pre_state, params, aggregated_proof) {
+        Ok(i) => i,
+        Err(e) => return Err(e.into()),
+    };
+
+    let updated_set = result.0;
+
+    match state.update_set(updated_set.clone()) {
+        Ok(i) => Ok((i, result.1, nft, updated_set)),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,37 +1,57 @@
 use crate::{
+    inbox::{check_not_nullifier_key, Inbox},
     nft::types::{
-        Burn, Future, FutureReceiptData, Mint, Nft, NftTransaction, NftTransactionMessage, Transfer, TransferReceiptData,
-        Trigger
+        Auction, BidReceiptData, Burn, CreateAuction, Future, FutureReceiptData, Mint, Nft, NftTransaction, NftTransactionMessage,
+        PlaceBid, SettleAuction, SwapLock, Transfer, TransferReceiptData, Trigger
     },
+    payments::types::{CallType, PaymentReceiptData},
+    receipts::ReceiptData,
     traits::StateTransition,
     types::{AggregatedBatch, ShaHasher, TransactionReceipt, Address},
+    errors::StateTransitionError,
 };
 use sparse_merkle_tree::traits::Value;
 use sparse_merkle_tree::H256;
-use anyhow::{Error, anyhow};
+
+//Chain which holds the escrow backing auction bids.
+const PAYMENTS_CHAIN_ID: u64 = 7001;
 
 use sha2::Digest;
 
-pub struct NftStateTransition;
+pub struct NftStateTransition {
+    chain_id: u64,
+}
 
 impl NftStateTransition {
-    pub fn new() -> Self {
-        NftStateTransition {}
+    //Multiple NFT chains can run side by side, as long as their chain IDs differ.
+    pub fn new(chain_id: u64) -> Self {
+        NftStateTransition { chain_id }
     }
 
     fn transfer(
         &self,
         params: Transfer,
         pre_state: Nft,
-    ) -> Result<(Vec<Nft>, TransactionReceipt), Error> {
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
         if pre_state == Nft::zero() {
-            return Err(anyhow!("NFT not minted."));
+            return Err(StateTransitionError::NotMinted);
         }
 
         if pre_state.owner != params.from {
-            return Err(anyhow!("Not owner"));
+            return Err(StateTransitionError::NotOwner);
         }
 
+        if pre_state.auction.is_some() {
+            return Err(StateTransitionError::UnderAuction);
+        }
+
+        check_future_deadline(
+            params.future_commitment,
+            params.future_deadline,
+            &aggregated_proof,
+        )?;
+
         let updated_nonce = pre_state.nonce + 1;
 
         match params.future_commitment {
@@ -42,9 +62,10 @@ impl NftStateTransition {
                     nonce: updated_nonce,
                     future: None,
                     metadata: pre_state.metadata,
+                    auction: None,
                 }],
                 TransactionReceipt {
-                    chain_id: 7000,
+                    chain_id: self.chain_id,
                     data: (TransferReceiptData {
                         id: params.id,
                         from: params.from,
@@ -62,12 +83,14 @@ impl NftStateTransition {
                     future: Some(Future {
                         to: params.to.clone(),
                         commitment: i,
+                        deadline: params.future_deadline,
                     }),
                     metadata: pre_state.metadata,
+                    auction: None,
                     nonce: updated_nonce,
                 }],
                 TransactionReceipt {
-                    chain_id: 7000,
+                    chain_id: self.chain_id,
                     data: (FutureReceiptData {
                         id: params.id,
                         from: params.from,
@@ -75,6 +98,7 @@ impl NftStateTransition {
                         data: params.data,
                         nonce: updated_nonce,
                         future_commitment: i,
+                        deadline: params.future_deadline,
                     })
                     .to_encoded(),
                 },
@@ -82,11 +106,22 @@ impl NftStateTransition {
         }
     }
 
-    fn mint(&self, params: Mint, pre_state: Nft) -> Result<(Vec<Nft>, TransactionReceipt), Error> {
+    fn mint(
+        &self,
+        params: Mint,
+        pre_state: Nft,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
         if pre_state != Nft::zero() {
-            return Err(anyhow!("Already minted, {:?}", pre_state));
+            return Err(StateTransitionError::AlreadyMinted);
         }
 
+        check_future_deadline(
+            params.future_commitment,
+            params.future_deadline,
+            &aggregated_proof,
+        )?;
+
         match params.future_commitment {
             None => Ok((
                 vec![Nft {
@@ -95,9 +130,10 @@ impl NftStateTransition {
                     nonce: 1,
                     future: None,
                     metadata: params.metadata,
+                    auction: None,
                 }],
                 TransactionReceipt {
-                    chain_id: 7000,
+                    chain_id: self.chain_id,
                     data: (TransferReceiptData {
                         id: params.id,
                         from: Address::zero(),
@@ -116,11 +152,13 @@ impl NftStateTransition {
                     future: Some(Future {
                         to: params.to.clone(),
                         commitment: i,
+                        deadline: params.future_deadline,
                     }),
                     metadata: params.metadata,
+                    auction: None,
                 }],
                 TransactionReceipt {
-                    chain_id: 7000,
+                    chain_id: self.chain_id,
                     data: (FutureReceiptData {
                         id: params.id,
                         from: Address::zero(),
@@ -128,6 +166,7 @@ impl NftStateTransition {
                         data: params.data,
                         nonce: 1,
                         future_commitment: i,
+                        deadline: params.future_deadline,
                     })
                     .to_encoded(),
                 },
@@ -135,15 +174,30 @@ impl NftStateTransition {
         }
     }
 
-    fn burn(&self, params: Burn, pre_state: Nft) -> Result<(Vec<Nft>, TransactionReceipt), Error> {
+    fn burn(
+        &self,
+        params: Burn,
+        pre_state: Nft,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
         if pre_state == Nft::zero() {
-            return Err(anyhow!("Nft does not exist"));
+            return Err(StateTransitionError::NotMinted);
         }
 
         if pre_state.owner != params.from {
-            return Err(anyhow!("Not owner"))
+            return Err(StateTransitionError::NotOwner)
+        }
+
+        if pre_state.auction.is_some() {
+            return Err(StateTransitionError::UnderAuction);
         }
 
+        check_future_deadline(
+            params.future_commitment,
+            params.future_deadline,
+            &aggregated_proof,
+        )?;
+
         let updated_nonce = pre_state.nonce + 1;
 
         match params.future_commitment {
@@ -154,9 +208,10 @@ impl NftStateTransition {
                     nonce: updated_nonce,
                     future: None,
                     metadata: pre_state.metadata,
+                    auction: None,
                 }],
                 TransactionReceipt {
-                    chain_id: 7000,
+                    chain_id: self.chain_id,
                     data: (TransferReceiptData {
                         id: params.id,
                         from: params.from,
@@ -174,12 +229,14 @@ impl NftStateTransition {
                     future: Some(Future {
                         to: Address::zero(),
                         commitment: i,
+                        deadline: params.future_deadline,
                     }),
                     metadata: pre_state.metadata,
+                    auction: None,
                     nonce: updated_nonce,
                 }],
                 TransactionReceipt {
-                    chain_id: 7000,
+                    chain_id: self.chain_id,
                     data: (FutureReceiptData {
                         id: params.id,
                         from: params.from,
@@ -187,6 +244,7 @@ impl NftStateTransition {
                         data: params.data,
                         nonce: updated_nonce,
                         future_commitment: i,
+                        deadline: params.future_deadline,
                     })
                     .to_encoded(),
                 },
@@ -199,24 +257,38 @@ impl NftStateTransition {
         params: Trigger,
         pre_state: Nft,
         aggregated_proof: AggregatedBatch,
-    ) -> Result<(Vec<Nft>, TransactionReceipt), Error> {
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
         if pre_state == Nft::zero() {
-            return Err(anyhow!("Nft does not exist."));
+            return Err(StateTransitionError::NotMinted);
         }
 
         let future = match pre_state.future {
-            None => return Err(anyhow!("No future registered.")),
+            None => return Err(StateTransitionError::NoFuture),
             Some(i) => i,
         };
 
-        match params.merkle_proof.verify::<ShaHasher>(
-            &aggregated_proof.receipts_root,
-            //Checks both inclusion or non inclusion.
-            vec![(future.commitment, params.receipt.to_h256())],
-        ) {
-            Ok(true) => (),
-            Ok(false) => return Err(anyhow!("Invalid merkle proof.")),
-            Err(_e) => return Err(anyhow!("Error while verifying merkle")),
+        //Inclusion is accepted against any recent aggregated root, but non
+        //inclusion only against the latest one.
+        if params.receipt.to_h256() != H256::zero() {
+            if !aggregated_proof.verify_inclusion(
+                &params.merkle_proof,
+                vec![(future.commitment, params.receipt.to_h256())],
+            ) {
+                return Err(StateTransitionError::InvalidMerkleProof);
+            }
+        } else {
+            if !future.can_revert(aggregated_proof.proof_number) {
+                return Err(StateTransitionError::FutureNotExpired);
+            }
+
+            match params.merkle_proof.verify::<ShaHasher>(
+                &aggregated_proof.receipts_root,
+                vec![(future.commitment, H256::zero())],
+            ) {
+                Ok(true) => (),
+                Ok(false) => return Err(StateTransitionError::InvalidMerkleProof),
+                Err(_e) => return Err(StateTransitionError::InvalidMerkleProof),
+            }
         }
 
         let updated_nonce = pre_state.nonce + 1;
@@ -230,10 +302,11 @@ impl NftStateTransition {
                     owner: pre_state.owner.clone(),
                     future: None,
                     metadata: pre_state.metadata,
+                    auction: None,
                     nonce: updated_nonce,
                 }],
                 TransactionReceipt {
-                    chain_id: 7000,
+                    chain_id: self.chain_id,
                     data: (TransferReceiptData {
                         id: params.id,
                         from: pre_state.owner.clone(),
@@ -252,9 +325,10 @@ impl NftStateTransition {
                     future: None,
                     nonce: updated_nonce,
                     metadata: pre_state.metadata,
+                    auction: None,
                 }],
                 TransactionReceipt {
-                    chain_id: 7000,
+                    chain_id: self.chain_id,
                     data: (TransferReceiptData {
                         id: params.id,
                         from: pre_state.owner,
@@ -267,32 +341,484 @@ impl NftStateTransition {
             ))
         }
     }
+
+    fn create_auction(
+        &self,
+        params: CreateAuction,
+        pre_state: Nft,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
+        if pre_state == Nft::zero() {
+            return Err(StateTransitionError::NotMinted);
+        }
+
+        if pre_state.owner != params.from {
+            return Err(StateTransitionError::NotOwner);
+        }
+
+        if pre_state.auction.is_some() || pre_state.future.is_some() {
+            return Err(StateTransitionError::Locked);
+        }
+
+        if params.end <= aggregated_proof.proof_number {
+            return Err(StateTransitionError::AuctionEndPassed);
+        }
+
+        let updated_nonce = pre_state.nonce + 1;
+
+        Ok((
+            vec![Nft {
+                id: params.id.clone(),
+                owner: pre_state.owner.clone(),
+                future: None,
+                nonce: updated_nonce,
+                metadata: pre_state.metadata,
+                auction: Some(Auction {
+                    reserve_price: params.reserve_price,
+                    end: params.end,
+                    highest_bid: None,
+                    settled: false,
+                }),
+            }],
+            TransactionReceipt {
+                chain_id: self.chain_id,
+                data: (TransferReceiptData {
+                    id: params.id,
+                    from: pre_state.owner.clone(),
+                    to: pre_state.owner,
+                    data: params.data,
+                    nonce: updated_nonce,
+                })
+                .to_encoded(),
+            },
+        ))
+    }
+
+    fn place_bid(
+        &self,
+        params: PlaceBid,
+        pre_state: Nft,
+        nullifier: (H256, Nft),
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
+        let mut auction = match pre_state.auction.clone() {
+            Some(i) => i,
+            None => return Err(StateTransitionError::NotUnderAuction),
+        };
+
+        if aggregated_proof.proof_number >= auction.end {
+            return Err(StateTransitionError::AuctionEnded);
+        }
+
+        let offer = params.offer;
+
+        if offer.buyer != params.from || offer.nft_id != params.id || offer.nft_chain_id != self.chain_id {
+            return Err(StateTransitionError::OfferMismatch(String::from("Bid")));
+        }
+
+        //Escrow must stay locked until the auction can be settled.
+        if offer.expiry <= auction.end {
+            return Err(StateTransitionError::EscrowExpiresBeforeAuctionEnd);
+        }
+
+        let min_amount = match &auction.highest_bid {
+            Some(i) => i.amount + 1,
+            None => auction.reserve_price,
+        };
+
+        if offer.amount < min_amount {
+            return Err(StateTransitionError::BidTooLow);
+        }
+
+        //Escrow receipt is consumed, so an offer refunded after being outbid can not
+        //be placed again.
+        let consumed = Inbox::new(self.chain_id).consume(
+            &params.escrow_receipt,
+            &params.merkle_proof,
+            &aggregated_proof,
+            &nullifier,
+        )?;
+
+        if params.escrow_receipt.chain_id != PAYMENTS_CHAIN_ID {
+            return Err(StateTransitionError::InvalidReceipt(String::from(
+                "Escrow receipt not from payments chain.",
+            )));
+        }
+
+        let escrow = match PaymentReceiptData::from_encoded(&params.escrow_receipt.data) {
+            Ok(i) => i,
+            Err(e) => return Err(StateTransitionError::InvalidReceipt(e.to_string())),
+        };
+
+        if escrow.call_type != CallType::Escrow(offer.clone())
+            || escrow.from != offer.buyer
+            || escrow.to != offer.escrow_address()
+            || escrow.amount != offer.amount
+        {
+            return Err(StateTransitionError::OfferMismatch(String::from("Escrow receipt")));
+        }
+
+        let outbid = auction.highest_bid.replace(offer.clone());
+        let updated_nonce = pre_state.nonce + 1;
+
+        Ok((
+            vec![
+                Nft {
+                    id: params.id.clone(),
+                    owner: pre_state.owner,
+                    future: None,
+                    nonce: updated_nonce,
+                    metadata: pre_state.metadata,
+                    auction: Some(auction),
+                },
+                consumed,
+            ],
+            TransactionReceipt {
+                chain_id: self.chain_id,
+                data: (BidReceiptData {
+                    id: params.id,
+                    bid: offer,
+                    outbid,
+                    nonce: updated_nonce,
+                })
+                .to_encoded(),
+            },
+        ))
+    }
+
+    fn settle_auction(
+        &self,
+        params: SettleAuction,
+        pre_state: Nft,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
+        let mut auction = match pre_state.auction.clone() {
+            Some(i) => i,
+            None => return Err(StateTransitionError::NotUnderAuction),
+        };
+
+        if auction.settled {
+            return Err(StateTransitionError::AuctionSettled);
+        }
+
+        if aggregated_proof.proof_number < auction.end {
+            return Err(StateTransitionError::AuctionNotEnded);
+        }
+
+        let updated_nonce = pre_state.nonce + 1;
+
+        //A winning bid whose escrow can already be refunded can no longer be released.
+        let winning_bid = auction
+            .highest_bid
+            .clone()
+            .filter(|i| i.expiry > aggregated_proof.proof_number);
+
+        match winning_bid {
+            //Auction ends without a sale, the NFT is unlocked.
+            None => Ok((
+                vec![Nft {
+                    id: params.id.clone(),
+                    owner: pre_state.owner.clone(),
+                    future: None,
+                    nonce: updated_nonce,
+                    metadata: pre_state.metadata,
+                    auction: None,
+                }],
+                TransactionReceipt {
+                    chain_id: self.chain_id,
+                    data: (TransferReceiptData {
+                        id: params.id,
+                        from: pre_state.owner.clone(),
+                        to: pre_state.owner,
+                        data: params.data,
+                        nonce: updated_nonce,
+                    })
+                    .to_encoded(),
+                },
+            )),
+            //Hold the NFT for the winner until the escrow is released, same as
+            //an accepted offer.
+            Some(offer) => {
+                let commitment = offer
+                    .release_receipt(PAYMENTS_CHAIN_ID, &pre_state.owner)
+                    .to_h256();
+                auction.settled = true;
+
+                Ok((
+                    vec![Nft {
+                        id: params.id.clone(),
+                        owner: pre_state.owner.clone(),
+                        future: Some(Future {
+                            to: offer.buyer.clone(),
+                            commitment,
+                            //Escrow can only be released until the bid expires.
+                            deadline: offer.expiry,
+                        }),
+                        nonce: updated_nonce,
+                        metadata: pre_state.metadata,
+                        auction: Some(auction),
+                    }],
+                    TransactionReceipt {
+                        chain_id: self.chain_id,
+                        data: (FutureReceiptData {
+                            id: params.id,
+                            from: pre_state.owner,
+                            to: offer.buyer,
+                            data: params.data,
+                            nonce: updated_nonce,
+                            future_commitment: commitment,
+                            deadline: offer.expiry,
+                        })
+                        .to_encoded(),
+                    },
+                ))
+            }
+        }
+    }
+
+    fn swap_lock(
+        &self,
+        params: SwapLock,
+        pre_state: Nft,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
+        if pre_state == Nft::zero() {
+            return Err(StateTransitionError::NotMinted);
+        }
+
+        if pre_state.owner != params.from {
+            return Err(StateTransitionError::NotOwner);
+        }
+
+        if pre_state.auction.is_some() || pre_state.future.is_some() {
+            return Err(StateTransitionError::Locked);
+        }
+
+        if aggregated_proof.proof_number >= params.swap.deadline {
+            return Err(StateTransitionError::SwapDeadlinePassed);
+        }
+
+        let swap = params.swap;
+        let (side, counterparty) = match swap.sides_for(self.chain_id, &params.id) {
+            Some(i) => i,
+            None => return Err(StateTransitionError::NotInSwap),
+        };
+
+        //Nonce pins the swap to the current state, so a stale swap cannot be locked.
+        if side.owner != pre_state.owner || side.nonce != pre_state.nonce {
+            return Err(StateTransitionError::SwapMismatch);
+        }
+
+        let updated_nonce = pre_state.nonce + 1;
+
+        Ok((
+            vec![Nft {
+                id: params.id,
+                owner: pre_state.owner,
+                future: Some(Future {
+                    to: counterparty.owner.clone(),
+                    commitment: swap.lock_receipt(counterparty).to_h256(),
+                    deadline: swap.deadline,
+                }),
+                nonce: updated_nonce,
+                metadata: pre_state.metadata,
+                auction: None,
+            }],
+            swap.lock_receipt(side),
+        ))
+    }
 }
 
 impl StateTransition<Nft, NftTransaction> for NftStateTransition {
     fn execute_tx(
         &self,
-        pre_state: Vec<Nft>,
+        pre_state: Vec<(H256, Nft)>,
         params: NftTransaction,
         aggregated_proof: AggregatedBatch,
-    ) -> Result<(Vec<Nft>, TransactionReceipt), Error> {
-        let message: NftTransactionMessage = NftTransactionMessage::try_from(params.clone())?;
-
-        match { match message.clone() {
-            NftTransactionMessage::Transfer(i) => i.from.verify_msg(&params.signature, &params.message),
-            NftTransactionMessage::Mint(i) => i.from.verify_msg(&params.signature, &params.message),
-            NftTransactionMessage::Burn(i) => i.from.verify_msg(&params.signature, &params.message),
-            NftTransactionMessage::Trigger(i) => i.from.verify_msg(&params.signature, &params.message),
-        } } {
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
+        let message: NftTransactionMessage = match NftTransactionMessage::try_from(params.clone()) {
+            Ok(i) => i,
+            Err(e) => return Err(StateTransitionError::InvalidTransaction(e.to_string())),
+        };
+
+        match message.sender().verify_msg(&params.signature, &params.message) {
             true => (),
-            false => return Err(anyhow!("Signature verification failed.")),
+            false => return Err(StateTransitionError::InvalidSignature),
+        };
+
+        let nft = match pre_state.first() {
+            Some((key, nft)) if key == &message.id().get_key() => nft.clone(),
+            _ => return Err(StateTransitionError::StateKeyMismatch),
         };
 
+        check_not_nullifier_key(&message.id().get_key())?;
+
         match message {
-            NftTransactionMessage::Transfer(i) => self.transfer(i, pre_state[0].clone()),
-            NftTransactionMessage::Mint(i) => self.mint(i, pre_state[0].clone()),
-            NftTransactionMessage::Burn(i) => self.burn(i, pre_state[0].clone()),
-            NftTransactionMessage::Trigger(i) => self.trigger(i, pre_state[0].clone(), aggregated_proof),
+            NftTransactionMessage::Transfer(i) => self.transfer(i, nft, aggregated_proof),
+            NftTransactionMessage::Mint(i) => self.mint(i, nft, aggregated_proof),
+            NftTransactionMessage::Burn(i) => self.burn(i, nft, aggregated_proof),
+            NftTransactionMessage::Trigger(i) => self.trigger(i, nft, aggregated_proof),
+            NftTransactionMessage::CreateAuction(i) => self.create_auction(i, nft, aggregated_proof),
+            NftTransactionMessage::PlaceBid(i) => match pre_state.get(1) {
+                Some(nullifier) => self.place_bid(i, nft, nullifier.clone(), aggregated_proof),
+                None => Err(StateTransitionError::StateKeyMismatch),
+            },
+            NftTransactionMessage::SettleAuction(i) => self.settle_auction(i, nft, aggregated_proof),
+            NftTransactionMessage::SwapLock(i) => self.swap_lock(i, nft, aggregated_proof),
+        }
+    }
+}
+
+//A future has to leave time for its receipt to be made before the deadline.
+fn check_future_deadline(
+    commitment: Option<H256>,
+    deadline: u64,
+    aggregated_proof: &AggregatedBatch,
+) -> Result<(), StateTransitionError> {
+    match commitment {
+        Some(_) if deadline <= aggregated_proof.proof_number => {
+            Err(StateTransitionError::FutureDeadlinePassed)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        nft::types::{NftId, NftMetadata},
+        types::{ReceiptLeaf, RECENT_ROOTS_WINDOW, SETTLEMENT_LAG},
+    };
+    use sparse_merkle_tree::{default_store::DefaultStore, MerkleProof, SparseMerkleTree};
+
+    type ReceiptsTree = SparseMerkleTree<ShaHasher, ReceiptLeaf, DefaultStore<ReceiptLeaf>>;
+
+    const CHAIN_ID: u64 = 7000;
+    const DEADLINE: u64 = 10;
+
+    fn commitment() -> H256 {
+        H256::from([1u8; 32])
+    }
+
+    fn future_receipt() -> TransactionReceipt {
+        TransactionReceipt {
+            chain_id: 7001,
+            data: vec![1, 2, 3],
+        }
+    }
+
+    fn nft_with_future() -> Nft {
+        Nft {
+            id: NftId([2u8; 32]),
+            owner: Address([3u8; 32]),
+            future: Some(Future {
+                to: Address([4u8; 32]),
+                commitment: commitment(),
+                deadline: DEADLINE,
+            }),
+            nonce: 0,
+            metadata: NftMetadata::default(),
+            auction: None,
+        }
+    }
+
+    fn trigger_params(receipt: TransactionReceipt, merkle_proof: MerkleProof) -> Trigger {
+        Trigger {
+            id: NftId([2u8; 32]),
+            from: Address([3u8; 32]),
+            data: None,
+            merkle_proof,
+            receipt,
+        }
+    }
+
+    //Tree with the future receipt committed at the future commitment.
+    fn tree_with_receipt() -> ReceiptsTree {
+        let mut tree = ReceiptsTree::default();
+        tree.update(
+            commitment(),
+            ReceiptLeaf {
+                key: commitment(),
+                receipt: future_receipt(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        tree
+    }
+
+    fn revert_at(
+        proof_number: u64,
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
+        let tree = ReceiptsTree::default();
+        let proof = tree.merkle_proof(vec![commitment()]).unwrap();
+        let aggregated_proof = AggregatedBatch {
+            proof_number,
+            receipts_root: *tree.root(),
+            ..Default::default()
+        };
+
+        NftStateTransition::new(CHAIN_ID).trigger(
+            trigger_params(TransactionReceipt::default(), proof),
+            nft_with_future(),
+            aggregated_proof,
+        )
+    }
+
+    //Proves the future receipt against the batch it was aggregated in, after
+    //the given number of later aggregations.
+    fn claim_after(
+        aggregations: usize,
+    ) -> Result<(Vec<Nft>, TransactionReceipt), StateTransitionError> {
+        let tree = tree_with_receipt();
+        let proof = tree.merkle_proof(vec![commitment()]).unwrap();
+        let mut aggregated_proof = AggregatedBatch {
+            proof_number: 1,
+            receipts_root: *tree.root(),
+            ..Default::default()
+        };
+
+        for i in 0..aggregations {
+            aggregated_proof = aggregated_proof.next(H256::from([i as u8 + 10; 32]), H256::zero());
         }
+
+        NftStateTransition::new(CHAIN_ID).trigger(
+            trigger_params(future_receipt(), proof),
+            nft_with_future(),
+            aggregated_proof,
+        )
+    }
+
+    #[test]
+    fn revert_rejected_at_settlement_lag() {
+        assert_eq!(
+            revert_at(DEADLINE + SETTLEMENT_LAG).unwrap_err(),
+            StateTransitionError::FutureNotExpired
+        );
+    }
+
+    #[test]
+    fn revert_accepted_after_settlement_lag() {
+        let (nfts, _) = revert_at(DEADLINE + SETTLEMENT_LAG + 1).unwrap();
+
+        assert_eq!(nfts[0].owner, Address([3u8; 32]));
+        assert_eq!(nfts[0].future, None);
+    }
+
+    #[test]
+    fn claim_accepted_within_recent_roots() {
+        let (nfts, _) = claim_after(RECENT_ROOTS_WINDOW).unwrap();
+
+        assert_eq!(nfts[0].owner, Address([4u8; 32]));
+    }
+
+    #[test]
+    fn claim_rejected_past_recent_roots() {
+        assert_eq!(
+            claim_after(RECENT_ROOTS_WINDOW + 1).unwrap_err(),
+            StateTransitionError::InvalidMerkleProof
+        );
     }
 }
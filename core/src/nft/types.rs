@@ -1,6 +1,9 @@
 use crate::{
+    inbox::Nullifier,
+    payments::types::Offer,
+    receipts::ReceiptData,
     traits::{Leaf, TxHasher},
-    types::{ShaHasher, TransactionReceipt, TxSignature, Address},
+    types::{ShaHasher, TransactionReceipt, TxSignature, Address, SETTLEMENT_LAG},
 };
 use risc0_zkvm::sha::rust_crypto::Digest;
 use parity_scale_codec::{Encode, Decode};
@@ -35,6 +38,9 @@ pub struct Nft {
     pub future: Option<Future>,
     pub nonce: u64,
     pub metadata: NftMetadata,
+    //Missing from NFTs stored by earlier nodes.
+    #[serde(default)]
+    pub auction: Option<Auction>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default, Encode, Decode)]
@@ -48,6 +54,38 @@ impl Nft {
     pub fn to_encoded(&self) -> Vec<u8> {
         self.encode()
     }
+
+    //Encoding from before auctions and future deadlines were added. NFTs using
+    //neither keep hashing to it, so state stored by earlier nodes keeps its root.
+    fn legacy_encoded(&self) -> Option<Vec<u8>> {
+        if self.auction.is_some() {
+            return None;
+        }
+
+        let future = match &self.future {
+            Some(i) if i.deadline != 0 => return None,
+            Some(i) => Some((i.to.clone(), i.commitment)),
+            None => None,
+        };
+
+        Some((&self.id, &self.owner, future, self.nonce, &self.metadata).encode())
+    }
+}
+
+//Nullifier leaves share the tree with NFTs, at keys in the reserved nullifier
+//domain. Their owner is set to the key itself only so they hash to a non zero
+//value, they can never be transferred.
+impl Nullifier for Nft {
+    fn nullifier(key: H256) -> Self {
+        Nft {
+            id: NftId(key.into()),
+            owner: Address(key.into()),
+            future: None,
+            nonce: 0,
+            metadata: NftMetadata::default(),
+            auction: None,
+        }
+    }
 }
 
 impl Value for Nft {
@@ -57,8 +95,19 @@ impl Value for Nft {
         }
 
         let mut hasher = ShaHasher::new();
-        let encoded = self.to_encoded();
-        hasher.0.update(&encoded);
+
+        match self.legacy_encoded() {
+            Some(encoded) => hasher.0.update(&encoded),
+            //Tagged digest of the full encoding. Legacy encodings are always longer
+            //than 33 bytes, so the two can not collide.
+            None => {
+                let mut inner = ShaHasher::new();
+                inner.0.update(&self.to_encoded());
+
+                hasher.0.update(&[1u8]);
+                hasher.0.update(inner.finish().as_slice());
+            }
+        }
 
         hasher.finish()
     }
@@ -72,6 +121,29 @@ impl Value for Nft {
 pub struct Future {
     pub to: Address,
     pub commitment: H256,
+    //Aggregated batch number until which the committed receipt can be made.
+    //Missing from futures stored by earlier nodes.
+    #[serde(default)]
+    pub deadline: u64,
+}
+
+impl Future {
+    //Non inclusion only proves the receipt is not aggregated yet. Once past the
+    //deadline and the settlement lag, any receipt made before the deadline would
+    //be aggregated, so its absence is final.
+    pub fn can_revert(&self, proof_number: u64) -> bool {
+        proof_number > self.deadline + SETTLEMENT_LAG
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct Auction {
+    pub reserve_price: u64,
+    //Aggregated batch number after which no bids are accepted.
+    pub end: u64,
+    pub highest_bid: Option<Offer>,
+    //Set once the NFT is held under a future for the highest bidder.
+    pub settled: bool,
 }
 
 // impl Future {
@@ -92,6 +164,8 @@ pub struct Transfer {
     pub from: Address,
     pub data: Option<String>,
     pub future_commitment: Option<H256>,
+    //Deadline of the future, only used with a future commitment.
+    pub future_deadline: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
@@ -101,6 +175,8 @@ pub struct Mint {
     pub to: Address,
     pub data: Option<String>,
     pub future_commitment: Option<H256>,
+    //Deadline of the future, only used with a future commitment.
+    pub future_deadline: u64,
     pub metadata: NftMetadata,
 }
 
@@ -110,6 +186,8 @@ pub struct Burn {
     pub from: Address,
     pub data: Option<String>,
     pub future_commitment: Option<H256>,
+    //Deadline of the future, only used with a future commitment.
+    pub future_deadline: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
@@ -121,12 +199,133 @@ pub struct Trigger {
     pub receipt: TransactionReceipt,
 }
 
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct CreateAuction {
+    pub id: NftId,
+    pub from: Address,
+    pub reserve_price: u64,
+    pub end: u64,
+    pub data: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct PlaceBid {
+    pub id: NftId,
+    pub from: Address,
+    pub offer: Offer,
+    //Receipt of the payments chain locking the bid in escrow.
+    pub escrow_receipt: TransactionReceipt,
+    pub merkle_proof: MerkleProof,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct SettleAuction {
+    pub id: NftId,
+    pub from: Address,
+    pub data: Option<String>,
+}
+
+//One NFT of a swap. Sides can live on the same or on different NFT chains.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct SwapSide {
+    pub chain_id: u64,
+    pub id: NftId,
+    pub owner: Address,
+    //Nonce of the NFT before it is locked for the swap.
+    pub nonce: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct Swap {
+    pub first: SwapSide,
+    pub second: SwapSide,
+    //Aggregated batch number after which the swap can no longer be locked.
+    pub deadline: u64,
+}
+
+impl Swap {
+    pub fn id(&self) -> H256 {
+        let mut hasher = ShaHasher::new();
+        hasher.0.update(&self.encode());
+
+        hasher.finish()
+    }
+
+    //Returns the side for the given NFT, and its counterparty.
+    pub fn sides_for(&self, chain_id: u64, id: &NftId) -> Option<(&SwapSide, &SwapSide)> {
+        if self.first.chain_id == chain_id && &self.first.id == id {
+            Some((&self.first, &self.second))
+        } else if self.second.chain_id == chain_id && &self.second.id == id {
+            Some((&self.second, &self.first))
+        } else {
+            None
+        }
+    }
+
+    //Receipt emitted when a side is locked. Each side's future commits to the
+    //lock receipt of the other side, so neither lock depends on the other's commitment.
+    pub fn lock_receipt(&self, side: &SwapSide) -> TransactionReceipt {
+        let counterparty = if side == &self.first { &self.second } else { &self.first };
+
+        TransactionReceipt {
+            chain_id: side.chain_id,
+            data: (SwapLockReceiptData {
+                swap_id: self.id(),
+                id: side.id.clone(),
+                from: side.owner.clone(),
+                to: counterparty.owner.clone(),
+                nonce: side.nonce + 1,
+            })
+            .to_encoded(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct SwapLock {
+    pub id: NftId,
+    pub from: Address,
+    pub swap: Swap,
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
 pub enum NftTransactionMessage {
     Transfer(Transfer),
     Mint(Mint),
     Burn(Burn),
     Trigger(Trigger),
+    CreateAuction(CreateAuction),
+    PlaceBid(PlaceBid),
+    SettleAuction(SettleAuction),
+    SwapLock(SwapLock),
+}
+
+impl NftTransactionMessage {
+    pub fn id(&self) -> &NftId {
+        match self {
+            NftTransactionMessage::Transfer(i) => &i.id,
+            NftTransactionMessage::Mint(i) => &i.id,
+            NftTransactionMessage::Burn(i) => &i.id,
+            NftTransactionMessage::Trigger(i) => &i.id,
+            NftTransactionMessage::CreateAuction(i) => &i.id,
+            NftTransactionMessage::PlaceBid(i) => &i.id,
+            NftTransactionMessage::SettleAuction(i) => &i.id,
+            NftTransactionMessage::SwapLock(i) => &i.id,
+        }
+    }
+
+    pub fn sender(&self) -> &Address {
+        match self {
+            NftTransactionMessage::Transfer(i) => &i.from,
+            NftTransactionMessage::Mint(i) => &i.from,
+            NftTransactionMessage::Burn(i) => &i.from,
+            NftTransactionMessage::Trigger(i) => &i.from,
+            NftTransactionMessage::CreateAuction(i) => &i.from,
+            NftTransactionMessage::PlaceBid(i) => &i.from,
+            NftTransactionMessage::SettleAuction(i) => &i.from,
+            NftTransactionMessage::SwapLock(i) => &i.from,
+        }
+    }
 }
 
 //TODO: Check the implications of decoding message inside ZKVM.
@@ -182,6 +381,7 @@ pub struct FutureReceiptData {
     pub from: Address,
     pub to: Address,
     pub future_commitment: H256,
+    pub deadline: u64,
     pub data: Option<String>,
     pub nonce: u64,
 }
@@ -195,14 +395,51 @@ pub struct TransferReceiptData {
     pub nonce: u64,
 }
 
-impl TransferReceiptData {
-    pub fn to_encoded(&self) -> Vec<u8> {
-        self.encode()
-    }
+//Emitted when a bid is placed. The payments chain refunds the outbid offer on
+//proof of this receipt, without waiting for the offer to expire.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct BidReceiptData {
+    pub id: NftId,
+    pub bid: Offer,
+    pub outbid: Option<Offer>,
+    pub nonce: u64,
 }
 
-impl FutureReceiptData {
-    pub fn to_encoded(&self) -> Vec<u8> {
-        self.encode()
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct SwapLockReceiptData {
+    pub swap_id: H256,
+    pub id: NftId,
+    pub from: Address,
+    pub to: Address,
+    pub nonce: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn future(deadline: u64) -> Future {
+        Future {
+            to: Address::zero(),
+            commitment: H256::zero(),
+            deadline,
+        }
+    }
+
+    #[test]
+    fn can_revert_only_after_settlement_lag() {
+        let future = future(10);
+
+        assert!(!future.can_revert(10));
+        assert!(!future.can_revert(10 + SETTLEMENT_LAG));
+        assert!(future.can_revert(10 + SETTLEMENT_LAG + 1));
+    }
+
+    #[test]
+    fn legacy_future_reverts_after_settlement_lag() {
+        let future = future(0);
+
+        assert!(!future.can_revert(SETTLEMENT_LAG));
+        assert!(future.can_revert(SETTLEMENT_LAG + 1));
     }
 }
@@ -1,12 +1,13 @@
 use crate::{
+    inbox::nullifier_key,
     payments::state_transition::PaymentsStateTransition,
-    payments::types::{Account, Transaction as PaymentsTransaction, TransactionMessage},
+    payments::types::{Account, CallType, Transaction as PaymentsTransaction, TransactionMessage},
     state::VmState,
     traits::{StateMachine, StateTransition},
     types::{AggregatedBatch, StateUpdate, TransactionReceipt},
+    errors::{StateMachineError, StateTransitionError},
 };
 
-use anyhow::{anyhow, Error};
 use sparse_merkle_tree::traits::Value;
 use sparse_merkle_tree::MerkleProof;
 use sparse_merkle_tree::H256;
@@ -17,12 +18,14 @@ pub struct PaymentsStateMachine {
 }
 
 impl StateMachine<Account, PaymentsTransaction> for PaymentsStateMachine {
-    fn new(root: H256) -> Self {
+    type Snapshot = VmState<Account>;
+
+    fn new(root: H256, chain_id: u64) -> Self {
         let state = VmState::new(root);
 
         PaymentsStateMachine {
             state: state,
-            stf: PaymentsStateTransition::new(),
+            stf: PaymentsStateTransition::new(chain_id),
         }
     }
 
@@ -30,55 +33,92 @@ impl StateMachine<Account, PaymentsTransaction> for PaymentsStateMachine {
         &mut self,
         params: PaymentsTransaction,
         aggregated_proof: AggregatedBatch,
-    ) -> Result<(StateUpdate<Account>, TransactionReceipt), Error> {
-        let message: TransactionMessage = TransactionMessage::try_from(params.clone())?;
-        let from_address_key = message.from.get_key();
-        let to_address_key = message.to.get_key();
-
-        let from_account: Account = match self.state.get(&from_address_key, false) {
-            Ok(Some(i)) => i,
-            Err(_e) => return Err(anyhow!("Error in finding account details")),
-            Ok(None) => Account::zero(),
-        };
-
-        let to_account = match self.state.get(&to_address_key, false) {
-            Ok(Some(i)) => i,
-            Err(_e) => return Err(anyhow!("Error in finding account details")),
-            Ok(None) => Account::zero(),
-        };
-
-        let result =
-            match self
-                .stf
-                .execute_tx(vec![from_account, to_account], params, aggregated_proof)
-            {
-                Ok(i) => i,
-                Err(e) => return Err(e),
-            };
-
-        match self.state.update_set(result.0) {
-            Ok(i) => Ok((i, result.1)),
-            Err(e) => Err(e),
-        }
+    ) -> Result<(StateUpdate<Account>, TransactionReceipt), StateMachineError> {
+        execute_in(&mut self.state, &self.stf, params, aggregated_proof)
+    }
+
+    fn simulate_tx(
+        &self,
+        params: PaymentsTransaction,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(StateUpdate<Account>, TransactionReceipt), StateMachineError> {
+        let mut state = self.state.scratch()?;
+
+        execute_in(&mut state, &self.stf, params, aggregated_proof)
     }
 
-    fn get_state_with_proof(&self, key: &H256) -> Result<(Account, MerkleProof), Error> {
-        self.state.get_with_proof(key)
+    fn get_state_with_proof(&self, key: &H256) -> Result<(Account, MerkleProof), StateMachineError> {
+        Ok(self.state.get_with_proof(key)?)
     }
 
-    fn get_state(&self, key: &H256) -> Result<Option<Account>, Error> {
-        self.state.get(key, true)
+    fn get_state(&self, key: &H256) -> Result<Option<Account>, StateMachineError> {
+        Ok(self.state.get(key, true)?)
     }
 
-    fn revert(&mut self) -> Result<(), Error> {
-        self.state.revert()
+    fn snapshot(&self) -> Result<VmState<Account>, StateMachineError> {
+        Ok(self.state.scratch()?)
     }
 
-    fn commit(&mut self) -> Result<(), Error> {
-        self.state.commit()
+    fn revert(&mut self) -> Result<(), StateMachineError> {
+        Ok(self.state.revert()?)
     }
 
-    fn get_root(&self) -> Result<H256, Error> {
+    fn commit(&mut self) -> Result<(), StateMachineError> {
+        Ok(self.state.commit()?)
+    }
+
+    fn get_root(&self) -> Result<H256, StateMachineError> {
         Ok(self.state.get_root())
     }
 }
+
+//Executes the transaction against the given state, so the same execution can
+//run on a scratch state.
+fn execute_in(
+    state: &mut VmState<Account>,
+    stf: &PaymentsStateTransition,
+    params: PaymentsTransaction,
+    aggregated_proof: AggregatedBatch,
+) -> Result<(StateUpdate<Account>, TransactionReceipt), StateMachineError> {
+    let message: TransactionMessage = match TransactionMessage::try_from(params.clone()) {
+        Ok(i) => i,
+        Err(e) => return Err(StateTransitionError::InvalidTransaction(e.to_string()).into()),
+    };
+    let from_address_key = message.from.get_key();
+    let to_address_key = message.to.get_key();
+
+    let from_account: Account = match state.get(&from_address_key, false) {
+        Ok(Some(i)) => i,
+        Err(e) => return Err(e.into()),
+        Ok(None) => Account::zero(),
+    };
+
+    let to_account = match state.get(&to_address_key, false) {
+        Ok(Some(i)) => i,
+        Err(e) => return Err(e.into()),
+        Ok(None) => Account::zero(),
+    };
+
+    let mut pre_state = vec![(from_address_key, from_account), (to_address_key, to_account)];
+
+    //Releases consume a receipt, so the nullifier leaf is part of the state read.
+    if let CallType::Release(release) = &message.call_type {
+        let nullifier_key = nullifier_key(&release.future_receipt);
+
+        pre_state.push(match state.get(&nullifier_key, false) {
+            Ok(Some(i)) => (nullifier_key, i),
+            Err(e) => return Err(e.into()),
+            Ok(None) => (nullifier_key, Account::zero()),
+        });
+    }
+
+    let result = match stf.execute_tx(pre_state, params, aggregated_proof) {
+        Ok(i) => i,
+        Err(e) => return Err(e.into()),
+    };
+
+    match state.update_set(result.0) {
+        Ok(i) => Ok((i, result.1)),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,32 +1,33 @@
 use crate::traits::StateTransition;
 use crate::{
+    inbox::{check_not_nullifier_key, Inbox},
+    nft::types::{BidReceiptData, FutureReceiptData},
     payments::types::{
-        Account, CallType, PaymentReceiptData, Transaction as PaymentsTransaction, TransactionMessage
+        Account, CallType, EscrowRelease, Offer, OutbidRefund, PaymentReceiptData, Transaction as PaymentsTransaction, TransactionMessage
     },
+    receipts::ReceiptData,
     traits::StateMachine,
     types::{AggregatedBatch, TransactionReceipt, Address},
 };
 use sparse_merkle_tree::traits::Value;
+use sparse_merkle_tree::H256;
 
-use anyhow::{Error, anyhow};
+use crate::errors::StateTransitionError;
 
 pub struct PaymentsStateTransition {
     chain_id: u64,
 }
 
 impl PaymentsStateTransition {
-    pub fn new() -> Self {
-        PaymentsStateTransition {
-            //TODO: make chain ID configurable.
-            chain_id: 7001,
-        }
+    pub fn new(chain_id: u64) -> Self {
+        PaymentsStateTransition { chain_id }
     }
 
     fn transfer(
         &self,
         params: TransactionMessage,
         pre_state: Vec<Account>,
-    ) -> Result<(Vec<Account>, TransactionReceipt), Error> {
+    ) -> Result<(Vec<Account>, TransactionReceipt), StateTransitionError> {
         #[cfg(any(feature = "native", feature = "native-metal"))]
         println!("\n Executing following transaction: {:?} \n", &params);
 
@@ -42,11 +43,11 @@ impl PaymentsStateTransition {
         println!("{:?}", from_account);
 
         if from_account.balance < params.amount {
-            return Err(anyhow!("not enough balance."))
+            return Err(StateTransitionError::InsufficientBalance)
         }
 
         if from_account.address == params.to {
-            return Err(anyhow!("Cannot transfer to self."));
+            return Err(StateTransitionError::SelfTransfer);
         }
 
         from_account.balance -= params.amount;
@@ -83,7 +84,7 @@ impl PaymentsStateTransition {
         &self,
         params: TransactionMessage,
         pre_state: Vec<Account>,
-    ) -> Result<(Vec<Account>, TransactionReceipt), Error> {
+    ) -> Result<(Vec<Account>, TransactionReceipt), StateTransitionError> {
         #[cfg(any(feature = "native", feature = "native-metal"))]
         println!("\n Executing following transaction: {:?} \n", &params);
 
@@ -148,24 +149,209 @@ impl PaymentsStateTransition {
             ))
         }
     }
+
+    fn escrow(
+        &self,
+        params: TransactionMessage,
+        pre_state: Vec<Account>,
+        offer: Offer,
+    ) -> Result<(Vec<Account>, TransactionReceipt), StateTransitionError> {
+        if params.from != offer.buyer
+            || params.to != offer.escrow_address()
+            || params.amount != offer.amount
+        {
+            return Err(StateTransitionError::OfferMismatch(String::from("Escrow")));
+        }
+
+        //An escrow account can only be funded once, so its release receipt is unique.
+        //Funding marks the account with a nonce, as a plain transfer to the escrow
+        //address only adds to its balance and must not block the offer.
+        if pre_state[1].nonce != 0 {
+            return Err(StateTransitionError::EscrowExists);
+        }
+
+        let (mut updated_set, receipt) = self.transfer(params, pre_state)?;
+        updated_set[1].nonce += 1;
+
+        Ok((updated_set, receipt))
+    }
+
+    fn release(
+        &self,
+        params: TransactionMessage,
+        pre_state: Vec<Account>,
+        nullifier: (H256, Account),
+        release: EscrowRelease,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Account>, TransactionReceipt), StateTransitionError> {
+        let offer = release.offer;
+
+        if params.from != offer.escrow_address() || params.amount != offer.amount {
+            return Err(StateTransitionError::OfferMismatch(String::from("Release")));
+        }
+
+        //Future receipt is consumed, so it cannot be used to release any other escrow.
+        let nullifier = Inbox::new(self.chain_id).consume(
+            &release.future_receipt,
+            &release.merkle_proof,
+            &aggregated_proof,
+            &nullifier,
+        )?;
+
+        if release.future_receipt.chain_id != offer.nft_chain_id {
+            return Err(StateTransitionError::InvalidReceipt(String::from(
+                "Future receipt not from offer chain.",
+            )));
+        }
+
+        let future = match FutureReceiptData::from_encoded(&release.future_receipt.data) {
+            Ok(i) => i,
+            Err(e) => return Err(StateTransitionError::InvalidReceipt(e.to_string())),
+        };
+
+        //Releases stop at the offer expiry, and at the future deadline, so they can
+        //not overlap with the future being reverted on the NFT chain.
+        if aggregated_proof.proof_number > offer.expiry.min(future.deadline) {
+            return Err(StateTransitionError::OfferExpired);
+        }
+
+        let expected_commitment = offer.release_receipt(self.chain_id, &params.to).to_h256();
+
+        if future.id != offer.nft_id
+            || future.to != offer.buyer
+            || future.from != params.to
+            || future.future_commitment != expected_commitment
+        {
+            return Err(StateTransitionError::OfferMismatch(String::from("Future")));
+        }
+
+        //Receipt is emitted as a plain transfer, so the seller can compute it in advance.
+        let (mut updated_set, receipt) = self.transfer(
+            TransactionMessage {
+                call_type: CallType::Transfer,
+                data: None,
+                ..params
+            },
+            pre_state,
+        )?;
+
+        updated_set.push(nullifier);
+
+        Ok((updated_set, receipt))
+    }
+
+    fn refund(
+        &self,
+        params: TransactionMessage,
+        pre_state: Vec<Account>,
+        offer: Offer,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Account>, TransactionReceipt), StateTransitionError> {
+        if params.from != offer.escrow_address()
+            || params.to != offer.buyer
+            || params.amount != offer.amount
+        {
+            return Err(StateTransitionError::OfferMismatch(String::from("Refund")));
+        }
+
+        if aggregated_proof.proof_number <= offer.expiry {
+            return Err(StateTransitionError::OfferNotExpired);
+        }
+
+        self.transfer(params, pre_state)
+    }
+
+    fn refund_outbid(
+        &self,
+        params: TransactionMessage,
+        pre_state: Vec<Account>,
+        refund: OutbidRefund,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Account>, TransactionReceipt), StateTransitionError> {
+        let offer = refund.offer;
+
+        if params.from != offer.escrow_address()
+            || params.to != offer.buyer
+            || params.amount != offer.amount
+        {
+            return Err(StateTransitionError::OfferMismatch(String::from("Refund")));
+        }
+
+        //Escrow accounts are funded once, so the receipt does not need to be consumed.
+        Inbox::verify_inclusion(&refund.bid_receipt, &refund.merkle_proof, &aggregated_proof)?;
+
+        if refund.bid_receipt.chain_id != offer.nft_chain_id {
+            return Err(StateTransitionError::InvalidReceipt(String::from(
+                "Bid receipt not from offer chain.",
+            )));
+        }
+
+        let bid = match BidReceiptData::from_encoded(&refund.bid_receipt.data) {
+            Ok(i) => i,
+            Err(e) => return Err(StateTransitionError::InvalidReceipt(e.to_string())),
+        };
+
+        if bid.id != offer.nft_id || bid.outbid != Some(offer) {
+            return Err(StateTransitionError::OfferMismatch(String::from("Bid")));
+        }
+
+        self.transfer(params, pre_state)
+    }
 }
 
 impl StateTransition<Account, PaymentsTransaction> for PaymentsStateTransition {
     fn execute_tx(
         &self,
-        pre_state: Vec<Account>,
+        pre_state: Vec<(H256, Account)>,
         params: PaymentsTransaction,
-        _aggregated_proof: AggregatedBatch,
-    ) -> Result<(Vec<Account>, TransactionReceipt), Error> {
-        let message: TransactionMessage = TransactionMessage::try_from(params.clone())?;
-        match message.from.verify_msg(&params.signature, &params.message) {
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(Vec<Account>, TransactionReceipt), StateTransitionError> {
+        let message: TransactionMessage = match TransactionMessage::try_from(params.clone()) {
+            Ok(i) => i,
+            Err(e) => return Err(StateTransitionError::InvalidTransaction(e.to_string())),
+        };
+        let verified = match message.call_type {
+            //Escrow accounts have no key, releases are authorised by the merkle proof.
+            CallType::Release(_) => true,
+            CallType::Refund(ref offer) => offer.buyer.verify_msg(&params.signature, &params.message),
+            CallType::RefundOutbid(ref refund) => {
+                refund.offer.buyer.verify_msg(&params.signature, &params.message)
+            }
+            _ => message.from.verify_msg(&params.signature, &params.message),
+        };
+
+        match verified {
             true => (), 
-            false => return Err(anyhow!("Signature verification failed.")),
+            false => return Err(StateTransitionError::InvalidSignature),
+        }
+
+        //Accounts are read at the sender and recipient keys. Only one is read when a
+        //mint is sent to self, as it only updates one account.
+        let keys = [message.from.get_key(), message.to.get_key()];
+
+        for ((key, _), expected) in pre_state.iter().zip(keys) {
+            if key != &expected {
+                return Err(StateTransitionError::StateKeyMismatch);
+            }
+
+            check_not_nullifier_key(key)?;
         }
 
-        match message.call_type {
+        let nullifier = pre_state.get(2).cloned();
+        let pre_state: Vec<Account> = pre_state.into_iter().take(2).map(|(_, i)| i).collect();
+
+        match message.call_type.clone() {
             CallType::Transfer => self.transfer(message, pre_state),
             CallType::Mint => self.mint(message, pre_state),
+            CallType::Escrow(offer) => self.escrow(message, pre_state, offer),
+            CallType::Release(release) => match nullifier {
+                Some(i) => self.release(message, pre_state, i, release, aggregated_proof),
+                None => Err(StateTransitionError::StateKeyMismatch),
+            },
+            CallType::Refund(offer) => self.refund(message, pre_state, offer, aggregated_proof),
+            CallType::RefundOutbid(refund) => {
+                self.refund_outbid(message, pre_state, refund, aggregated_proof)
+            }
         }
     }
 }
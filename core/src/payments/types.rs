@@ -1,6 +1,9 @@
 use crate::{
+    inbox::Nullifier,
+    nft::types::NftId,
+    receipts::ReceiptData,
     traits::{Leaf, TxHasher},
-    types::{ShaHasher, TxSignature, Address},
+    types::{ShaHasher, TransactionReceipt, TxSignature, Address},
 };
 use risc0_zkvm::sha::rust_crypto::Digest;
 use parity_scale_codec::{Encode, Decode};
@@ -8,6 +11,7 @@ use serde::{Deserialize, Serialize};
 use ed25519_consensus::Signature;
 use anyhow::{anyhow};
 use sparse_merkle_tree::{
+    merkle_proof::MerkleProof,
     traits::{Hasher, Value},
     H256,
 };
@@ -25,6 +29,16 @@ impl Leaf<H256> for Account {
     }
 }
 
+impl Nullifier for Account {
+    fn nullifier(key: H256) -> Self {
+        Account {
+            address: Address(key.into()),
+            balance: 0,
+            nonce: 1,
+        }
+    }
+}
+
 impl Value for Account {
     fn to_h256(&self) -> H256 {
         if self.balance == 0 && self.nonce == 0 {
@@ -47,6 +61,88 @@ impl Value for Account {
 pub enum CallType {
     Transfer,
     Mint,
+    //Locks funds of the buyer in the escrow account derived from the offer.
+    Escrow(Offer),
+    //Pays out escrowed funds to the NFT owner, on proof that the NFT is held
+    //under a future for the buyer.
+    Release(EscrowRelease),
+    //Returns escrowed funds to the buyer once the offer has expired.
+    Refund(Offer),
+    //Returns escrowed funds to the buyer, on proof that the bid was outbid.
+    RefundOutbid(OutbidRefund),
+}
+
+//An offer to buy an NFT on any NFT chain, backed by funds in escrow.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct Offer {
+    pub buyer: Address,
+    pub nft_chain_id: u64,
+    pub nft_id: NftId,
+    pub amount: u64,
+    //Aggregated batch number after which the buyer can claim a refund.
+    pub expiry: u64,
+    //Allows the same buyer to place multiple offers with the same terms.
+    pub salt: u64,
+}
+
+impl Offer {
+    //Address of the escrow account. No one holds the key for it, so funds can
+    //only leave it through a release or refund.
+    pub fn escrow_address(&self) -> Address {
+        let mut hasher = ShaHasher::new();
+        hasher.0.update(b"escrow");
+        hasher.0.update(&self.encode());
+
+        Address(hasher.finish().into())
+    }
+
+    //Receipt emitted by the payments chain when escrow is released to the seller.
+    //NFT owners accepting the offer use its hash as the future commitment.
+    pub fn release_receipt(&self, chain_id: u64, seller: &Address) -> TransactionReceipt {
+        TransactionReceipt {
+            chain_id,
+            data: (PaymentReceiptData {
+                from: self.escrow_address(),
+                to: seller.clone(),
+                amount: self.amount,
+                call_type: CallType::Transfer,
+                data: None,
+                //Funding marks the escrow account with nonce 1, so the release is
+                //always its second nonce.
+                nonce: 2,
+            })
+            .to_encoded(),
+        }
+    }
+}
+
+//Offer as published off-chain, signed by the buyer so no one else can place it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct SignedOffer {
+    pub offer: Offer,
+    pub signature: TxSignature,
+}
+
+impl SignedOffer {
+    pub fn verify(&self) -> bool {
+        self.offer.buyer.verify_msg(&self.signature, &self.offer.encode())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct EscrowRelease {
+    pub offer: Offer,
+    //Future receipt of the NFT chain, proving the NFT is held for the buyer.
+    pub future_receipt: TransactionReceipt,
+    pub merkle_proof: MerkleProof,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct OutbidRefund {
+    pub offer: Offer,
+    //Bid receipt of the NFT chain, recording the offer as outbid.
+    pub bid_receipt: TransactionReceipt,
+    pub merkle_proof: MerkleProof,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
@@ -102,12 +198,6 @@ pub struct PaymentReceiptData {
     pub nonce: u64,
 }
 
-impl PaymentReceiptData {
-    pub fn to_encoded(&self) -> Vec<u8> {
-        self.encode()
-    }
-}
-
 impl TryFrom<Transaction> for TransactionMessage {
     type Error = anyhow::Error;
 
@@ -0,0 +1,242 @@
+use crate::{
+    nft::types::{BidReceiptData, FutureReceiptData, SwapLockReceiptData, TransferReceiptData},
+    payments::types::PaymentReceiptData,
+    types::{ReceiptOrigin, TransactionReceipt},
+};
+use anyhow::{anyhow, Error};
+use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use sparse_merkle_tree::MerkleProof;
+use std::collections::HashMap;
+
+//Receipt data is prefixed with its kind and the version of its encoding, so
+//receipts can be decoded without knowing which chain or call emitted them.
+pub const RECEIPT_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum ReceiptKind {
+    NftTransfer = 0,
+    NftFuture = 1,
+    NftSwapLock = 2,
+    Payment = 3,
+    Message = 4,
+    NftBid = 5,
+}
+
+impl TryFrom<u8> for ReceiptKind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ReceiptKind::NftTransfer),
+            1 => Ok(ReceiptKind::NftFuture),
+            2 => Ok(ReceiptKind::NftSwapLock),
+            3 => Ok(ReceiptKind::Payment),
+            4 => Ok(ReceiptKind::Message),
+            5 => Ok(ReceiptKind::NftBid),
+            _ => Err(anyhow!("Unknown receipt kind {}", value)),
+        }
+    }
+}
+
+pub trait ReceiptData: Encode + Decode {
+    const KIND: ReceiptKind;
+
+    fn to_encoded(&self) -> Vec<u8> {
+        let mut encoded = vec![Self::KIND as u8, RECEIPT_VERSION];
+        self.encode_to(&mut encoded);
+
+        encoded
+    }
+
+    fn from_encoded(data: &[u8]) -> Result<Self, Error> {
+        let (kind, mut payload) = split_tag(data)?;
+
+        if kind != Self::KIND {
+            return Err(anyhow!("Expected {:?} receipt, got {:?}", Self::KIND, kind));
+        }
+
+        match Self::decode(&mut payload) {
+            Ok(i) => Ok(i),
+            Err(e) => Err(anyhow!("{:?}", e)),
+        }
+    }
+}
+
+//Returns the kind of the receipt data and the untagged payload.
+pub fn split_tag(data: &[u8]) -> Result<(ReceiptKind, &[u8]), Error> {
+    if data.len() < 2 {
+        return Err(anyhow!("Receipt data is not tagged."));
+    }
+
+    if data[1] != RECEIPT_VERSION {
+        return Err(anyhow!("Unsupported receipt version {}", data[1]));
+    }
+
+    Ok((ReceiptKind::try_from(data[0])?, &data[2..]))
+}
+
+impl ReceiptData for TransferReceiptData {
+    const KIND: ReceiptKind = ReceiptKind::NftTransfer;
+}
+
+impl ReceiptData for FutureReceiptData {
+    const KIND: ReceiptKind = ReceiptKind::NftFuture;
+}
+
+impl ReceiptData for SwapLockReceiptData {
+    const KIND: ReceiptKind = ReceiptKind::NftSwapLock;
+}
+
+impl ReceiptData for BidReceiptData {
+    const KIND: ReceiptKind = ReceiptKind::NftBid;
+}
+
+impl ReceiptData for PaymentReceiptData {
+    const KIND: ReceiptKind = ReceiptKind::Payment;
+}
+
+//Messages are generic over their payload, so they are decoded with the payload
+//left as raw bytes.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Encode, Decode)]
+pub struct MessageReceiptData {
+    pub to_chain: u64,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum DecodedReceipt {
+    NftTransfer(TransferReceiptData),
+    NftFuture(FutureReceiptData),
+    NftSwapLock(SwapLockReceiptData),
+    NftBid(BidReceiptData),
+    Payment(PaymentReceiptData),
+    Message(MessageReceiptData),
+}
+
+//Response of the nexus receipt endpoint. `decoded` is empty when the receipt is
+//not yet aggregated, or its chain has no schema registered for it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReceiptWithProof {
+    pub receipt: TransactionReceipt,
+    pub origin: ReceiptOrigin,
+    pub proof: MerkleProof,
+    //Aggregated batch whose receipts root the proof is against.
+    pub proof_number: u64,
+    pub decoded: Option<DecodedReceipt>,
+}
+
+pub type ReceiptDecoder = fn(&[u8]) -> Result<DecodedReceipt, Error>;
+
+fn decode_nft_transfer(data: &[u8]) -> Result<DecodedReceipt, Error> {
+    Ok(DecodedReceipt::NftTransfer(TransferReceiptData::from_encoded(data)?))
+}
+
+fn decode_nft_future(data: &[u8]) -> Result<DecodedReceipt, Error> {
+    Ok(DecodedReceipt::NftFuture(FutureReceiptData::from_encoded(data)?))
+}
+
+fn decode_nft_swap_lock(data: &[u8]) -> Result<DecodedReceipt, Error> {
+    Ok(DecodedReceipt::NftSwapLock(SwapLockReceiptData::from_encoded(data)?))
+}
+
+fn decode_nft_bid(data: &[u8]) -> Result<DecodedReceipt, Error> {
+    Ok(DecodedReceipt::NftBid(BidReceiptData::from_encoded(data)?))
+}
+
+fn decode_payment(data: &[u8]) -> Result<DecodedReceipt, Error> {
+    Ok(DecodedReceipt::Payment(PaymentReceiptData::from_encoded(data)?))
+}
+
+fn decode_message(data: &[u8]) -> Result<DecodedReceipt, Error> {
+    let (_, mut payload) = split_tag(data)?;
+
+    let to_chain = match u64::decode(&mut payload) {
+        Ok(i) => i,
+        Err(e) => return Err(anyhow!("{:?}", e)),
+    };
+
+    Ok(DecodedReceipt::Message(MessageReceiptData {
+        to_chain,
+        payload: payload.to_vec(),
+    }))
+}
+
+//Decodes receipt data by its kind tag alone, for tools that do not know which
+//schemas the emitting chain registered.
+pub fn decode_tagged(data: &[u8]) -> Result<DecodedReceipt, Error> {
+    let (kind, _) = split_tag(data)?;
+
+    match kind {
+        ReceiptKind::NftTransfer => decode_nft_transfer(data),
+        ReceiptKind::NftFuture => decode_nft_future(data),
+        ReceiptKind::NftSwapLock => decode_nft_swap_lock(data),
+        ReceiptKind::NftBid => decode_nft_bid(data),
+        ReceiptKind::Payment => decode_payment(data),
+        ReceiptKind::Message => decode_message(data),
+    }
+}
+
+//Kind of app chain, which decides the receipt schemas it emits.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ChainKind {
+    Nft,
+    Payments,
+}
+
+//Schemas of the receipts each chain emits, keyed by chain ID and receipt kind.
+#[derive(Clone)]
+pub struct ReceiptRegistry {
+    decoders: HashMap<(u64, ReceiptKind), ReceiptDecoder>,
+}
+
+impl ReceiptRegistry {
+    pub fn new() -> Self {
+        ReceiptRegistry {
+            decoders: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, chain_id: u64, kind: ReceiptKind, decoder: ReceiptDecoder) {
+        self.decoders.insert((chain_id, kind), decoder);
+    }
+
+    pub fn register_nft_chain(&mut self, chain_id: u64) {
+        self.register(chain_id, ReceiptKind::NftTransfer, decode_nft_transfer);
+        self.register(chain_id, ReceiptKind::NftFuture, decode_nft_future);
+        self.register(chain_id, ReceiptKind::NftSwapLock, decode_nft_swap_lock);
+        self.register(chain_id, ReceiptKind::NftBid, decode_nft_bid);
+        self.register(chain_id, ReceiptKind::Message, decode_message);
+    }
+
+    pub fn register_payments_chain(&mut self, chain_id: u64) {
+        self.register(chain_id, ReceiptKind::Payment, decode_payment);
+        self.register(chain_id, ReceiptKind::Message, decode_message);
+    }
+
+    pub fn register_chain(&mut self, chain_id: u64, kind: ChainKind) {
+        match kind {
+            ChainKind::Nft => self.register_nft_chain(chain_id),
+            ChainKind::Payments => self.register_payments_chain(chain_id),
+        }
+    }
+
+    pub fn decode(&self, receipt: &TransactionReceipt) -> Result<DecodedReceipt, Error> {
+        let (kind, _) = split_tag(&receipt.data)?;
+
+        match self.decoders.get(&(receipt.chain_id, kind)) {
+            Some(decoder) => decoder(&receipt.data),
+            None => Err(anyhow!(
+                "No schema for {:?} receipts on chain {}",
+                kind,
+                receipt.chain_id
+            )),
+        }
+    }
+}
+
+impl Default for ReceiptRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -21,6 +21,15 @@ impl MerkleStore {
         MerkleStore { db, cache }
     }
 
+    //Store over the same db with an empty cache of its own, so writes to it are
+    //not seen by this store.
+    pub fn scratch(&self) -> Self {
+        MerkleStore {
+            db: self.db.clone(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
     pub fn get<V: DeserializeOwned>(
         &self,
         serialized_key: &[u8],
@@ -1,5 +1,9 @@
-use crate::{state::MerkleStore, traits::Leaf, types::StateUpdate};
-use anyhow::{anyhow, Error};
+use crate::{
+    errors::{StateError, StateMachineError},
+    state::MerkleStore,
+    traits::{Leaf, StateReader},
+    types::StateUpdate,
+};
 use risc0_zkvm::sha::rust_crypto::{Digest as _, Sha256};
 use rocksdb::{Options, DB};
 use serde::{de::DeserializeOwned, Serialize};
@@ -65,15 +69,15 @@ impl<
     }
 
     //Revert to last committed state and clear cache.
-    pub fn revert(&mut self) -> Result<(), Error> {
+    pub fn revert(&mut self) -> Result<(), StateError> {
         self.merkle_store.clear_cache()?;
 
         let tree = match SparseMerkleTree::new_with_store(self.merkle_store.clone()) {
             Ok(i) => i,
             Err(e) => {
-                return Err(anyhow!(
-                    "Could not calculate root from last committed state. Critical error. {e}"
-                ))
+                return Err(StateError::CorruptState(format!(
+                    "Could not calculate root from last committed state. {e}"
+                )))
             }
         };
 
@@ -83,19 +87,35 @@ impl<
         Ok(())
     }
 
-    pub fn commit(&mut self) -> Result<(), Error> {
+    //State at the last committed root, with a cache of its own. Updates to the
+    //scratch state are never committed and do not touch this state.
+    pub fn scratch(&self) -> Result<Self, StateError> {
+        let merkle_store = self.merkle_store.scratch();
+
+        let tree = match SparseMerkleTree::new_with_store(merkle_store.clone()) {
+            Ok(i) => i,
+            Err(e) => {
+                return Err(StateError::CorruptState(format!(
+                    "Could not calculate root from last committed state. {e}"
+                )))
+            }
+        };
+
+        Ok(VmState { tree, merkle_store })
+    }
+
+    pub fn commit(&mut self) -> Result<(), StateError> {
         match self.merkle_store.commit() {
             Ok(()) => Ok(()),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(StateError::from(e)),
         }
     }
 
-    pub fn update_set(&mut self, set: Vec<V>) -> Result<StateUpdate<V>, Error> {
+    pub fn update_set(&mut self, set: Vec<V>) -> Result<StateUpdate<V>, StateError> {
         let pre_state_root = self.get_root();
         let pre_merkle_proof = self
             .tree
-            .merkle_proof(set.iter().map(|v| v.get_key()).collect())
-            .unwrap();
+            .merkle_proof(set.iter().map(|v| v.get_key()).collect())?;
 
         let pre_merkle_set = set
             .iter()
@@ -108,16 +128,14 @@ impl<
             .collect();
 
         self.tree
-            .update_all(set.clone().into_iter().map(|v| (v.get_key(), v)).collect())
-            .unwrap();
+            .update_all(set.clone().into_iter().map(|v| (v.get_key(), v)).collect())?;
 
         let post_state_root = self.get_root();
 
         let post_merkle_set = set.iter().map(|v| (v.get_key(), v.clone())).collect();
         let post_merkle_proof = self
             .tree
-            .merkle_proof(set.iter().map(|v| v.get_key()).collect())
-            .unwrap();
+            .merkle_proof(set.iter().map(|v| v.get_key()).collect())?;
 
         //println!("Pre: {:?} || Post {:?}", pre_merkle_proof, post_merkle_proof);
 
@@ -129,22 +147,22 @@ impl<
         })
     }
 
-    pub fn get(&self, key: &H256, committed: bool) -> Result<Option<V>, Error> {
+    pub fn get(&self, key: &H256, committed: bool) -> Result<Option<V>, StateError> {
         self.merkle_store
             .get(key.as_slice(), committed)
-            .map_err(|e| anyhow!({ e }))
+            .map_err(StateError::from)
     }
 
     //Gets from state even if not committed.
-    pub fn get_with_proof(&self, key: &H256) -> Result<(V, MerkleProof), Error> {
+    pub fn get_with_proof(&self, key: &H256) -> Result<(V, MerkleProof), StateError> {
         let value = match self.tree.get(key) {
             Ok(i) => i,
-            Err(_e) => return Err(anyhow!("Erroneous state.")),
+            Err(e) => return Err(StateError::CorruptState(e.to_string())),
         };
 
         let proof = match self.tree.merkle_proof(vec![*key]) {
             Ok(i) => i,
-            Err(_e) => return Err(anyhow!("Erroneous state.")),
+            Err(e) => return Err(StateError::CorruptState(e.to_string())),
         };
 
         Ok((value, proof))
@@ -154,3 +172,28 @@ impl<
         *self.tree.root()
     }
 }
+
+//Scratch states are used as snapshots, as they only read committed state.
+impl<
+        V: Value
+            + std::default::Default
+            + Clone
+            + Leaf<H256>
+            + PartialEq
+            + DeserializeOwned
+            + Serialize
+            + std::fmt::Debug,
+    > StateReader<V> for VmState<V>
+{
+    fn get_state_with_proof(&self, key: &H256) -> Result<(V, MerkleProof), StateMachineError> {
+        Ok(self.get_with_proof(key)?)
+    }
+
+    fn get_state(&self, key: &H256) -> Result<Option<V>, StateMachineError> {
+        Ok(self.get(key, true)?)
+    }
+
+    fn get_root(&self) -> Result<H256, StateMachineError> {
+        Ok(VmState::get_root(self))
+    }
+}
@@ -1,5 +1,5 @@
+use crate::errors::{StateMachineError, StateTransitionError};
 use crate::types::{AggregatedBatch, StateUpdate, TransactionReceipt};
-use anyhow::Error;
 use parity_scale_codec::{Decode, Encode};
 use serde::{de::DeserializeOwned, Serialize};
 use sparse_merkle_tree::MerkleProof;
@@ -9,29 +9,47 @@ pub trait Leaf<K> {
     fn get_key(&self) -> K;
 }
 
+//Read-only view of the committed state, which can be read while the state machine
+//executes and proves the next batch.
+pub trait StateReader<V> {
+    fn get_state_with_proof(&self, key: &H256) -> Result<(V, MerkleProof), StateMachineError>;
+    fn get_state(&self, key: &H256) -> Result<Option<V>, StateMachineError>;
+    fn get_root(&self) -> Result<H256, StateMachineError>;
+}
+
 pub trait StateMachine<V, T: Clone + DeserializeOwned + Serialize + Encode + Decode> {
-    fn new(root: H256) -> Self;
+    type Snapshot: StateReader<V> + Send + Sync;
+
+    fn new(root: H256, chain_id: u64) -> Self;
     fn execute_tx(
         &mut self,
         call: T,
         aggregated_proof: AggregatedBatch,
-    ) -> Result<(StateUpdate<V>, TransactionReceipt), Error>;
-    fn get_state_with_proof(&self, key: &H256) -> Result<(V, MerkleProof), Error>;
-    fn get_state(&self, key: &H256) -> Result<Option<V>, Error>;
-    fn revert(&mut self) -> Result<(), Error>;
-    fn commit(&mut self) -> Result<(), Error>;
-    fn get_root(&self) -> Result<H256, Error>;
+    ) -> Result<(StateUpdate<V>, TransactionReceipt), StateMachineError>;
+    //Executes the call against the last committed state without updating it.
+    fn simulate_tx(
+        &self,
+        call: T,
+        aggregated_proof: AggregatedBatch,
+    ) -> Result<(StateUpdate<V>, TransactionReceipt), StateMachineError>;
+    fn get_state_with_proof(&self, key: &H256) -> Result<(V, MerkleProof), StateMachineError>;
+    fn get_state(&self, key: &H256) -> Result<Option<V>, StateMachineError>;
+    //Snapshot of the last committed state, only valid until the next commit.
+    fn snapshot(&self) -> Result<Self::Snapshot, StateMachineError>;
+    fn revert(&mut self) -> Result<(), StateMachineError>;
+    fn commit(&mut self) -> Result<(), StateMachineError>;
+    fn get_root(&self) -> Result<H256, StateMachineError>;
 }
 
 pub trait StateTransition<V, T> {
     //Requiring the Value to be in a vector adds overhead when only one state is modified,
-    //but we do it for sake of simplicity.
+    //but we do it for sake of simplicity. Each value comes with the key it was read at.
     fn execute_tx(
         &self,
-        pre_state: Vec<V>,
+        pre_state: Vec<(H256, V)>,
         call_params: T,
         aggregated_proof: AggregatedBatch,
-    ) -> Result<(Vec<V>, TransactionReceipt), Error>;
+    ) -> Result<(Vec<V>, TransactionReceipt), StateTransitionError>;
 }
 
 pub trait TxHasher {
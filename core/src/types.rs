@@ -4,6 +4,10 @@ use crate::{
 };
 use risc0_zkvm::sha::rust_crypto::{Digest, Sha256};
 #[cfg(any(feature = "native", feature = "native-metal"))]
+use crate::receipts::DecodedReceipt;
+#[cfg(any(feature = "native", feature = "native-metal"))]
+use crate::errors::{StateError, StateMachineError, StateTransitionError};
+#[cfg(any(feature = "native", feature = "native-metal"))]
 use risc0_zkvm::Receipt;
 use serde::{Deserialize, Serialize};
 use sparse_merkle_tree::{
@@ -18,6 +22,10 @@ use serde_big_array::BigArray;
 
 #[cfg(any(feature = "native", feature = "native-metal"))]
 use http::status::StatusCode;
+#[cfg(any(feature = "native", feature = "native-metal"))]
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+#[cfg(any(feature = "native", feature = "native-metal"))]
+use std::io::{Read, Write};
 use std::convert::TryFrom;
 use anyhow::{anyhow, Error};
 
@@ -69,6 +77,12 @@ pub struct BatchHeader {
     //nexus verify receipts list and update its tree.
     pub receipts_root: H256,
     pub batch_number: u64,
+    //Aggregated batch the receipt proofs of this batch were checked against.
+    //Committed so nexus can reject batches proven against roots it never produced.
+    pub aggregated_proof_number: u64,
+    pub aggregated_receipts_root: H256,
+    //Hash of the whole aggregated batch, which also commits to its recent roots.
+    pub aggregated_batch_hash: H256,
 }
 
 impl BatchHeader {
@@ -79,8 +93,25 @@ impl BatchHeader {
             transactions_root: H256::zero(),
             receipts_root: H256::zero(),
             batch_number: 0,
+            aggregated_proof_number: 0,
+            aggregated_receipts_root: H256::zero(),
+            aggregated_batch_hash: H256::zero(),
         }
     }
+
+    pub fn hash(&self) -> H256 {
+        let mut hasher = ShaHasher::new();
+        hasher.0.update(self.pre_state_root.as_slice());
+        hasher.0.update(self.state_root.as_slice());
+        hasher.0.update(self.transactions_root.as_slice());
+        hasher.0.update(self.receipts_root.as_slice());
+        hasher.0.update(self.batch_number.to_be_bytes());
+        hasher.0.update(self.aggregated_proof_number.to_be_bytes());
+        hasher.0.update(self.aggregated_receipts_root.as_slice());
+        hasher.0.update(self.aggregated_batch_hash.as_slice());
+
+        hasher.finish()
+    }
 }
 
 #[cfg(any(feature = "native", feature = "native-metal"))]
@@ -96,6 +127,25 @@ pub struct BatchWithProof<T> {
     pub header: BatchHeader,
     pub transaction_with_receipts: Vec<TransactionWithReceipt<T>>,
     pub proof: Receipt,
+    pub da_tx_pointer: DaTxPointer,
+}
+
+//Batch as kept by an app node for its explorer endpoints.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BatchRecord {
+    pub header: BatchHeader,
+    pub tx_hashes: Vec<H256>,
+    pub receipts: Vec<TransactionReceipt>,
+    pub da_tx_pointer: DaTxPointer,
+    pub proof: Receipt,
+    //Proof number of the aggregated batch which included this batch, once aggregated.
+    #[serde(default)]
+    pub included_in: Option<u64>,
+    //Filled in from the receipt registry when the batch is served, in the order
+    //of the receipts.
+    #[serde(default)]
+    pub decoded: Vec<Option<DecodedReceipt>>,
 }
 
 #[cfg(any(feature = "native", feature = "native-metal"))]
@@ -103,6 +153,21 @@ pub struct BatchWithProof<T> {
 pub struct TransactionWithReceipt<T> {
     pub transaction: T,
     pub receipt: TransactionReceipt,
+    //Keys of the state leaves modified by the transaction, used to index history.
+    //Missing from records saved by earlier nodes.
+    #[serde(default)]
+    pub state_keys: Vec<H256>,
+}
+
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HistoryEntry {
+    pub batch_number: u64,
+    pub tx_hash: H256,
+    pub receipt: TransactionReceipt,
+    //Filled in from the receipt registry when history is served.
+    #[serde(default)]
+    pub decoded: Option<DecodedReceipt>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone, Default, Encode, Decode)]
@@ -221,7 +286,9 @@ impl TransactionReceipt {
 
 impl Value for TransactionReceipt {
     fn to_h256(&self) -> H256 {
-        if self.chain_id == 0 && self.data == vec![0] {
+        //Receipts are never emitted with chain ID 0, so the default receipt,
+        //which is what the tree returns for a missing key, hashes to zero.
+        if self.chain_id == 0 && (self.data.is_empty() || self.data == vec![0]) {
             return H256::zero();
         }
 
@@ -243,10 +310,187 @@ impl Leaf<H256> for TransactionReceipt {
     }
 }
 
+//Position of a receipt in the batches aggregated by nexus.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default, Encode, Decode)]
+pub struct ReceiptOrigin {
+    pub chain_id: u64,
+    pub batch_number: u64,
+    pub index: u64,
+}
+
+impl ReceiptOrigin {
+    pub fn key(&self) -> H256 {
+        let mut hasher = ShaHasher::new();
+        hasher.0.update(b"receipt-origin");
+        hasher.0.update(self.encode());
+
+        hasher.finish()
+    }
+}
+
+//Leaf of the nexus receipts tree. Every receipt is stored under the key of its
+//origin, which is unique even for identical receipts. The first occurrence of a
+//receipt is also stored under its hash, so future commitments can be proven
+//without knowing the origin, and the origin can be looked up from the hash.
+//Both leaves hash to the receipt hash.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
+pub struct ReceiptLeaf {
+    pub key: H256,
+    pub origin: ReceiptOrigin,
+    pub receipt: TransactionReceipt,
+}
+
+impl Value for ReceiptLeaf {
+    fn to_h256(&self) -> H256 {
+        self.receipt.to_h256()
+    }
+
+    fn zero() -> Self {
+        Default::default()
+    }
+}
+
+impl Leaf<H256> for ReceiptLeaf {
+    fn get_key(&self) -> H256 {
+        self.key
+    }
+}
+
+//Leaf of the nexus chain states tree, holding the state root of the last batch
+//of a chain included in an aggregated batch.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
+pub struct ChainStateLeaf {
+    pub chain_id: u64,
+    pub state_root: H256,
+}
+
+impl ChainStateLeaf {
+    pub fn key(chain_id: u64) -> H256 {
+        let mut hasher = ShaHasher::new();
+        hasher.0.update(b"chain-state");
+        hasher.0.update(chain_id.to_be_bytes());
+
+        hasher.finish()
+    }
+}
+
+impl Value for ChainStateLeaf {
+    fn to_h256(&self) -> H256 {
+        self.state_root
+    }
+
+    fn zero() -> Self {
+        Default::default()
+    }
+}
+
+impl Leaf<H256> for ChainStateLeaf {
+    fn get_key(&self) -> H256 {
+        Self::key(self.chain_id)
+    }
+}
+
+//Proof of the state root of a chain in an aggregated batch, served by nexus.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChainStateProof {
+    pub leaf: ChainStateLeaf,
+    pub proof: MerkleProof,
+    pub proof_number: u64,
+}
+
+//Number of aggregated receipts roots, before the latest one, which app chains
+//still accept inclusion proofs against.
+pub const RECENT_ROOTS_WINDOW: usize = 16;
+
+//Nexus rejects batches proven against an aggregated batch older than the recent
+//roots window, so a receipt of a batch executed against aggregated batch n is
+//aggregated by n + SETTLEMENT_LAG at the latest.
+pub const SETTLEMENT_LAG: u64 = RECENT_ROOTS_WINDOW as u64 + 1;
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
 pub struct AggregatedBatch {
     pub proof_number: u64,
     pub receipts_root: H256,
+    //Receipts roots of the aggregated batches before this one, newest first.
+    //Keeps a proof fetched just before a new aggregation valid.
+    #[serde(default)]
+    pub recent_roots: Vec<H256>,
+    //Root of the tree of chain ID to the state root of its last aggregated batch.
+    #[serde(default)]
+    pub chain_states_root: H256,
+}
+
+impl AggregatedBatch {
+    //Aggregated batch following this one, with this root moved into the window.
+    pub fn next(&self, receipts_root: H256, chain_states_root: H256) -> Self {
+        let mut recent_roots = vec![self.receipts_root];
+        recent_roots.extend(self.recent_roots.iter().cloned());
+        recent_roots.truncate(RECENT_ROOTS_WINDOW);
+
+        AggregatedBatch {
+            proof_number: self.proof_number + 1,
+            receipts_root,
+            recent_roots,
+            chain_states_root,
+        }
+    }
+
+    pub fn hash(&self) -> H256 {
+        let mut hasher = ShaHasher::new();
+        hasher.0.update(self.proof_number.to_be_bytes());
+        hasher.0.update(self.receipts_root.as_slice());
+        hasher.0.update(self.chain_states_root.as_slice());
+
+        for root in &self.recent_roots {
+            hasher.0.update(root.as_slice());
+        }
+
+        hasher.finish()
+    }
+
+    pub fn known_roots(&self) -> Vec<H256> {
+        let mut roots = vec![self.receipts_root];
+        roots.extend(self.recent_roots.iter().cloned());
+
+        roots
+    }
+
+    //Checks the leaves against any of the known roots. Only to be used for
+    //inclusion, as a leaf missing from an older root could have been added since.
+    pub fn verify_inclusion(&self, proof: &MerkleProof, leaves: Vec<(H256, H256)>) -> bool {
+        self.known_roots().iter().any(|root| {
+            matches!(
+                proof.clone().verify::<ShaHasher>(root, leaves.clone()),
+                Ok(true)
+            )
+        })
+    }
+
+    //Checks a value in the state of another chain in two hops: the chain state
+    //leaf against the chain states root, then the value against its state root.
+    pub fn verify_chain_state(
+        &self,
+        chain_state: &ChainStateProof,
+        key: H256,
+        value: H256,
+        state_proof: &MerkleProof,
+    ) -> bool {
+        let chain_state_valid = matches!(
+            chain_state.proof.clone().verify::<ShaHasher>(
+                &self.chain_states_root,
+                vec![(chain_state.leaf.get_key(), chain_state.leaf.to_h256())],
+            ),
+            Ok(true)
+        );
+
+        chain_state_valid
+            && matches!(
+                state_proof
+                    .clone()
+                    .verify::<ShaHasher>(&chain_state.leaf.state_root, vec![(key, value)]),
+                Ok(true)
+            )
+    }
 }
 
 #[cfg(any(feature = "native", feature = "native-metal"))]
@@ -254,13 +498,25 @@ pub struct AggregatedBatch {
 pub struct DaTxPointer {
   pub block_hash: [u8; 32],
   pub hash: [u8; 32], 
-  pub chain: AppChain,
+  pub chain_id: u64,
+}
+
+//Error body returned by the nexus HTTP API.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NexusErrorReply {
+    pub code: String,
+    pub message: String,
+    pub retry: bool,
 }
 
+//Body nexus returns for an accepted batch submission, with code `verified`, or
+//`buffered` if the batch is waiting for the batch before it.
+#[cfg(any(feature = "native", feature = "native-metal"))]
 #[derive(Debug, Deserialize, Serialize, Clone)]
-pub enum AppChain {
-    Nft,
-    Payments,
+pub struct SubmitBatchReply {
+    pub code: String,
+    pub message: String,
 }
 
 #[cfg(any(feature = "native", feature = "native-metal"))]
@@ -268,16 +524,174 @@ pub enum AppChain {
 pub struct SubmitProofParam {
     pub session_receipt: Vec<u8>,
     pub receipts: Vec<TransactionReceipt>,
-    pub chain: AppChain,
+    pub chain_id: u64,
     pub da_tx_pointer: DaTxPointer,
 }
 
+//Prefix of DA blobs carrying a batch proof, so nexus can tell them apart from
+//batches while following DA.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+pub const PROOF_ENVELOPE_MAGIC: &[u8; 8] = b"zknftprf";
+
+//Batch proof posted to DA, so nexus and third parties can verify a chain from
+//DA alone. The session receipt carries the journal and seal of the proof.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ProofEnvelope {
+    pub chain_id: u64,
+    pub session_receipt: Vec<u8>,
+    pub receipts: Vec<TransactionReceipt>,
+}
+
+#[cfg(any(feature = "native", feature = "native-metal"))]
+impl ProofEnvelope {
+    //Envelopes are compressed, as the seal is most of the blob.
+    pub fn to_blob(&self) -> Result<Vec<u8>, Error> {
+        let mut encoder = ZlibEncoder::new(PROOF_ENVELOPE_MAGIC.to_vec(), Compression::best());
+
+        encoder.write_all(&bincode::serialize(self)?)?;
+
+        Ok(encoder.finish()?)
+    }
+
+    //Returns None if the blob is not a proof envelope.
+    pub fn from_blob(blob: &[u8]) -> Result<Option<Self>, Error> {
+        if !blob.starts_with(PROOF_ENVELOPE_MAGIC) {
+            return Ok(None);
+        }
+
+        let mut serialized: Vec<u8> = vec![];
+
+        match ZlibDecoder::new(&blob[PROOF_ENVELOPE_MAGIC.len()..]).read_to_end(&mut serialized) {
+            Ok(_i) => (),
+            Err(e) => return Err(anyhow!("Invalid proof envelope. {:?}", e)),
+        }
+
+        match bincode::deserialize(&serialized) {
+            Ok(i) => Ok(Some(i)),
+            Err(e) => Err(anyhow!("Invalid proof envelope. {:?}", e)),
+        }
+    }
+}
+
+//Lifecycle of a transaction sent to an app node, in order.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum TxStatus {
+    //In the pool, waiting to be executed.
+    Pending,
+    Executed,
+    Proven,
+    SubmittedToDa { block_hash: H256 },
+    Verified,
+    Aggregated { proof_number: u64 },
+    Failed { reason: ErrorReply },
+    //Removed from the pool before its batch was verified, for example by a node restart.
+    Dropped,
+}
+
+#[cfg(any(feature = "native", feature = "native-metal"))]
+impl TxStatus {
+    //Statuses which need the transaction to still be in the pool.
+    pub fn in_progress(&self) -> bool {
+        matches!(
+            self,
+            TxStatus::Pending | TxStatus::Executed | TxStatus::Proven | TxStatus::SubmittedToDa { .. }
+        )
+    }
+}
+
+//Status of a transaction, persisted by the app node per transaction hash.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TxRecord {
+    pub tx_hash: H256,
+    pub status: TxStatus,
+    //Set once the transaction is executed in a batch.
+    pub batch_number: Option<u64>,
+    pub receipt: Option<TransactionReceipt>,
+    //Filled in from the receipt registry when the status is served.
+    #[serde(default)]
+    pub decoded: Option<DecodedReceipt>,
+}
+
+//Batch numbers an app node has reached at each level of finality.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ChainHeads {
+    //Last batch executed by the state machine, which may not be proven yet.
+    pub soft: u64,
+    //Last batch proven and accepted by nexus.
+    pub proven: u64,
+    //Last batch included in an aggregated batch, and the proof number of that
+    //aggregated batch. Receipts of a batch are only provable once aggregated.
+    pub aggregated: u64,
+    pub aggregated_proof_number: u64,
+}
+
+//Value of a key before and after a simulated transaction.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StateChange<V> {
+    pub key: H256,
+    pub before: V,
+    pub after: V,
+}
+
+//Outcome of executing a transaction against the committed state without
+//adding it to the pool. Either a receipt or an error is set.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SimulationResult<V> {
+    pub receipt: Option<TransactionReceipt>,
+    pub state_diff: Vec<StateChange<V>>,
+    pub error: Option<ErrorReply>,
+    //Only counted when requested, as it needs a run of the zkvm executor.
+    pub cycles: Option<u64>,
+}
+
+//Error body returned by app node RPCs.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ErrorReply {
+    pub code: String,
+    pub message: String,
+}
+
+#[cfg(any(feature = "native", feature = "native-metal"))]
+impl ErrorReply {
+    pub fn from_error(e: &Error) -> Self {
+        ErrorReply {
+            code: String::from(error_code(e).0),
+            message: e.to_string(),
+        }
+    }
+}
+
+//Rejected transactions are the caller's error, anything else is the node's.
+#[cfg(any(feature = "native", feature = "native-metal"))]
+fn error_code(e: &Error) -> (&'static str, StatusCode) {
+    if let Some(i) = e.downcast_ref::<StateTransitionError>() {
+        return (i.code(), StatusCode::BAD_REQUEST);
+    }
+
+    match e.downcast_ref::<StateMachineError>() {
+        Some(StateMachineError::Transition(i)) => (i.code(), StatusCode::BAD_REQUEST),
+        Some(StateMachineError::State(i)) => (i.code(), StatusCode::INTERNAL_SERVER_ERROR),
+        None => match e.downcast_ref::<StateError>() {
+            Some(i) => (i.code(), StatusCode::INTERNAL_SERVER_ERROR),
+            None => ("internal", StatusCode::INTERNAL_SERVER_ERROR),
+        },
+    }
+}
+
 #[cfg(any(feature = "native", feature = "native-metal"))]
 #[derive(Debug)]
 pub enum ClientReply<T: Serialize> {
     Ok(T), 
     Error(anyhow::Error), 
     BadRequest,
+    NotFound,
 }
 #[cfg(any(feature = "native", feature = "native-metal"))]
 impl <T: Send + Serialize> warp::Reply for ClientReply<T> {
@@ -288,8 +702,15 @@ impl <T: Send + Serialize> warp::Reply for ClientReply<T> {
                     StatusCode::OK,
             ).into_response(), 
             ClientReply::Error(e) => warp::reply::with_status(
-				warp::reply::json(&e.to_string()),
-				StatusCode::INTERNAL_SERVER_ERROR,
+                warp::reply::json(&ErrorReply::from_error(&e)),
+                error_code(&e).1,
+            ).into_response(),
+            ClientReply::NotFound => warp::reply::with_status(
+                warp::reply::json(&ErrorReply {
+                    code: String::from("not_found"),
+                    message: String::from("Not found."),
+                }),
+                StatusCode::NOT_FOUND,
             ).into_response(),
             ClientReply::BadRequest => warp::reply::with_status(
 				warp::reply::json(&"Bad Request".to_owned()),
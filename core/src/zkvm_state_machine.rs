@@ -1,4 +1,5 @@
 use crate::{
+    errors::StateTransitionError,
     traits::{Leaf, StateTransition, TxHasher},
     types::{AggregatedBatch, BatchHeader, ShaHasher, StateUpdate, TransactionReceipt},
 };
@@ -53,20 +54,21 @@ impl<
             }
         };
 
-        let pre_state: Vec<V> = state_update
-            .pre_state_with_proof
-            .0
-            .into_iter()
-            .map(|(_, v)| v)
-            .collect();
+        //Keys are proven along with the values, so the state transition can check
+        //it was given the leaves the transaction touches.
+        let pre_state: Vec<(H256, V)> = state_update.pre_state_with_proof.0;
 
-        let call_result: Result<(Vec<V>, TransactionReceipt), Error> =
+        let aggregated_proof_number = aggregated_proof.proof_number;
+        let aggregated_receipts_root = aggregated_proof.receipts_root;
+        let aggregated_batch_hash = aggregated_proof.hash();
+
+        let call_result: Result<(Vec<V>, TransactionReceipt), StateTransitionError> =
             self.stf
                 .execute_tx(pre_state, params.clone(), aggregated_proof);
 
         let (updated_set, receipt): (Vec<V>, TransactionReceipt) = match call_result {
             Ok(v) => v,
-            Err(e) => return Err(e),
+            Err(e) => return Err(Error::new(e)),
         };
 
         match state_update
@@ -106,6 +108,9 @@ impl<
             receipts_root: receipt.to_h256(),
             //Note: Batch can be removed from public parameters.
             batch_number,
+            aggregated_proof_number,
+            aggregated_receipts_root,
+            aggregated_batch_hash,
         })
     }
 }
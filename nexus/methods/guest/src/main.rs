@@ -0,0 +1,19 @@
+#![no_main]
+use nft_core::aggregation::{aggregate, AggregationInput};
+use risc0_zkvm::guest::env;
+
+risc0_zkvm::guest::entry!(main);
+
+pub fn main() {
+    let input: AggregationInput = env::read();
+
+    let journal = match aggregate(input) {
+        Ok(i) => i,
+        Err(e) => {
+            println!("{:?}", e);
+            panic!("Aggregation failed.")
+        }
+    };
+
+    env::commit(&journal);
+}
@@ -0,0 +1,705 @@
+use crate::nexus_app::NexusApp;
+use crate::types::AggregationProof;
+use anyhow::{anyhow, Error};
+use nexus_methods::{AGGREGATE_ELF, AGGREGATE_ID};
+use nft_core::{
+    aggregation::{
+        chain_state_leaves, receipt_leaves, AggregatedBatchRecord, AggregationInput,
+        AggregationJournal, ChainBatches,
+    },
+    db::NodeDB,
+    events::NexusEvent,
+    receipts::ReceiptWithProof,
+    traits::Leaf,
+    types::{
+        AggregatedBatch, ChainStateLeaf, ChainStateProof, ReceiptLeaf, ShaHasher, StateUpdate,
+        TransactionReceipt, RECENT_ROOTS_WINDOW,
+    },
+};
+use risc0_zkvm::{
+    serde::{from_slice, to_vec},
+    Executor, ExecutorEnv,
+};
+use sparse_merkle_tree::default_store::DefaultStore;
+use sparse_merkle_tree::traits::Value;
+use sparse_merkle_tree::{MerkleProof, SparseMerkleTree, H256};
+use std::collections::BTreeMap;
+
+//Aggregation of the verified batches into the receipts and chain states trees,
+//and proofs against them for aggregated batches in the recent roots window.
+
+type ReceiptsTree = SparseMerkleTree<ShaHasher, ReceiptLeaf, DefaultStore<ReceiptLeaf>>;
+
+//Receipts tree at the start of the recent roots window, rebuilt once and moved
+//forward as batches are aggregated. Trees at later roots are served by applying
+//the leaves added since, and undoing them afterwards.
+pub(crate) struct HistoricalTree {
+    proof_number: u64,
+    tree: ReceiptsTree,
+}
+
+impl HistoricalTree {
+    pub(crate) fn new() -> Self {
+        HistoricalTree {
+            proof_number: 0,
+            tree: SparseMerkleTree::default(),
+        }
+    }
+}
+
+impl NexusApp {
+    //Proving takes minutes, so the inputs are read under the locks, which are
+    //released while proving so batches can still be verified and receipts served.
+    pub(crate) async fn aggregate_proofs(&mut self) {
+        let (input, leaves_to_add, chain_states) = match self.aggregation_input() {
+            Ok(Some(i)) => i,
+            Ok(None) => return,
+            Err(e) => {
+                println!("Panic shutdown due to error, {:?}", e);
+
+                panic!("Aggregation input failed.");
+            }
+        };
+        let execute_only = self.execute_only;
+
+        //Batches are kept to be aggregated again in the next cycle if proving fails.
+        let (input, aggregation_proof) = match tokio::task::spawn_blocking(move || {
+            let proof = prove_aggregation(&input, execute_only);
+
+            (input, proof)
+        })
+        .await
+        {
+            Ok((input, Ok(i))) => (input, i),
+            Ok((_, Err(e))) => {
+                println!("Aggregation failed, will retry next cycle. {:?}", e);
+
+                return;
+            }
+            Err(e) => {
+                println!("Aggregation failed, will retry next cycle. {:?}", e);
+
+                return;
+            }
+        };
+
+        let mut app_state = self.app_state.lock().unwrap();
+        let mut tree_state = self.tree_state.lock().unwrap();
+        let db = self.db.lock().unwrap();
+
+        //Only this loop updates the receipts tree, so it is still at the root the
+        //update was made against.
+        match tree_state.update_set(leaves_to_add.clone()) {
+            Ok(i) if i.post_state_root == input.state_update.post_state_root => (),
+            Ok(_) => panic!("Receipts tree does not match the aggregated update."),
+            Err(e) => {
+                println!("Panic shutdown due to error, {:?}", e);
+
+                panic!("State update failed.");
+            }
+        }
+
+        println!(
+            "New proof aggregated. root is: {:?}",
+            &input.state_update.post_state_root
+        );
+
+        let last_aggregated_batch = aggregation_proof.journal.aggregated_batch.clone();
+        let record = AggregatedBatchRecord::new(last_aggregated_batch.clone(), &input.chains);
+
+        for chain in &input.chains {
+            let header = match chain.batches.last() {
+                Some(i) => i.header.clone(),
+                None => continue,
+            };
+
+            match app_state
+                .registry
+                .set_last_header(&db, chain.chain_id, header)
+            {
+                Ok(()) => (),
+                Err(e) => panic!("Could not start node. {:?}", e),
+            }
+        }
+
+        //TODO: Set this through a method.
+        app_state.last_aggregated_batch = last_aggregated_batch.clone();
+
+        match db.put::<AggregatedBatch>(b"last_aggregated_proof", &last_aggregated_batch) {
+            Ok(()) => (),
+            Err(e) => panic!("Could not start node. {:?}", e),
+        }
+        //Every aggregated batch and the leaves it added are kept, so proofs
+        //against older roots can be served.
+        match db.put::<AggregatedBatch>(
+            &aggregated_batch_key(last_aggregated_batch.proof_number),
+            &last_aggregated_batch,
+        ) {
+            Ok(()) => (),
+            Err(e) => panic!("Could not start node. {:?}", e),
+        }
+        match db.put::<Vec<ReceiptLeaf>>(
+            &aggregated_leaves_key(last_aggregated_batch.proof_number),
+            &leaves_to_add,
+        ) {
+            Ok(()) => (),
+            Err(e) => panic!("Could not start node. {:?}", e),
+        }
+        match db.put::<AggregationProof>(
+            &aggregation_proof_key(last_aggregated_batch.proof_number),
+            &aggregation_proof,
+        ) {
+            Ok(()) => (),
+            Err(e) => panic!("Could not start node. {:?}", e),
+        }
+        match db.put::<Vec<ChainStateLeaf>>(
+            &aggregated_chain_states_key(last_aggregated_batch.proof_number),
+            &chain_states,
+        ) {
+            Ok(()) => (),
+            Err(e) => panic!("Could not start node. {:?}", e),
+        }
+        //Published to DA after the cycle, so aggregation is not held up by DA.
+        match db.put::<AggregatedBatchRecord>(
+            &aggregated_record_key(last_aggregated_batch.proof_number),
+            &record,
+        ) {
+            Ok(()) => (),
+            Err(e) => panic!("Could not start node. {:?}", e),
+        }
+
+        tree_state.commit();
+
+        //Only the aggregated batches are removed from the pending queues, batches
+        //verified while proving are aggregated in the next cycle. Removed only once
+        //the aggregated batch is stored, so a restart before this point replays them.
+        for chain in &input.chains {
+            let batches = match app_state.verified_batches.get_mut(&chain.chain_id) {
+                Some(i) => i,
+                None => continue,
+            };
+
+            for _ in &chain.batches {
+                batches.delete_first();
+            }
+
+            match batches.persist(&db, chain.chain_id) {
+                Ok(()) => (),
+                Err(e) => panic!("Could not start node. {:?}", e),
+            }
+        }
+
+        self.publish_event(NexusEvent::AggregatedBatch(last_aggregated_batch.clone()));
+
+        for (chain_id, batch_number, _) in &record.batches {
+            self.publish_event(NexusEvent::BatchAggregated {
+                chain_id: *chain_id,
+                batch_number: *batch_number,
+                proof_number: last_aggregated_batch.proof_number,
+            });
+        }
+
+        //Hash leaves are new if their origin was added in this batch, otherwise
+        //they are rewritten unchanged.
+        for leaf in &leaves_to_add {
+            if leaf.key != leaf.origin.key()
+                && leaves_to_add.iter().any(|i| i.key == leaf.origin.key())
+            {
+                self.publish_event(NexusEvent::ReceiptIncluded {
+                    key: leaf.key,
+                    proof_number: last_aggregated_batch.proof_number,
+                });
+            }
+        }
+    }
+
+    //Reads the pending batches and builds the aggregation input, along with the
+    //receipt leaves and chain states to store once it is proven. The receipts
+    //tree update is made on a scratch state, so the tree is not touched until
+    //the proof is made. Returns None if there is nothing to aggregate.
+    fn aggregation_input(
+        &self,
+    ) -> Result<Option<(AggregationInput, Vec<ReceiptLeaf>, Vec<ChainStateLeaf>)>, Error> {
+        let mut app_state = self.app_state.lock().unwrap();
+        let tree_state = self.tree_state.lock().unwrap();
+        let db = self.db.lock().unwrap();
+
+        for event in app_state.drop_expired_batches(&db)? {
+            self.publish_event(event);
+        }
+
+        let chains: Vec<ChainBatches> = app_state
+            .registry
+            .chains()
+            .into_iter()
+            .map(|chain| ChainBatches {
+                chain_id: chain.config.chain_id,
+                last_header: chain.last_header,
+                batches: match app_state.verified_batches.get(&chain.config.chain_id) {
+                    Some(i) => i.batches().iter().map(|i| i.to_verified_batch()).collect(),
+                    None => vec![],
+                },
+            })
+            .collect();
+
+        println!(
+            "aggregating proofs: {:?}",
+            chains.iter().map(|i| i.batches.len()).sum::<usize>()
+        );
+
+        let leaves_to_add = receipt_leaves(&chains, |key| {
+            tree_state
+                .get_with_proof(key)
+                .map(|(leaf, _)| leaf)
+                .map_err(Error::from)
+        })?;
+
+        if leaves_to_add.is_empty() {
+            return Ok(None);
+        }
+
+        let mut scratch = tree_state.scratch()?;
+        let state_update = scratch.update_set(leaves_to_add.clone())?;
+        let (chain_state_update, chain_states) =
+            load_chain_states(&db, app_state.last_aggregated_batch.proof_number)
+                .and_then(|i| update_chain_states(i, chain_state_leaves(&chains)))?;
+
+        let input = AggregationInput {
+            previous: app_state.last_aggregated_batch.clone(),
+            chains,
+            state_update,
+            chain_state_update,
+        };
+
+        Ok(Some((input, leaves_to_add, chain_states)))
+    }
+
+    pub(crate) fn get_aggregation_proof(
+        &self,
+        proof_number: u64,
+    ) -> Result<Option<AggregationProof>, Error> {
+        let db = self.db.lock().unwrap();
+
+        Ok(db.get::<AggregationProof>(&aggregation_proof_key(proof_number))?)
+    }
+
+    pub(crate) fn get_aggregated_record(
+        &self,
+        proof_number: u64,
+    ) -> Result<Option<AggregatedBatchRecord>, Error> {
+        let db = self.db.lock().unwrap();
+
+        Ok(db.get::<AggregatedBatchRecord>(&aggregated_record_key(proof_number))?)
+    }
+
+    pub(crate) fn get_aggregated_batch(
+        &self,
+        proof_number: u64,
+    ) -> Result<Option<AggregatedBatch>, Error> {
+        //Genesis aggregated batch, which apps start from before anything is aggregated.
+        if proof_number == 0 {
+            return Ok(Some(AggregatedBatch::default()));
+        }
+
+        let db = self.db.lock().unwrap();
+
+        Ok(db.get::<AggregatedBatch>(&aggregated_batch_key(proof_number))?)
+    }
+
+    pub(crate) fn get_recent_aggregated_batches(&self) -> Result<Vec<AggregatedBatch>, Error> {
+        let last_proof_number = {
+            let app_state = self.app_state.lock().unwrap();
+
+            app_state.last_aggregated_batch.proof_number
+        };
+        let first_proof_number = last_proof_number.saturating_sub(RECENT_ROOTS_WINDOW as u64);
+        let mut batches: Vec<AggregatedBatch> = vec![];
+
+        for proof_number in first_proof_number..=last_proof_number {
+            if let Some(i) = self.get_aggregated_batch(proof_number)? {
+                batches.push(i);
+            }
+        }
+
+        Ok(batches)
+    }
+
+    //The receipts tree is updated in place, so the tree at an older aggregated
+    //batch is rebuilt in memory. Chains only accept proofs against recent roots,
+    //so only roots within the recent roots window are served.
+    fn get_historical_leaf_with_proof(
+        &self,
+        key: &H256,
+        proof_number: u64,
+        last_proof_number: u64,
+    ) -> Result<(ReceiptLeaf, MerkleProof), Error> {
+        let window_start = last_proof_number.saturating_sub(RECENT_ROOTS_WINDOW as u64);
+
+        if proof_number < window_start || proof_number > last_proof_number {
+            return Err(anyhow!(
+                "Aggregated batch {} is outside the recent roots window.",
+                proof_number
+            ));
+        }
+
+        let aggregated_batch = match self.get_aggregated_batch(proof_number)? {
+            Some(i) => i,
+            None => return Err(anyhow!("Unknown aggregated batch {}", proof_number)),
+        };
+        let db = self.db.lock().unwrap();
+        let mut historical_tree = self.historical_tree.lock().unwrap();
+        let historical = &mut *historical_tree;
+
+        //Only replays from the first aggregated batch once, then follows the window.
+        if historical.proof_number < window_start {
+            let mut previous = BTreeMap::new();
+
+            if let Err(e) = apply_aggregated_leaves(
+                &db,
+                &mut historical.tree,
+                historical.proof_number + 1..=window_start,
+                &mut previous,
+            ) {
+                *historical = HistoricalTree::new();
+
+                return Err(e);
+            }
+
+            historical.proof_number = window_start;
+        }
+
+        let mut previous = BTreeMap::new();
+        let result = apply_aggregated_leaves(
+            &db,
+            &mut historical.tree,
+            historical.proof_number + 1..=proof_number,
+            &mut previous,
+        )
+        .and_then(|()| {
+            if *historical.tree.root() != aggregated_batch.receipts_root {
+                return Err(anyhow!(
+                    "Rebuilt root does not match aggregated batch {}",
+                    proof_number
+                ));
+            }
+
+            let leaf = match historical.tree.get(key) {
+                Ok(i) => i,
+                Err(e) => return Err(anyhow!("{:?}", e)),
+            };
+
+            match historical.tree.merkle_proof(vec![*key]) {
+                Ok(i) => Ok((leaf, i)),
+                Err(e) => Err(anyhow!("{:?}", e)),
+            }
+        });
+
+        //Leaves are undone even on failure, so the tree stays at the window start.
+        if let Err(e) = historical.tree.update_all(previous.into_iter().collect()) {
+            println!(
+                "Could not undo historical receipts tree, rebuilding. {:?}",
+                e
+            );
+
+            *historical = HistoricalTree::new();
+        }
+
+        result
+    }
+
+    //Proof of the state root of a chain in the chain states tree at the given
+    //aggregated batch, or the latest one.
+    pub fn get_chain_state_with_proof(
+        &self,
+        chain_id: u64,
+        at: Option<u64>,
+    ) -> Result<ChainStateProof, Error> {
+        let aggregated_batch = {
+            let app_state = self.app_state.lock().unwrap();
+
+            app_state.last_aggregated_batch.clone()
+        };
+        let proof_number = at.unwrap_or(aggregated_batch.proof_number);
+        let aggregated_batch = match self.get_aggregated_batch(proof_number)? {
+            Some(i) => i,
+            None => return Err(anyhow!("Unknown aggregated batch {}", proof_number)),
+        };
+        let chain_states = {
+            let db = self.db.lock().unwrap();
+
+            load_chain_states(&db, proof_number)?
+        };
+        let tree = chain_states_tree(&chain_states)?;
+
+        if *tree.root() != aggregated_batch.chain_states_root {
+            return Err(anyhow!(
+                "Chain states do not match aggregated batch {}",
+                proof_number
+            ));
+        }
+
+        let key = ChainStateLeaf::key(chain_id);
+        let leaf = match tree.get(&key) {
+            Ok(i) => i,
+            Err(e) => return Err(anyhow!("{:?}", e)),
+        };
+        let proof = match tree.merkle_proof(vec![key]) {
+            Ok(i) => i,
+            Err(e) => return Err(anyhow!("{:?}", e)),
+        };
+
+        Ok(ChainStateProof {
+            leaf,
+            proof,
+            proof_number,
+        })
+    }
+
+    pub(crate) fn get_receipt_leaf_with_proof(
+        &self,
+        key: &H256,
+        at: Option<u64>,
+    ) -> Result<ReceiptWithProof, Error> {
+        //Lock order matches aggregation, so the proof number and tree are consistent.
+        let (leaf, proof, proof_number) = {
+            let app_state = self.app_state.lock().unwrap();
+            let last_proof_number = app_state.last_aggregated_batch.proof_number;
+
+            match at {
+                Some(i) if i != last_proof_number => {
+                    drop(app_state);
+                    let (leaf, proof) =
+                        self.get_historical_leaf_with_proof(key, i, last_proof_number)?;
+
+                    (leaf, proof, i)
+                }
+                _ => {
+                    let tree_state = self.tree_state.lock().unwrap();
+                    let (leaf, proof) = tree_state.get_with_proof(key)?;
+
+                    (leaf, proof, last_proof_number)
+                }
+            }
+        };
+
+        let decoded = if leaf.receipt == TransactionReceipt::zero() {
+            None
+        } else {
+            let app_state = self.app_state.lock().unwrap();
+
+            app_state.registry.decode_receipt(&leaf.receipt).ok()
+        };
+
+        Ok(ReceiptWithProof {
+            receipt: leaf.receipt,
+            origin: leaf.origin,
+            proof,
+            proof_number,
+            decoded,
+        })
+    }
+}
+
+//Runs the aggregation guest over the update of the receipts tree, proving it
+//unless nexus runs in execute only mode. Blocks until the proof is made, so
+//needs to be run on a blocking thread.
+fn prove_aggregation(
+    input: &AggregationInput,
+    execute_only: bool,
+) -> Result<AggregationProof, Error> {
+    let env = ExecutorEnv::builder().add_input(&to_vec(input)?).build()?;
+    let mut exec = Executor::from_elf(env, AGGREGATE_ELF)?;
+    let session = exec.run()?;
+
+    let (journal, receipt): (AggregationJournal, Option<Vec<u8>>) = if execute_only {
+        (from_slice(&session.journal)?, None)
+    } else {
+        let session_receipt = match session.prove() {
+            Ok(i) => i,
+            Err(e) => return Err(anyhow!("{:?}", e)),
+        };
+
+        session_receipt.verify(AGGREGATE_ID)?;
+
+        (
+            from_slice(&session_receipt.journal)?,
+            Some(bincode::serialize(&session_receipt)?),
+        )
+    };
+
+    let expected = input.previous.next(
+        input.state_update.post_state_root,
+        input.chain_state_update.post_state_root,
+    );
+
+    if journal.aggregated_batch != expected {
+        return Err(anyhow!(
+            "Aggregation journal does not match receipts tree update."
+        ));
+    }
+
+    Ok(AggregationProof { journal, receipt })
+}
+
+fn aggregated_batch_key(proof_number: u64) -> Vec<u8> {
+    [b"aggregated-".as_slice(), &proof_number.to_be_bytes()].concat()
+}
+
+//Applies the leaves added by each aggregated batch in the range, recording the
+//value each key had before the first update in `previous`.
+fn apply_aggregated_leaves(
+    db: &NodeDB,
+    tree: &mut ReceiptsTree,
+    proof_numbers: std::ops::RangeInclusive<u64>,
+    previous: &mut BTreeMap<H256, ReceiptLeaf>,
+) -> Result<(), Error> {
+    for n in proof_numbers {
+        let leaves = match db.get::<Vec<ReceiptLeaf>>(&aggregated_leaves_key(n))? {
+            Some(i) => i,
+            None => return Err(anyhow!("Leaves of aggregated batch {} not stored.", n)),
+        };
+
+        for leaf in &leaves {
+            if !previous.contains_key(&leaf.get_key()) {
+                let value = match tree.get(&leaf.get_key()) {
+                    Ok(i) => i,
+                    Err(e) => return Err(anyhow!("{:?}", e)),
+                };
+
+                previous.insert(leaf.get_key(), value);
+            }
+        }
+
+        if let Err(e) = tree.update_all(
+            leaves
+                .into_iter()
+                .map(|leaf| (leaf.get_key(), leaf))
+                .collect(),
+        ) {
+            return Err(anyhow!("{:?}", e));
+        }
+    }
+
+    Ok(())
+}
+
+fn aggregated_leaves_key(proof_number: u64) -> Vec<u8> {
+    [
+        b"aggregated-leaves-".as_slice(),
+        &proof_number.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn aggregation_proof_key(proof_number: u64) -> Vec<u8> {
+    [
+        b"aggregation-proof-".as_slice(),
+        &proof_number.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn aggregated_chain_states_key(proof_number: u64) -> Vec<u8> {
+    [
+        b"aggregated-chain-states-".as_slice(),
+        &proof_number.to_be_bytes(),
+    ]
+    .concat()
+}
+
+//Chain state leaves of every chain in the given aggregated batch. Batches from
+//before chain states were committed have none.
+fn load_chain_states(db: &NodeDB, proof_number: u64) -> Result<Vec<ChainStateLeaf>, Error> {
+    match db.get::<Vec<ChainStateLeaf>>(&aggregated_chain_states_key(proof_number))? {
+        Some(i) => Ok(i),
+        None => Ok(vec![]),
+    }
+}
+
+//The chain states tree has a leaf per chain, so it is kept in memory and built
+//from the leaves stored with each aggregated batch.
+fn chain_states_tree(
+    leaves: &[ChainStateLeaf],
+) -> Result<SparseMerkleTree<ShaHasher, ChainStateLeaf, DefaultStore<ChainStateLeaf>>, Error> {
+    let mut tree: SparseMerkleTree<ShaHasher, ChainStateLeaf, DefaultStore<ChainStateLeaf>> =
+        SparseMerkleTree::default();
+
+    if let Err(e) = tree.update_all(
+        leaves
+            .iter()
+            .map(|leaf| (leaf.get_key(), leaf.clone()))
+            .collect(),
+    ) {
+        return Err(anyhow!("{:?}", e));
+    }
+
+    Ok(tree)
+}
+
+//Applies new chain state leaves to the previous ones, returning the update with
+//proofs for the aggregation guest and every chain state leaf after it.
+fn update_chain_states(
+    previous: Vec<ChainStateLeaf>,
+    leaves: Vec<ChainStateLeaf>,
+) -> Result<(StateUpdate<ChainStateLeaf>, Vec<ChainStateLeaf>), Error> {
+    let mut tree = chain_states_tree(&previous)?;
+    let keys: Vec<H256> = leaves.iter().map(|leaf| leaf.get_key()).collect();
+    let pre_state_root = *tree.root();
+    let mut pre_set: Vec<(H256, ChainStateLeaf)> = vec![];
+
+    for key in &keys {
+        match tree.get(key) {
+            Ok(i) => pre_set.push((*key, i)),
+            Err(e) => return Err(anyhow!("{:?}", e)),
+        }
+    }
+
+    let pre_proof = match tree.merkle_proof(keys.clone()) {
+        Ok(i) => i,
+        Err(e) => return Err(anyhow!("{:?}", e)),
+    };
+
+    if let Err(e) = tree.update_all(
+        leaves
+            .iter()
+            .map(|leaf| (leaf.get_key(), leaf.clone()))
+            .collect(),
+    ) {
+        return Err(anyhow!("{:?}", e));
+    }
+
+    let post_proof = match tree.merkle_proof(keys) {
+        Ok(i) => i,
+        Err(e) => return Err(anyhow!("{:?}", e)),
+    };
+    let mut chain_states: Vec<ChainStateLeaf> = previous
+        .into_iter()
+        .filter(|i| !leaves.iter().any(|leaf| leaf.chain_id == i.chain_id))
+        .collect();
+
+    chain_states.extend(leaves.iter().cloned());
+
+    Ok((
+        StateUpdate {
+            pre_state_root,
+            post_state_root: *tree.root(),
+            pre_state_with_proof: (pre_set, pre_proof),
+            post_state_with_proof: (
+                leaves
+                    .into_iter()
+                    .map(|leaf| (leaf.get_key(), leaf))
+                    .collect(),
+                post_proof,
+            ),
+        },
+        chain_states,
+    ))
+}
+
+pub(crate) fn aggregated_record_key(proof_number: u64) -> Vec<u8> {
+    [
+        b"aggregated-record-".as_slice(),
+        &proof_number.to_be_bytes(),
+    ]
+    .concat()
+}
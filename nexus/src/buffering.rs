@@ -0,0 +1,555 @@
+use crate::registry::ChainRegistry;
+use anyhow::Error;
+use nft_core::{
+    aggregation::VerifiedBatch,
+    db::NodeDB,
+    events::NexusEvent,
+    types::{AggregatedBatch, BatchHeader, TransactionReceipt, RECENT_ROOTS_WINDOW},
+};
+use serde::{Deserialize, Serialize};
+use sparse_merkle_tree::H256;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//Verified batches waiting to be aggregated, and batches received ahead of the
+//batch before them, persisted per chain.
+
+//Batches submitted ahead of their predecessor are dropped if the gap is not
+//filled within this time.
+const BUFFER_TIMEOUT: Duration = Duration::from_secs(600);
+
+pub struct AppState {
+    pub last_aggregated_batch: AggregatedBatch,
+    pub registry: ChainRegistry,
+    //Verified batches not yet aggregated, persisted so they survive a restart.
+    pub verified_batches: HashMap<u64, OrderedBatches>,
+    //Verified batches received ahead of their predecessor.
+    pub buffered_batches: HashMap<u64, BatchBuffer>,
+}
+
+impl AppState {
+    pub fn new(last_aggregated_batch: AggregatedBatch, registry: ChainRegistry) -> Self {
+        AppState {
+            last_aggregated_batch,
+            registry,
+            verified_batches: HashMap::new(),
+            buffered_batches: HashMap::new(),
+        }
+    }
+
+    //Replays the pending batches of every registered chain from the db. Batches
+    //already covered by an aggregated batch are dropped.
+    pub fn load(
+        db: &NodeDB,
+        last_aggregated_batch: AggregatedBatch,
+        registry: ChainRegistry,
+    ) -> Result<Self, Error> {
+        let mut app_state = Self::new(last_aggregated_batch, registry);
+
+        for chain in app_state.registry.chains() {
+            let chain_id = chain.config.chain_id;
+            let mut batches = OrderedBatches::load(db, chain_id)?;
+
+            while let Some(i) = batches.first() {
+                if i.header.batch_number > chain.last_header.batch_number {
+                    break;
+                }
+
+                batches.delete_first();
+            }
+
+            if batches.proof_count() > 0 {
+                println!(
+                    "Replayed {} pending batches of chain {}",
+                    batches.proof_count(),
+                    chain_id
+                );
+            }
+
+            batches.persist(db, chain_id)?;
+
+            let last_batch_number = match batches.last() {
+                Some(i) => i.header.batch_number,
+                None => chain.last_header.batch_number,
+            };
+            let mut buffer = BatchBuffer::load(db, chain_id)?;
+
+            buffer.remove_until(last_batch_number);
+            buffer.persist(db, chain_id)?;
+
+            app_state.verified_batches.insert(chain_id, batches);
+            app_state.buffered_batches.insert(chain_id, buffer);
+        }
+
+        Ok(app_state)
+    }
+
+    //Adds a batch following the last verified batch of the chain, along with any
+    //buffered batches it unblocks. Returns an event for every batch added, and
+    //for every buffered batch dropped.
+    pub fn add_verified_batch(
+        &mut self,
+        db: &NodeDB,
+        chain_id: u64,
+        batch: BatchWithReceipts,
+    ) -> Result<Vec<NexusEvent>, Error> {
+        let mut pending = match self.verified_batches.get(&chain_id) {
+            Some(i) => i.clone(),
+            None => OrderedBatches::new(),
+        };
+        let mut buffer = match self.buffered_batches.get(&chain_id) {
+            Some(i) => i.clone(),
+            None => BatchBuffer::new(),
+        };
+        let mut events = vec![NexusEvent::BatchVerified {
+            chain_id,
+            batch_number: batch.header.batch_number,
+        }];
+
+        db.put(
+            &verified_header_key(chain_id, batch.header.batch_number),
+            &batch.header.hash(),
+        )?;
+        pending.add_batch(batch);
+
+        loop {
+            let last_header = match pending.last() {
+                Some(i) => i.header.clone(),
+                None => break,
+            };
+            let next = match buffer.take(last_header.batch_number + 1) {
+                Some(i) => i,
+                None => break,
+            };
+
+            let batch_number = next.batch.header.batch_number;
+
+            if next.batch.header.pre_state_root != last_header.state_root {
+                let reason = format!("It does not follow batch {}.", last_header.batch_number);
+
+                println!(
+                    "Dropped buffered batch {} of chain {}. {}",
+                    batch_number, chain_id, reason
+                );
+                events.push(NexusEvent::BatchDropped {
+                    chain_id,
+                    batch_number,
+                    reason,
+                });
+
+                continue;
+            }
+
+            if is_stale(&next.batch.header, self.last_aggregated_batch.proof_number) {
+                let reason = format!(
+                    "It was executed against stale aggregated batch {}.",
+                    next.batch.header.aggregated_proof_number
+                );
+
+                println!(
+                    "Dropped buffered batch {} of chain {}. {}",
+                    batch_number, chain_id, reason
+                );
+                events.push(NexusEvent::BatchDropped {
+                    chain_id,
+                    batch_number,
+                    reason,
+                });
+
+                continue;
+            }
+
+            events.push(NexusEvent::BatchVerified {
+                chain_id,
+                batch_number,
+            });
+            db.put(
+                &verified_header_key(chain_id, batch_number),
+                &next.batch.header.hash(),
+            )?;
+            pending.add_batch(next.batch);
+        }
+
+        //Batches are only accepted once persisted, as the app chain builds on top
+        //of them after a successful response.
+        pending.persist(db, chain_id)?;
+        buffer.persist(db, chain_id)?;
+
+        self.verified_batches.insert(chain_id, pending);
+        self.buffered_batches.insert(chain_id, buffer);
+
+        Ok(events)
+    }
+
+    pub fn buffer_batch(
+        &mut self,
+        db: &NodeDB,
+        chain_id: u64,
+        batch: BatchWithReceipts,
+    ) -> Result<(), Error> {
+        let mut buffer = match self.buffered_batches.get(&chain_id) {
+            Some(i) => i.clone(),
+            None => BatchBuffer::new(),
+        };
+
+        buffer.insert(batch);
+        buffer.persist(db, chain_id)?;
+
+        self.buffered_batches.insert(chain_id, buffer);
+
+        Ok(())
+    }
+
+    //Drops buffered batches whose gap was not filled in time, returning an event
+    //for each.
+    pub fn drop_expired_batches(&mut self, db: &NodeDB) -> Result<Vec<NexusEvent>, Error> {
+        let mut events = vec![];
+
+        for (chain_id, buffer) in self.buffered_batches.iter_mut() {
+            let expired = buffer.remove_expired(BUFFER_TIMEOUT);
+
+            if expired.is_empty() {
+                continue;
+            }
+
+            println!(
+                "Dropped buffered batches {:?} of chain {}, their previous batches were not submitted in time.",
+                expired, chain_id
+            );
+
+            buffer.persist(db, *chain_id)?;
+
+            for batch_number in expired {
+                events.push(NexusEvent::BatchDropped {
+                    chain_id: *chain_id,
+                    batch_number,
+                    reason: String::from("The batches before it were not submitted in time."),
+                });
+            }
+        }
+
+        Ok(events)
+    }
+
+    //Whether the header is a pending or aggregated batch of the chain.
+    pub fn is_verified(
+        &self,
+        db: &NodeDB,
+        chain_id: u64,
+        header: &BatchHeader,
+    ) -> Result<bool, Error> {
+        let pending = match self.verified_batches.get(&chain_id) {
+            Some(i) => i.batches().iter().any(|i| i.header.hash() == header.hash()),
+            None => false,
+        };
+        let aggregated = match self.registry.get(chain_id) {
+            Some(i) => i.last_header.hash() == header.hash(),
+            None => false,
+        };
+
+        if pending || aggregated {
+            return Ok(true);
+        }
+
+        //Older batches are found by the header hash kept when they were verified.
+        match db.get::<H256>(&verified_header_key(chain_id, header.batch_number))? {
+            Some(i) => Ok(i == header.hash()),
+            None => Ok(false),
+        }
+    }
+
+    pub fn get_last_verified_batch(&self, chain_id: u64) -> Option<BatchHeader> {
+        match self.verified_batches.get(&chain_id).and_then(|i| i.last()) {
+            Some(i) => Some(i.header.clone()),
+            None => self.registry.get(chain_id).map(|i| i.last_header.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BatchWithReceipts {
+    pub(crate) receipts: Vec<TransactionReceipt>,
+    pub(crate) header: BatchHeader,
+}
+
+impl BatchWithReceipts {
+    pub fn to_verified_batch(&self) -> VerifiedBatch {
+        VerifiedBatch {
+            header: self.header.clone(),
+            receipts: self.receipts.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BufferedBatch {
+    batch: BatchWithReceipts,
+    //Unix time in seconds the batch was received.
+    received_at: u64,
+}
+
+//Verified batches of a chain received out of order, keyed by batch number until
+//the batches before them arrive.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BatchBuffer(BTreeMap<u64, BufferedBatch>);
+
+impl BatchBuffer {
+    pub fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    pub fn load(db: &NodeDB, chain_id: u64) -> Result<Self, Error> {
+        match db.get::<BatchBuffer>(&buffered_batches_key(chain_id))? {
+            Some(i) => Ok(i),
+            None => Ok(Self::new()),
+        }
+    }
+
+    pub fn persist(&self, db: &NodeDB, chain_id: u64) -> Result<(), Error> {
+        Ok(db.put::<BatchBuffer>(&buffered_batches_key(chain_id), self)?)
+    }
+
+    //Replaces any batch already buffered with the same number, so retries are kept.
+    pub fn insert(&mut self, batch: BatchWithReceipts) {
+        self.0.insert(
+            batch.header.batch_number,
+            BufferedBatch {
+                batch,
+                received_at: unix_time(),
+            },
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn take(&mut self, batch_number: u64) -> Option<BufferedBatch> {
+        self.0.remove(&batch_number)
+    }
+
+    //Removes batches up to and including the given batch number.
+    pub fn remove_until(&mut self, batch_number: u64) {
+        self.0.retain(|i, _| *i > batch_number);
+    }
+
+    //Removes batches buffered for longer than the timeout, returning their numbers.
+    pub fn remove_expired(&mut self, timeout: Duration) -> Vec<u64> {
+        let now = unix_time();
+        let expired: Vec<u64> = self
+            .0
+            .iter()
+            .filter(|(_, i)| now.saturating_sub(i.received_at) > timeout.as_secs())
+            .map(|(i, _)| *i)
+            .collect();
+
+        for batch_number in &expired {
+            self.0.remove(batch_number);
+        }
+
+        expired
+    }
+}
+
+//Queue of verified batches of a chain, waiting to be aggregated.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct OrderedBatches(Vec<BatchWithReceipts>);
+
+impl OrderedBatches {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+
+    pub fn load(db: &NodeDB, chain_id: u64) -> Result<Self, Error> {
+        match db.get::<OrderedBatches>(&pending_batches_key(chain_id))? {
+            Some(i) => Ok(i),
+            None => Ok(Self::new()),
+        }
+    }
+
+    pub fn persist(&self, db: &NodeDB, chain_id: u64) -> Result<(), Error> {
+        Ok(db.put::<OrderedBatches>(&pending_batches_key(chain_id), self)?)
+    }
+
+    pub fn batches(&self) -> &Vec<BatchWithReceipts> {
+        &self.0
+    }
+
+    pub fn last(&self) -> Option<&BatchWithReceipts> {
+        self.0.last()
+    }
+
+    pub fn first(&self) -> Option<&BatchWithReceipts> {
+        self.0.first()
+    }
+
+    pub fn add_batch(&mut self, batch: BatchWithReceipts) {
+        self.0.push(batch);
+    }
+
+    pub fn delete_first(&mut self) {
+        if !self.0.is_empty() {
+            self.0.remove(0);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if !self.0.is_empty() {
+            self.0.clear();
+        }
+    }
+
+    pub fn proof_count(&self) -> usize {
+        self.0.len()
+    }
+}
+
+//Batches executed against an aggregated batch older than the recent roots window
+//are rejected. This bounds how long after its deadline a receipt committed by a
+//future can still be aggregated, see SETTLEMENT_LAG.
+pub(crate) fn is_stale(header: &BatchHeader, last_proof_number: u64) -> bool {
+    header.aggregated_proof_number + (RECENT_ROOTS_WINDOW as u64) < last_proof_number
+}
+
+fn pending_batches_key(chain_id: u64) -> Vec<u8> {
+    [b"pending-".as_slice(), &chain_id.to_be_bytes()].concat()
+}
+
+fn buffered_batches_key(chain_id: u64) -> Vec<u8> {
+    [b"buffered-".as_slice(), &chain_id.to_be_bytes()].concat()
+}
+
+fn verified_header_key(chain_id: u64, batch_number: u64) -> Vec<u8> {
+    [
+        b"verified-".as_slice(),
+        &chain_id.to_be_bytes(),
+        &batch_number.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn unix_time() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(i) => i.as_secs(),
+        Err(_e) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAIN_ID: u64 = 7000;
+
+    fn batch(batch_number: u64) -> BatchWithReceipts {
+        BatchWithReceipts {
+            receipts: vec![],
+            header: BatchHeader {
+                pre_state_root: H256::from([batch_number as u8; 32]),
+                state_root: H256::from([batch_number as u8 + 1; 32]),
+                batch_number,
+                ..BatchHeader::default()
+            },
+        }
+    }
+
+    //Fresh db in the temp directory, unique to the test and process.
+    fn test_db(name: &str) -> NodeDB {
+        let path = std::env::temp_dir().join(format!("nexus_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+
+        NodeDB::from_path(path.to_string_lossy().to_string())
+    }
+
+    fn batch_numbers(app_state: &AppState) -> Vec<u64> {
+        app_state.verified_batches[&CHAIN_ID]
+            .batches()
+            .iter()
+            .map(|i| i.header.batch_number)
+            .collect()
+    }
+
+    #[test]
+    fn buffer_removes_batches_until_number() {
+        let mut buffer = BatchBuffer::new();
+
+        buffer.insert(batch(4));
+        buffer.insert(batch(2));
+        buffer.insert(batch(3));
+        buffer.remove_until(3);
+
+        assert!(buffer.take(2).is_none());
+        assert!(buffer.take(3).is_none());
+        assert!(buffer.take(4).is_some());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn buffer_removes_expired_batches() {
+        let mut buffer = BatchBuffer::new();
+
+        buffer.insert(batch(2));
+        buffer.insert(batch(3));
+        buffer.0.get_mut(&2).unwrap().received_at = unix_time() - BUFFER_TIMEOUT.as_secs() - 1;
+
+        assert_eq!(buffer.remove_expired(BUFFER_TIMEOUT), vec![2]);
+        assert!(buffer.remove_expired(BUFFER_TIMEOUT).is_empty());
+        assert!(buffer.take(3).is_some());
+    }
+
+    #[test]
+    fn buffered_batches_are_added_in_order() {
+        let db = test_db("buffer_order");
+        let mut app_state = AppState::new(
+            AggregatedBatch::default(),
+            ChainRegistry::load(&db).unwrap(),
+        );
+
+        app_state.buffer_batch(&db, CHAIN_ID, batch(3)).unwrap();
+        app_state.buffer_batch(&db, CHAIN_ID, batch(2)).unwrap();
+
+        let events = app_state
+            .add_verified_batch(&db, CHAIN_ID, batch(1))
+            .unwrap();
+        let verified: Vec<NexusEvent> = (1..4)
+            .map(|batch_number| NexusEvent::BatchVerified {
+                chain_id: CHAIN_ID,
+                batch_number,
+            })
+            .collect();
+
+        assert_eq!(events, verified);
+        assert_eq!(batch_numbers(&app_state), vec![1, 2, 3]);
+        assert!(app_state.buffered_batches[&CHAIN_ID].is_empty());
+    }
+
+    #[test]
+    fn buffered_batch_not_following_is_dropped() {
+        let db = test_db("buffer_drop");
+        let mut app_state = AppState::new(
+            AggregatedBatch::default(),
+            ChainRegistry::load(&db).unwrap(),
+        );
+        let mut forked = batch(2);
+        forked.header.pre_state_root = H256::zero();
+
+        app_state.buffer_batch(&db, CHAIN_ID, forked).unwrap();
+        app_state.buffer_batch(&db, CHAIN_ID, batch(3)).unwrap();
+
+        let events = app_state
+            .add_verified_batch(&db, CHAIN_ID, batch(1))
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            events[1],
+            NexusEvent::BatchDropped {
+                batch_number: 2,
+                ..
+            }
+        ));
+        //Batch 3 waits for a valid batch 2.
+        assert_eq!(batch_numbers(&app_state), vec![1]);
+        assert!(!app_state.buffered_batches[&CHAIN_ID].is_empty());
+    }
+}
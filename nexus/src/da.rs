@@ -0,0 +1,327 @@
+use crate::aggregation::aggregated_record_key;
+use crate::errors::NexusError;
+use crate::nexus_app::NexusApp;
+use crate::types::{BatchAcceptance, ChainConfig, DaTxPointer};
+use anyhow::{anyhow, Error};
+use avail::avail::AvailBlobTransaction;
+use avail::service::{BlockNotFound, DaProvider, DaServiceConfig};
+use nft_core::{
+    aggregation::AggregatedBatchRecord,
+    types::{BatchHeader, ProofEnvelope},
+};
+use primitive_types::H256 as SubstrateH256;
+use risc0_zkvm::{serde::from_slice, Receipt};
+use std::time::Duration;
+
+//Connections to DA: fetching submitted batches, following the app IDs of the
+//registered chains, and publishing aggregated batches on nexus' own app ID.
+
+const DA_SYNC_INTERVAL: Duration = Duration::from_secs(20);
+
+impl NexusApp {
+    //Connects to DA for a registered chain.
+    pub async fn add_da_service(&self, config: &ChainConfig) {
+        let da_service = DaProvider::new(DaServiceConfig {
+            light_client_url: config.light_client_url.clone(),
+            node_client_url: self.node_client_url.clone(),
+            seed: self.seed.clone(),
+            app_id: config.da_app_id,
+        })
+        .await;
+
+        let mut da_services = self.da_services.lock().unwrap();
+
+        da_services.insert(config.chain_id, da_service);
+    }
+
+    //Connects to DA on nexus' own app ID, to publish aggregated batches.
+    pub async fn add_da_publisher(&self, app_id: u32, light_client_url: String) {
+        let da_service = DaProvider::new(DaServiceConfig {
+            light_client_url,
+            node_client_url: self.node_client_url.clone(),
+            seed: self.seed.clone(),
+            app_id,
+        })
+        .await;
+
+        let mut da_publisher = self.da_publisher.lock().unwrap();
+
+        *da_publisher = Some(da_service);
+    }
+
+    //Publishing starts after the current aggregated batch when first enabled, as
+    //batches aggregated by older versions have no records.
+    pub(crate) fn init_last_published(&self) -> Result<(), Error> {
+        if self.da_publisher.lock().unwrap().is_none() {
+            return Ok(());
+        }
+
+        let app_state = self.app_state.lock().unwrap();
+        let db = self.db.lock().unwrap();
+
+        if db.get::<u64>(b"last_published_proof")?.is_some() {
+            return Ok(());
+        }
+
+        let last_aggregated = app_state.last_aggregated_batch.proof_number;
+
+        db.put::<u64>(b"last_published_proof", &last_aggregated)?;
+
+        println!(
+            "Publishing aggregated batches to DA after aggregated batch {}.",
+            last_aggregated
+        );
+
+        Ok(())
+    }
+
+    //Posts the records of aggregated batches not yet published to DA, in order.
+    pub(crate) async fn publish_aggregated_batches(&self) -> Result<(), Error> {
+        let da_publisher = match self.da_publisher.lock().unwrap().clone() {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let (last_published, last_aggregated) = {
+            let app_state = self.app_state.lock().unwrap();
+            let db = self.db.lock().unwrap();
+            let last_published = match db.get::<u64>(b"last_published_proof")? {
+                Some(i) => i,
+                None => return Err(anyhow!("Last published aggregated batch not set.")),
+            };
+
+            (last_published, app_state.last_aggregated_batch.proof_number)
+        };
+
+        for proof_number in (last_published + 1)..=last_aggregated {
+            let record = {
+                let db = self.db.lock().unwrap();
+
+                match db.get::<AggregatedBatchRecord>(&aggregated_record_key(proof_number))? {
+                    Some(i) => i,
+                    None => {
+                        return Err(anyhow!(
+                            "Record of aggregated batch {} not found.",
+                            proof_number
+                        ))
+                    }
+                }
+            };
+
+            da_publisher.send_transaction(&record.to_blob()?).await?;
+
+            println!("Published aggregated batch {} to DA.", proof_number);
+
+            let db = self.db.lock().unwrap();
+
+            db.put::<u64>(b"last_published_proof", &proof_number)?;
+        }
+
+        Ok(())
+    }
+
+    //Follows the DA app ID of every registered chain with a DA sender from the
+    //start height, and verifies the proofs posted to it, so nexus catches up on
+    //batches it was not sent while down. Chains without a DA sender are not
+    //followed, as anyone can post to their app ID.
+    pub async fn follow_da(&self) {
+        for chain in self.get_chains() {
+            if chain.config.da_sender.is_none() {
+                println!(
+                    "Chain {} has no DA sender, its proofs on DA are not followed.",
+                    chain.config.chain_id
+                );
+            }
+        }
+
+        loop {
+            for chain in self.get_chains() {
+                if chain.config.da_sender.is_none() {
+                    continue;
+                }
+
+                match self.sync_chain(chain.config.chain_id).await {
+                    Ok(()) => (),
+                    Err(e) => {
+                        println!("DA sync of chain {} failed. {:?}", chain.config.chain_id, e)
+                    }
+                }
+            }
+
+            tokio::time::sleep(DA_SYNC_INTERVAL).await;
+        }
+    }
+
+    async fn sync_chain(&self, chain_id: u64) -> Result<(), Error> {
+        let da_service = {
+            let da_services = self.da_services.lock().unwrap();
+
+            match da_services.get(&chain_id) {
+                Some(i) => i.clone(),
+                None => return Err(anyhow!("Chain {} not registered.", chain_id)),
+            }
+        };
+        let da_sender = match self.app_state.lock().unwrap().registry.get(chain_id) {
+            Some(i) => i.config.da_sender,
+            None => return Err(anyhow!("Chain {} not registered.", chain_id)),
+        };
+        let mut height = self.get_da_sync_height(chain_id)?;
+
+        loop {
+            //Blocks are synced until one is not produced yet. Other failures are
+            //returned, and the block is synced again in the next round.
+            let block = match da_service.get_block_at(height).await {
+                Ok(i) => i,
+                Err(e) if e.downcast_ref::<BlockNotFound>().is_some() => return Ok(()),
+                Err(e) => return Err(e),
+            };
+
+            for tx in &block.transactions {
+                //Only blobs of the chain's sender are followed.
+                if Some(tx.sender().0) != da_sender {
+                    continue;
+                }
+
+                let envelope = match ProofEnvelope::from_blob(tx.blob()) {
+                    Ok(Some(i)) => i,
+                    //Batches are posted before their proofs, and kept to check the
+                    //proofs against.
+                    Ok(None) => {
+                        if let Err(e) = self.record_da_batch(chain_id, tx.blob()) {
+                            println!("Skipping blob at DA height {}. {:?}", height, e);
+                        }
+
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("Skipping blob at DA height {}. {:?}", height, e);
+
+                        continue;
+                    }
+                };
+
+                if envelope.chain_id != chain_id {
+                    println!(
+                        "Skipping proof of chain {} posted to app ID of chain {}.",
+                        envelope.chain_id, chain_id
+                    );
+
+                    continue;
+                }
+
+                let da_header = match self.get_da_batch(chain_id, &envelope) {
+                    Ok(Some(i)) => i,
+                    Ok(None) => {
+                        println!(
+                            "Skipping proof at DA height {}, its batch was not found on DA.",
+                            height
+                        );
+
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("Skipping proof at DA height {}. {:?}", height, e);
+
+                        continue;
+                    }
+                };
+
+                match self.verify_proof(envelope, tx.sender(), &da_header) {
+                    Ok(BatchAcceptance::Verified) => println!(
+                        "Verified batch of chain {} from DA height {}.",
+                        chain_id, height
+                    ),
+                    Ok(BatchAcceptance::Buffered) => println!(
+                        "Buffered batch of chain {} from DA height {}.",
+                        chain_id, height
+                    ),
+                    Err(e) => println!("Proof at DA height {} not accepted. {:?}", height, e),
+                }
+            }
+
+            height += 1;
+
+            let db = self.db.lock().unwrap();
+
+            db.put::<u64>(&da_sync_height_key(chain_id), &height)?;
+        }
+    }
+
+    fn record_da_batch(&self, chain_id: u64, blob: &[u8]) -> Result<(), Error> {
+        //Transactions are specific to each chain, so only the header is decoded.
+        let header: BatchHeader = bincode::deserialize(blob)?;
+        let db = self.db.lock().unwrap();
+
+        Ok(db.put(&da_batch_key(chain_id, header.batch_number), &header)?)
+    }
+
+    //Header of the batch found on DA, that the proof in the envelope claims to be of.
+    fn get_da_batch(
+        &self,
+        chain_id: u64,
+        envelope: &ProofEnvelope,
+    ) -> Result<Option<BatchHeader>, Error> {
+        let session_receipt: Receipt = bincode::deserialize(&envelope.session_receipt)?;
+        let header: BatchHeader = from_slice(&session_receipt.journal)?;
+        let db = self.db.lock().unwrap();
+
+        Ok(db.get::<BatchHeader>(&da_batch_key(chain_id, header.batch_number))?)
+    }
+
+    //Next DA height to be synced for a chain.
+    fn get_da_sync_height(&self, chain_id: u64) -> Result<u64, Error> {
+        let db = self.db.lock().unwrap();
+
+        match db.get::<u64>(&da_sync_height_key(chain_id))? {
+            Some(i) => Ok(i),
+            None => Ok(self.da_start_height),
+        }
+    }
+
+    pub(crate) async fn get_da_tx(
+        &self,
+        pointer: DaTxPointer,
+    ) -> Result<AvailBlobTransaction, NexusError> {
+        let da_service = {
+            let da_services = self.da_services.lock().unwrap();
+
+            match da_services.get(&pointer.chain_id) {
+                Some(i) => i.clone(),
+                None => return Err(NexusError::ChainNotRegistered(pointer.chain_id)),
+            }
+        };
+
+        println!("Chain: {}", pointer.chain_id);
+
+        let block = match da_service.get_block_with_hash(pointer.block_hash).await {
+            Ok(i) => i,
+            Err(e) => {
+                println!("Error getting block: {:?}", e);
+
+                return Err(NexusError::DaUnavailable(e.to_string()));
+            }
+        };
+        let hash = SubstrateH256::from(pointer.hash);
+        println!("Da hash: {:?}, {:?}", hash, &block);
+
+        match block.find_tx(&hash) {
+            Some(i) => Ok(i),
+            None => {
+                println!("Could not find tx");
+                Err(NexusError::DaTxNotFound)
+            }
+        }
+    }
+}
+
+fn da_batch_key(chain_id: u64, batch_number: u64) -> Vec<u8> {
+    [
+        b"da-batch-".as_slice(),
+        &chain_id.to_be_bytes(),
+        &batch_number.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn da_sync_height_key(chain_id: u64) -> Vec<u8> {
+    [b"da-sync-".as_slice(), &chain_id.to_be_bytes()].concat()
+}
@@ -0,0 +1,291 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use nft_core::types::NexusErrorReply;
+use thiserror::Error;
+
+//Errors returned by the nexus API. Each maps to a status code and a stable
+//error code, and whether the request can succeed if retried later.
+#[derive(Debug, Error)]
+pub enum NexusError {
+    #[error("Chain {0} not registered.")]
+    ChainNotRegistered(u64),
+    #[error("DA pointer is for a different chain.")]
+    InvalidDaPointer,
+    #[error("Could not fetch DA block. {0}")]
+    DaUnavailable(String),
+    #[error("DA transaction not found in block.")]
+    DaTxNotFound,
+    #[error("Batch not posted to DA by chain {0}.")]
+    InvalidSender(u64),
+    #[error("DA batch deserialization failed. {0}")]
+    InvalidDaBatch(String),
+    #[error("Proof is not of batch {0} posted to DA.")]
+    DaBatchMismatch(u64),
+    #[error("Proof deserialization failed. {0}")]
+    InvalidProofEncoding(String),
+    #[error("Unable to verify proof.")]
+    InvalidProof,
+    #[error("Unknown aggregated batch {0}.")]
+    UnknownAggregatedBatch(u64),
+    #[error("Aggregated batch {0} does not match nexus history.")]
+    AggregatedBatchMismatch(u64),
+    //Batch has to be executed again against a recent aggregated batch.
+    #[error("Aggregated batch {0} is older than the recent roots window.")]
+    StaleAggregatedBatch(u64),
+    #[error("Batch has no receipts.")]
+    MissingReceipts,
+    #[error("Receipts do not match the receipts root of the batch.")]
+    ReceiptsRootMismatch,
+    #[error("Receipts are not from chain {0}.")]
+    ReceiptChainMismatch(u64),
+    #[error("Pre state root does not match the last batch of chain {0}.")]
+    PreStateRootMismatch(u64),
+    #[error("A different batch {0} is already verified.")]
+    AlreadyVerified(u64),
+    #[error("Chain {0} is registered with a different image ID.")]
+    ImageIdChanged(u64),
+    #[error("Chain {0} has batches waiting to be aggregated.")]
+    PendingBatches(u64),
+    #[error("Missing or invalid admin token.")]
+    Unauthorized,
+    #[error("Not found.")]
+    NotFound,
+    #[error("Internal error. {0}")]
+    Internal(String),
+}
+
+impl NexusError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            NexusError::ChainNotRegistered(_) => "chain_not_registered",
+            NexusError::InvalidDaPointer => "invalid_da_pointer",
+            NexusError::DaUnavailable(_) => "da_unavailable",
+            NexusError::DaTxNotFound => "da_tx_not_found",
+            NexusError::InvalidSender(_) => "invalid_sender",
+            NexusError::InvalidDaBatch(_) => "invalid_da_batch",
+            NexusError::DaBatchMismatch(_) => "da_batch_mismatch",
+            NexusError::InvalidProofEncoding(_) => "invalid_proof_encoding",
+            NexusError::InvalidProof => "invalid_proof",
+            NexusError::UnknownAggregatedBatch(_) => "unknown_aggregated_batch",
+            NexusError::AggregatedBatchMismatch(_) => "aggregated_batch_mismatch",
+            NexusError::StaleAggregatedBatch(_) => "stale_aggregated_batch",
+            NexusError::MissingReceipts => "missing_receipts",
+            NexusError::ReceiptsRootMismatch => "receipts_root_mismatch",
+            NexusError::ReceiptChainMismatch(_) => "receipt_chain_mismatch",
+            NexusError::PreStateRootMismatch(_) => "pre_state_root_mismatch",
+            NexusError::AlreadyVerified(_) => "already_verified",
+            NexusError::ImageIdChanged(_) => "image_id_changed",
+            NexusError::PendingBatches(_) => "pending_batches",
+            NexusError::Unauthorized => "unauthorized",
+            NexusError::NotFound => "not_found",
+            NexusError::Internal(_) => "internal",
+        }
+    }
+
+    //Errors caused by nexus or DA rather than the request. A DA transaction can
+    //be missing while the light client catches up with the block.
+    pub fn retry(&self) -> bool {
+        matches!(
+            self,
+            NexusError::DaUnavailable(_) | NexusError::DaTxNotFound | NexusError::Internal(_)
+        )
+    }
+}
+
+impl From<anyhow::Error> for NexusError {
+    fn from(e: anyhow::Error) -> Self {
+        NexusError::Internal(e.to_string())
+    }
+}
+
+impl ResponseError for NexusError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            NexusError::ChainNotRegistered(_) | NexusError::NotFound => StatusCode::NOT_FOUND,
+            NexusError::InvalidDaPointer
+            | NexusError::DaTxNotFound
+            | NexusError::InvalidDaBatch(_)
+            | NexusError::InvalidProofEncoding(_)
+            | NexusError::MissingReceipts => StatusCode::BAD_REQUEST,
+            NexusError::InvalidSender(_) => StatusCode::FORBIDDEN,
+            NexusError::InvalidProof
+            | NexusError::DaBatchMismatch(_)
+            | NexusError::UnknownAggregatedBatch(_)
+            | NexusError::AggregatedBatchMismatch(_)
+            | NexusError::StaleAggregatedBatch(_)
+            | NexusError::ReceiptsRootMismatch
+            | NexusError::ReceiptChainMismatch(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            NexusError::PreStateRootMismatch(_)
+            | NexusError::AlreadyVerified(_)
+            | NexusError::ImageIdChanged(_)
+            | NexusError::PendingBatches(_) => StatusCode::CONFLICT,
+            NexusError::Unauthorized => StatusCode::UNAUTHORIZED,
+            NexusError::DaUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            NexusError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(NexusErrorReply {
+            code: String::from(self.code()),
+            message: self.to_string(),
+            retry: self.retry(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_code_retry_and_status() {
+        let cases = vec![
+            (
+                NexusError::ChainNotRegistered(1),
+                "chain_not_registered",
+                false,
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                NexusError::InvalidDaPointer,
+                "invalid_da_pointer",
+                false,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                NexusError::DaUnavailable(String::new()),
+                "da_unavailable",
+                true,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                NexusError::DaTxNotFound,
+                "da_tx_not_found",
+                true,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                NexusError::InvalidSender(1),
+                "invalid_sender",
+                false,
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                NexusError::InvalidDaBatch(String::new()),
+                "invalid_da_batch",
+                false,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                NexusError::DaBatchMismatch(1),
+                "da_batch_mismatch",
+                false,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                NexusError::InvalidProofEncoding(String::new()),
+                "invalid_proof_encoding",
+                false,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                NexusError::InvalidProof,
+                "invalid_proof",
+                false,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                NexusError::UnknownAggregatedBatch(1),
+                "unknown_aggregated_batch",
+                false,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                NexusError::AggregatedBatchMismatch(1),
+                "aggregated_batch_mismatch",
+                false,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                NexusError::StaleAggregatedBatch(1),
+                "stale_aggregated_batch",
+                false,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                NexusError::MissingReceipts,
+                "missing_receipts",
+                false,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                NexusError::ReceiptsRootMismatch,
+                "receipts_root_mismatch",
+                false,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                NexusError::ReceiptChainMismatch(1),
+                "receipt_chain_mismatch",
+                false,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                NexusError::PreStateRootMismatch(1),
+                "pre_state_root_mismatch",
+                false,
+                StatusCode::CONFLICT,
+            ),
+            (
+                NexusError::AlreadyVerified(1),
+                "already_verified",
+                false,
+                StatusCode::CONFLICT,
+            ),
+            (
+                NexusError::ImageIdChanged(1),
+                "image_id_changed",
+                false,
+                StatusCode::CONFLICT,
+            ),
+            (
+                NexusError::PendingBatches(1),
+                "pending_batches",
+                false,
+                StatusCode::CONFLICT,
+            ),
+            (
+                NexusError::Unauthorized,
+                "unauthorized",
+                false,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                NexusError::NotFound,
+                "not_found",
+                false,
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                NexusError::Internal(String::new()),
+                "internal",
+                true,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+
+        for (error, code, retry, status) in cases {
+            assert_eq!(error.code(), code);
+            assert_eq!(error.retry(), retry, "{}", code);
+            assert_eq!(error.status_code(), status, "{}", code);
+        }
+    }
+
+    #[test]
+    fn anyhow_errors_are_internal() {
+        let error = NexusError::from(anyhow::anyhow!("db closed"));
+
+        assert_eq!(error.code(), "internal");
+        assert!(error.retry());
+    }
+}
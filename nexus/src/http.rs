@@ -0,0 +1,319 @@
+use crate::errors::NexusError;
+use crate::nexus_app::NexusApp;
+use crate::types::{
+    BatchAcceptance, ChainConfig, ChainStateQuery, EventsQuery, ImageIdUpdate, ReceiptQuery,
+    SubmitProofParam,
+};
+use actix_web::{http::header, HttpRequest, HttpResponse, ResponseError};
+use actix_web::{web, App, HttpServer, Responder};
+use anyhow::{anyhow, Error};
+use futures::stream;
+use nft_core::{
+    events::NexusEvent,
+    types::{ReceiptOrigin, SubmitBatchReply},
+};
+use sparse_merkle_tree::H256;
+use tokio::sync::broadcast::error::RecvError;
+
+//HTTP API of nexus.
+
+impl NexusApp {
+    //Checks the bearer token of an admin request.
+    pub fn check_admin(&self, req: &HttpRequest) -> Result<(), NexusError> {
+        let admin_token = match &self.admin_token {
+            Some(i) => i,
+            None => return Err(NexusError::Unauthorized),
+        };
+        let token = match req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|i| i.to_str().ok())
+            .and_then(|i| i.strip_prefix("Bearer "))
+        {
+            Some(i) => i,
+            None => return Err(NexusError::Unauthorized),
+        };
+
+        if !tokens_match(token.as_bytes(), admin_token.as_bytes()) {
+            return Err(NexusError::Unauthorized);
+        }
+
+        Ok(())
+    }
+}
+
+async fn submit_batch(
+    service: web::Data<NexusApp>,
+    call: web::Json<SubmitProofParam>,
+) -> impl Responder {
+    let deserialized_call: SubmitProofParam = call.into_inner();
+
+    match service.submit_batch(deserialized_call).await {
+        Ok(BatchAcceptance::Verified) => HttpResponse::Ok().json(SubmitBatchReply {
+            code: String::from("verified"),
+            message: String::from("Proof verified and submitted successfully."),
+        }),
+        //Not final, the batch is verified or dropped once the batch before it
+        //is submitted or the buffer times out.
+        Ok(BatchAcceptance::Buffered) => HttpResponse::Accepted().json(SubmitBatchReply {
+            code: String::from("buffered"),
+            message: String::from(
+                "Proof verified, batch buffered until the batch before it is verified.",
+            ),
+        }),
+        Err(e) => {
+            println!("Batch submission rejected. {:?}", e);
+
+            e.error_response()
+        }
+    }
+}
+
+fn hex_string_to_u8_array(hex_string: &str) -> Result<[u8; 32], Error> {
+    let bytes = hex::decode(hex_string)?;
+
+    if bytes.len() != 32 {
+        return Err(anyhow!(
+            "Hexadecimal string must represent exactly 32 bytes"
+        ));
+    }
+
+    let mut array = [0u8; 32];
+    array.copy_from_slice(&bytes);
+
+    Ok(array)
+}
+
+async fn get_receipt_with_proof(
+    service: web::Data<NexusApp>,
+    call: web::Query<ReceiptQuery>,
+) -> impl Responder {
+    let deserialized_call: ReceiptQuery = call.into_inner();
+    let u8_array: [u8; 32] = match hex_string_to_u8_array(&deserialized_call.key) {
+        Ok(i) => i,
+        Err(e) => return HttpResponse::BadRequest().json("Invalid receipt hash."),
+    };
+
+    let key: H256 = H256::from(u8_array);
+
+    match service.get_receipt_leaf_with_proof(&key, deserialized_call.at) {
+        Ok(i) => HttpResponse::Ok().json(i),
+        Err(e) => NexusError::from(e).error_response(),
+    }
+}
+
+async fn get_receipt_by_origin(
+    service: web::Data<NexusApp>,
+    call: web::Query<ReceiptOrigin>,
+) -> impl Responder {
+    let origin: ReceiptOrigin = call.into_inner();
+
+    match service.get_receipt_leaf_with_proof(&origin.key(), None) {
+        Ok(i) => HttpResponse::Ok().json(i),
+        Err(e) => NexusError::from(e).error_response(),
+    }
+}
+
+async fn get_chain_state(
+    service: web::Data<NexusApp>,
+    call: web::Query<ChainStateQuery>,
+) -> impl Responder {
+    let query: ChainStateQuery = call.into_inner();
+
+    match service.get_chain_state_with_proof(query.chain_id, query.at) {
+        Ok(i) => HttpResponse::Ok().json(i),
+        Err(e) => NexusError::from(e).error_response(),
+    }
+}
+
+async fn get_aggregated_batch(
+    service: web::Data<NexusApp>,
+    proof_number: web::Path<u64>,
+) -> impl Responder {
+    match service.get_aggregated_batch(proof_number.into_inner()) {
+        Ok(Some(i)) => HttpResponse::Ok().json(i),
+        Ok(None) => NexusError::NotFound.error_response(),
+        Err(e) => NexusError::from(e).error_response(),
+    }
+}
+
+async fn get_aggregation_proof(
+    service: web::Data<NexusApp>,
+    proof_number: web::Path<u64>,
+) -> impl Responder {
+    match service.get_aggregation_proof(proof_number.into_inner()) {
+        Ok(Some(i)) => HttpResponse::Ok().json(i),
+        Ok(None) => NexusError::NotFound.error_response(),
+        Err(e) => NexusError::from(e).error_response(),
+    }
+}
+
+async fn get_aggregated_record(
+    service: web::Data<NexusApp>,
+    proof_number: web::Path<u64>,
+) -> impl Responder {
+    match service.get_aggregated_record(proof_number.into_inner()) {
+        Ok(Some(i)) => HttpResponse::Ok().json(i),
+        Ok(None) => NexusError::NotFound.error_response(),
+        Err(e) => NexusError::from(e).error_response(),
+    }
+}
+
+async fn register_chain(
+    service: web::Data<NexusApp>,
+    req: HttpRequest,
+    call: web::Json<ChainConfig>,
+) -> impl Responder {
+    if let Err(e) = service.check_admin(&req) {
+        return e.error_response();
+    }
+
+    match service.register_chain(call.into_inner()).await {
+        Ok(()) => HttpResponse::Ok().json("Chain registered."),
+        Err(e) => e.error_response(),
+    }
+}
+
+async fn update_image_id(
+    service: web::Data<NexusApp>,
+    req: HttpRequest,
+    chain_id: web::Path<u64>,
+    call: web::Json<ImageIdUpdate>,
+) -> impl Responder {
+    if let Err(e) = service.check_admin(&req) {
+        return e.error_response();
+    }
+
+    match service.update_image_id(chain_id.into_inner(), call.into_inner().image_id) {
+        Ok(()) => HttpResponse::Ok().json("Image ID updated."),
+        Err(e) => e.error_response(),
+    }
+}
+
+async fn get_chains(service: web::Data<NexusApp>) -> impl Responder {
+    HttpResponse::Ok().json(service.get_chains())
+}
+
+async fn get_recent_aggregated_batches(service: web::Data<NexusApp>) -> impl Responder {
+    match service.get_recent_aggregated_batches() {
+        Ok(i) => HttpResponse::Ok().json(i),
+        Err(e) => NexusError::from(e).error_response(),
+    }
+}
+
+//Streams nexus events as server sent events, starting with the latest aggregated
+//batch. Receipt events are only sent for the receipt hashes being watched, if
+//any are given.
+async fn get_events(service: web::Data<NexusApp>, call: web::Query<EventsQuery>) -> impl Responder {
+    let query: EventsQuery = call.into_inner();
+    let mut watched: Vec<H256> = vec![];
+
+    for key in query.watch.iter().flat_map(|i| i.split(',')) {
+        match hex_string_to_u8_array(key) {
+            Ok(i) => watched.push(H256::from(i)),
+            Err(_e) => return HttpResponse::BadRequest().json("Invalid receipt hash."),
+        }
+    }
+
+    let latest = {
+        let app_state = service.app_state.lock().unwrap();
+
+        NexusEvent::AggregatedBatch(app_state.last_aggregated_batch.clone())
+    };
+    let stream = stream::unfold(
+        (service.subscribe(), Some(latest)),
+        move |(mut receiver, latest)| {
+            let watched = watched.clone();
+
+            async move {
+                let event = match latest {
+                    Some(i) => i,
+                    None => loop {
+                        match receiver.recv().await {
+                            Ok(NexusEvent::ReceiptIncluded { key, .. })
+                                if !watched.is_empty() && !watched.contains(&key) =>
+                            {
+                                continue
+                            }
+                            Ok(i) => break i,
+                            //Events are skipped if the client falls behind.
+                            Err(RecvError::Lagged(_)) => continue,
+                            Err(RecvError::Closed) => return None,
+                        }
+                    },
+                };
+
+                match event.to_sse() {
+                    Ok(i) => Some((
+                        Ok::<web::Bytes, actix_web::Error>(web::Bytes::from(i)),
+                        (receiver, None),
+                    )),
+                    Err(_e) => None,
+                }
+            }
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
+
+async fn get_current_batch(service: web::Data<NexusApp>) -> impl Responder {
+    let app_state = service.app_state.lock().unwrap();
+
+    //TODO: Create method on App or app state to get this.
+    let current_batch = app_state.last_aggregated_batch.clone();
+
+    HttpResponse::Ok().json(current_batch)
+}
+
+pub async fn start_rpc_server(shared_service: NexusApp) -> impl Send {
+    let json_cfg = web::JsonConfig::default()
+        // limit request payload size
+        .limit(1800000000);
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(shared_service.clone()))
+            .app_data(json_cfg.clone())
+            .route("/submit-batch", web::post().to(submit_batch))
+            .route("/current-batch", web::get().to(get_current_batch))
+            .route("/events", web::get().to(get_events))
+            .route("/chains", web::get().to(get_chains))
+            .route("/admin/chains", web::post().to(register_chain))
+            .route(
+                "/admin/chains/{chain_id}/image_id",
+                web::post().to(update_image_id),
+            )
+            .route("/receipt", web::get().to(get_receipt_with_proof))
+            .route("/receipt/origin", web::get().to(get_receipt_by_origin))
+            .route("/chain-state", web::get().to(get_chain_state))
+            .route(
+                "/aggregated/recent",
+                web::get().to(get_recent_aggregated_batches),
+            )
+            .route(
+                "/aggregated/{proof_number}",
+                web::get().to(get_aggregated_batch),
+            )
+            .route(
+                "/aggregated/{proof_number}/proof",
+                web::get().to(get_aggregation_proof),
+            )
+            .route(
+                "/aggregated/{proof_number}/record",
+                web::get().to(get_aggregated_record),
+            )
+    })
+    .bind(("127.0.0.1", 8080))
+    .unwrap()
+    .run()
+    .await;
+}
+
+//Compares in constant time, so the admin token can't be guessed from timings.
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
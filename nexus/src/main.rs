@@ -1,16 +1,45 @@
+mod aggregation;
+mod buffering;
+mod da;
+mod errors;
+mod http;
 mod nexus_app;
-mod store;
+mod registry;
 mod types;
 
-use crate::nexus_app::{AggregatedBatch, AppState, NexusAppConfig};
-use avail::service::{DaProvider, DaServiceConfig};
-use nexus_app::{start_rpc_server, NexusApp};
-use nft_core::{db::NodeDB, state::VmState, types::BatchHeader};
+use crate::buffering::AppState;
+use crate::http::start_rpc_server;
+use crate::nexus_app::{NexusApp, NexusAppConfig};
+use crate::registry::ChainRegistry;
+use crate::types::ChainConfig;
+use nft_core::{db::NodeDB, receipts::ChainKind, state::VmState, types::AggregatedBatch};
 use nft_methods::TRANSFER_ID as NFT_ID;
 use payments_methods::TRANSFER_ID;
 use sparse_merkle_tree::H256;
 use std::sync::{Arc, Mutex};
 
+//Chains registered when nexus starts with an empty registry and no config file.
+fn default_chains() -> Vec<ChainConfig> {
+    vec![
+        ChainConfig {
+            chain_id: 7000,
+            image_id: NFT_ID,
+            da_app_id: 7,
+            da_sender: None,
+            light_client_url: String::from("http://127.0.0.1:8000"),
+            kind: Some(ChainKind::Nft),
+        },
+        ChainConfig {
+            chain_id: 7001,
+            image_id: TRANSFER_ID,
+            da_app_id: 8,
+            da_sender: None,
+            light_client_url: String::from("http://127.0.0.1:8001"),
+            kind: Some(ChainKind::Payments),
+        },
+    ]
+}
+
 fn main() {
     println!(
         "Nexus started, ZKVM IDs: {:?}, and {:?}",
@@ -23,70 +52,86 @@ fn main() {
             Ok(None) => AggregatedBatch {
                 proof_number: 0,
                 receipts_root: H256::zero(),
+                recent_roots: vec![],
+                chain_states_root: H256::zero(),
             },
             Err(e) => panic!("Could not start node. {:?}", e),
         };
-    let last_aggregated_nft_batch: BatchHeader =
-        match db.get::<BatchHeader>(b"last_aggregated_nft_batch") {
-            Ok(Some(i)) => i,
-            Ok(None) => BatchHeader::default(),
-            Err(e) => panic!("Could not start node. {:?}", e),
-        };
-    let last_aggregated_payments_batch: BatchHeader =
-        match db.get::<BatchHeader>(b"last_aggregated_payments_batch") {
-            Ok(Some(i)) => i,
-            Ok(None) => BatchHeader::default(),
+    let mut registry = match ChainRegistry::load(&db) {
+        Ok(i) => i,
+        Err(e) => panic!("Could not start node. {:?}", e),
+    };
+
+    //Chains in the config file are registered on every start, so their config
+    //can be updated without the admin API. Their image ID can't be changed here.
+    let chains_config_path =
+        std::env::var("NEXUS_CHAINS_CONFIG").unwrap_or(String::from("./chains.json"));
+    let configured_chains: Vec<ChainConfig> = match std::fs::read_to_string(&chains_config_path) {
+        Ok(i) => match serde_json::from_str(&i) {
+            Ok(i) => i,
+            Err(e) => panic!("Invalid chains config. {:?}", e),
+        },
+        Err(_e) if registry.is_empty() => default_chains(),
+        Err(_e) => vec![],
+    };
+
+    for config in configured_chains {
+        match registry.register(&db, config) {
+            Ok(()) => (),
             Err(e) => panic!("Could not start node. {:?}", e),
-        };
+        }
+    }
 
+    let chains = registry.chains();
     let shared_tree = Arc::new(Mutex::new(VmState::new(
         last_aggregated_batch.receipts_root,
     )));
+    let app_state = match AppState::load(&db, last_aggregated_batch, registry) {
+        Ok(i) => i,
+        Err(e) => panic!("Could not start node. {:?}", e),
+    };
     let shared_db = Arc::new(Mutex::new(db));
-    let shared_app_state = Arc::new(Mutex::new(AppState::new(
-        last_aggregated_batch,
-        last_aggregated_nft_batch,
-        last_aggregated_payments_batch,
-    )));
+    let shared_app_state = Arc::new(Mutex::new(app_state));
     let rt = tokio::runtime::Runtime::new().unwrap();
 
-    let nft_da_service = rt.block_on(async move {
-        DaProvider::new(DaServiceConfig {
-            light_client_url: String::from("http://127.0.0.1:8000"),
-            node_client_url: String::from("wss://goldberg.avail.tools:443/ws"),
-            seed: String::from(
-                "rose label choose orphan garlic upset scout payment first have boil stamp",
-            ),
-            app_id: 7,
-        })
-        .await
-    });
-    let payments_da_service = rt.block_on(async move {
-        DaProvider::new(DaServiceConfig {
-            light_client_url: String::from("http://127.0.0.1:8001"),
-            node_client_url: String::from("wss://goldberg.avail.tools:443/ws"),
-            seed: String::from(
-                "rose label choose orphan garlic upset scout payment first have boil stamp",
-            ),
-            app_id: 8,
-        })
-        .await
-    });
-
     let mut app = NexusApp::new(
         shared_tree,
         shared_app_state,
         shared_db,
         NexusAppConfig {
             da_start_height: 490325,
-            nft_da_service,
-            payments_da_service,
+            node_client_url: String::from("wss://goldberg.avail.tools:443/ws"),
+            seed: String::from(
+                "rose label choose orphan garlic upset scout payment first have boil stamp",
+            ),
+            execute_only: std::env::var("NEXUS_EXECUTE_ONLY").is_ok(),
+            admin_token: std::env::var("NEXUS_ADMIN_TOKEN").ok(),
         },
     );
+
+    rt.block_on(async {
+        for chain in &chains {
+            app.add_da_service(&chain.config).await;
+        }
+
+        //Aggregated batches are only published to DA if nexus has an app ID.
+        if let Ok(i) = std::env::var("NEXUS_DA_APP_ID") {
+            let app_id: u32 = match i.parse() {
+                Ok(i) => i,
+                Err(e) => panic!("Invalid NEXUS_DA_APP_ID. {:?}", e),
+            };
+
+            app.add_da_publisher(app_id, String::from("http://127.0.0.1:8002"))
+                .await;
+        }
+    });
+
     let app_clone = app.clone();
+    let da_follower = app.clone();
 
     rt.block_on(async move {
         tokio::spawn(async move { app.start().await });
+        tokio::spawn(async move { da_follower.follow_da().await });
 
         start_rpc_server(app_clone).await;
     })
@@ -1,487 +1,377 @@
 use nft_core::{
+    aggregation::receipts_match,
     db::NodeDB,
-    nft::types::NftTransaction,
-    payments::types::Transaction as PaymentsTransaction,
+    events::NexusEvent,
     state::VmState,
-    types::{BatchHeader, DABatch, TransactionReceipt},
+    types::{BatchHeader, ProofEnvelope, ReceiptLeaf},
 };
-use primitive_types::H256 as SubstrateH256;
-use serde::{Deserialize, Serialize};
-use sparse_merkle_tree::traits::Value;
-use sparse_merkle_tree::H256;
-
+use std::collections::HashMap;
 use std::time::Duration;
 
-use crate::types::{AppChain, DaTxPointer, ReceiptQuery, SubmitProofParam};
-
-//Below imports for HTTP server.
-
-use actix_web::HttpResponse;
-use actix_web::{web, App, HttpServer, Responder};
+use crate::aggregation::HistoricalTree;
+use crate::buffering::{is_stale, AppState, BatchWithReceipts};
+use crate::errors::NexusError;
+use crate::registry::RegisteredChain;
+use crate::types::{BatchAcceptance, ChainConfig, SubmitProofParam};
 
-use anyhow::anyhow;
-use anyhow::Error;
-use avail::avail::AvailBlobTransaction;
+use avail::avail::AvailAddress;
 use avail::service::DaProvider;
-use nft_methods::TRANSFER_ID as NFT_ID;
-use payments_methods::TRANSFER_ID as PAYMENTS_ID;
-use risc0_zkvm::{serde::from_slice, InnerReceipt, Receipt};
+use risc0_zkvm::{serde::from_slice, Receipt};
 use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
 
 const AGGREGATE_INTERVAL: Duration = Duration::from_secs(30);
+//Events a slow subscriber can fall behind by before it skips them.
+const EVENTS_CAPACITY: usize = 1000;
 
 #[derive(Clone)]
 pub struct NexusApp {
-    tree_state: Arc<Mutex<VmState<TransactionReceipt>>>,
-    app_state: Arc<Mutex<AppState>>,
-    db: Arc<Mutex<NodeDB>>,
-    da_start_height: u64,
-    nft_da_service: DaProvider,
-    payments_da_service: DaProvider,
+    pub(crate) tree_state: Arc<Mutex<VmState<ReceiptLeaf>>>,
+    pub(crate) app_state: Arc<Mutex<AppState>>,
+    pub(crate) db: Arc<Mutex<NodeDB>>,
+    pub(crate) da_start_height: u64,
+    pub(crate) da_services: Arc<Mutex<HashMap<u64, DaProvider>>>,
+    //DA connection on nexus' own app ID, aggregated batches are posted to.
+    pub(crate) da_publisher: Arc<Mutex<Option<DaProvider>>>,
+    pub(crate) node_client_url: String,
+    pub(crate) seed: String,
+    pub(crate) execute_only: bool,
+    pub(crate) events: broadcast::Sender<NexusEvent>,
+    pub(crate) historical_tree: Arc<Mutex<HistoricalTree>>,
+    pub(crate) admin_token: Option<String>,
 }
 
 pub struct NexusAppConfig {
     pub da_start_height: u64,
-    pub nft_da_service: DaProvider,
-    pub payments_da_service: DaProvider,
-}
-
-pub struct AppState {
-    pub last_aggregated_batch: AggregatedBatch,
-    pub last_aggregated_nft_batch: BatchHeader,
-    pub last_aggregated_payments_batch: BatchHeader,
-    pub verified_nft_batches: Vec<BatchWithReceipts>,
-    pub verified_payments_batches: Vec<BatchWithReceipts>,
+    //Used to connect to DA for each registered chain.
+    pub node_client_url: String,
+    pub seed: String,
+    //Only executes the aggregation guest without proving, for local testing.
+    pub execute_only: bool,
+    //Bearer token required by the admin API, which is disabled if not set.
+    pub admin_token: Option<String>,
 }
 
-impl AppState {
+impl NexusApp {
     pub fn new(
-        last_aggregated_batch: AggregatedBatch,
-        last_aggregated_nft_batch: BatchHeader,
-        last_aggregated_payments_batch: BatchHeader,
+        tree_state: Arc<Mutex<VmState<ReceiptLeaf>>>,
+        app_state: Arc<Mutex<AppState>>,
+        db: Arc<Mutex<NodeDB>>,
+        config: NexusAppConfig,
     ) -> Self {
-        AppState {
-            last_aggregated_batch,
-            last_aggregated_nft_batch,
-            last_aggregated_payments_batch,
-            verified_nft_batches: vec![],
-            verified_payments_batches: vec![],
-        }
-    }
-
-    pub fn get_last_nft_verified_batch(&self) -> BatchHeader {
-        if self.verified_nft_batches.is_empty() {
-            self.last_aggregated_nft_batch.clone()
-        } else {
-            match self.verified_nft_batches.last() {
-                Some(i) => i.header.clone(),
-                None => unreachable!("Will not be empty in else."),
-            }
-        }
-    }
-
-    pub fn get_last_payments_verified_batch(&self) -> BatchHeader {
-        if self.verified_payments_batches.is_empty() {
-            self.last_aggregated_payments_batch.clone()
-        } else {
-            match self.verified_payments_batches.last() {
-                Some(i) => i.header.clone(),
-                None => unreachable!("Will not be empty in else."),
-            }
+        Self {
+            tree_state,
+            app_state,
+            db,
+            da_start_height: config.da_start_height,
+            da_services: Arc::new(Mutex::new(HashMap::new())),
+            da_publisher: Arc::new(Mutex::new(None)),
+            node_client_url: config.node_client_url,
+            seed: config.seed,
+            execute_only: config.execute_only,
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            historical_tree: Arc::new(Mutex::new(HistoricalTree::new())),
+            admin_token: config.admin_token,
         }
     }
-}
-
-pub struct BatchWithReceipts {
-    receipts: Vec<TransactionReceipt>,
-    header: BatchHeader,
-}
 
-pub struct OrderedBatches(Vec<BatchWithReceipts>);
-
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
-pub struct AggregatedBatch {
-    pub proof_number: u64,
-    pub receipts_root: H256,
-}
-
-impl OrderedBatches {
-    pub fn new() -> Self {
-        Self(vec![])
+    pub fn subscribe(&self) -> broadcast::Receiver<NexusEvent> {
+        self.events.subscribe()
     }
 
-    pub fn last(&self) -> Option<&BatchWithReceipts> {
-        self.0.last()
+    pub(crate) fn publish_event(&self, event: NexusEvent) {
+        //Sending only fails if there are no subscribers.
+        let _ = self.events.send(event);
     }
 
-    pub fn first(&self) -> Option<&BatchWithReceipts> {
-        self.0.first()
-    }
+    pub async fn register_chain(&self, config: ChainConfig) -> Result<(), NexusError> {
+        {
+            let mut app_state = self.app_state.lock().unwrap();
+            let db = self.db.lock().unwrap();
 
-    pub fn add_batch(&mut self, batch: BatchWithReceipts) {
-        self.0.push(batch);
-    }
+            if let Some(i) = app_state.registry.get(config.chain_id) {
+                if i.config.image_id != config.image_id {
+                    return Err(NexusError::ImageIdChanged(config.chain_id));
+                }
+            }
 
-    pub fn delete_first(&mut self) {
-        if !self.0.is_empty() {
-            self.0.remove(0);
+            app_state.registry.register(&db, config.clone())?;
         }
-    }
 
-    pub fn clear(&mut self) {
-        if !self.0.is_empty() {
-            self.0.clear();
-        }
-    }
+        self.add_da_service(&config).await;
 
-    pub fn proof_count(&self) -> usize {
-        self.0.len()
-    }
-}
+        println!("Registered chain {}", config.chain_id);
 
-impl NexusApp {
-    pub fn new(
-        tree_state: Arc<Mutex<VmState<TransactionReceipt>>>,
-        app_state: Arc<Mutex<AppState>>,
-        db: Arc<Mutex<NodeDB>>,
-        config: NexusAppConfig,
-    ) -> Self {
-        Self {
-            tree_state,
-            app_state,
-            db,
-            da_start_height: config.da_start_height,
-            nft_da_service: config.nft_da_service,
-            payments_da_service: config.payments_da_service,
+        if config.da_sender.is_none() {
+            println!(
+                "Chain {} has no DA sender, its proofs on DA are not followed.",
+                config.chain_id
+            );
         }
-    }
-
-    pub async fn start(&mut self) {
-        loop {
-            self.aggregate_proofs();
 
-            tokio::time::sleep(AGGREGATE_INTERVAL).await;
-        }
+        Ok(())
     }
 
-    fn aggregate_proofs(&mut self) {
+    //Changes the guest a chain's batches are verified against. Refused while the
+    //chain has verified or buffered batches, as they were proven with the
+    //previous guest.
+    pub fn update_image_id(&self, chain_id: u64, image_id: [u32; 8]) -> Result<(), NexusError> {
         let mut app_state = self.app_state.lock().unwrap();
-        let mut tree_state = self.tree_state.lock().unwrap();
         let db = self.db.lock().unwrap();
-        println!(
-            "aggregating proofs: {:?}, {:?}",
-            app_state.verified_payments_batches.len(),
-            app_state.verified_payments_batches.len()
-        );
-        let mut receipts_to_add: Vec<TransactionReceipt> = vec![];
 
-        for batch in &app_state.verified_nft_batches {
-            receipts_to_add.extend(batch.receipts.clone());
+        if app_state.registry.get(chain_id).is_none() {
+            return Err(NexusError::ChainNotRegistered(chain_id));
         }
 
-        for batch in &app_state.verified_payments_batches {
-            receipts_to_add.extend(batch.receipts.clone());
+        let has_verified = match app_state.verified_batches.get(&chain_id) {
+            Some(i) => i.proof_count() > 0,
+            None => false,
+        };
+        let has_buffered = match app_state.buffered_batches.get(&chain_id) {
+            Some(i) => !i.is_empty(),
+            None => false,
+        };
+
+        if has_verified || has_buffered {
+            return Err(NexusError::PendingBatches(chain_id));
         }
 
-        if !receipts_to_add.is_empty() {
-            let state_update = match tree_state.update_set(receipts_to_add) {
-                Ok(i) => i,
-                Err(e) => {
-                    println!("Panic shutdown due to error, {:?}", e);
+        app_state
+            .registry
+            .update_image_id(&db, chain_id, image_id)?;
 
-                    panic!("State update failed.");
-                }
-            };
+        println!("Updated image ID of chain {}", chain_id);
 
-            println!(
-                "New proof aggregated. root is: {:?}",
-                &state_update.post_state_root
-            );
+        Ok(())
+    }
 
-            let last_aggregated_batch = AggregatedBatch {
-                proof_number: app_state.last_aggregated_batch.proof_number + 1,
-                receipts_root: state_update.post_state_root,
-            };
+    pub fn get_chains(&self) -> Vec<RegisteredChain> {
+        let app_state = self.app_state.lock().unwrap();
 
-            //TODO: Ensure there are no race conditions here, so a new batch, not aggregated is not stored.
-            //Most probably, there are no race conditions, as we lock app_state here.
-            let last_aggregated_nft_batch = app_state.get_last_nft_verified_batch();
-            let last_aggregated_payments_batch = app_state.get_last_payments_verified_batch();
+        app_state.registry.chains()
+    }
 
-            //TODO: Set this through a method.
-            app_state.last_aggregated_batch = last_aggregated_batch.clone();
-            app_state.last_aggregated_nft_batch = last_aggregated_nft_batch;
-            app_state.last_aggregated_payments_batch = last_aggregated_payments_batch;
+    pub async fn start(&mut self) {
+        //Set before the first aggregation, so the batches it aggregates are published.
+        if let Err(e) = self.init_last_published() {
+            panic!("Could not start node. {:?}", e);
+        }
 
-            match db.put::<AggregatedBatch>(b"last_aggregated_proof", &last_aggregated_batch) {
-                Ok(()) => (),
-                Err(e) => panic!("Could not start node. {:?}", e),
-            }
-            match db.put::<BatchHeader>(
-                b"last_aggregated_nft_batch",
-                &app_state.last_aggregated_nft_batch,
-            ) {
-                Ok(()) => (),
-                Err(e) => panic!("Could not start node. {:?}", e),
-            }
-            match db.put::<BatchHeader>(
-                b"last_aggregated_payments_batch",
-                &app_state.last_aggregated_payments_batch,
-            ) {
+        loop {
+            self.aggregate_proofs().await;
+
+            match self.publish_aggregated_batches().await {
                 Ok(()) => (),
-                Err(e) => panic!("Could not start node. {:?}", e),
+                Err(e) => println!("Publishing aggregated batches failed, will retry. {:?}", e),
             }
 
-            tree_state.commit();
+            tokio::time::sleep(AGGREGATE_INTERVAL).await;
         }
-
-        app_state.verified_nft_batches.clear();
-        app_state.verified_payments_batches.clear();
     }
 
-    async fn get_da_tx(&self, pointer: DaTxPointer) -> Result<AvailBlobTransaction, Error> {
-        let da_service = match pointer.chain {
-            AppChain::Nft => &self.nft_da_service,
-            AppChain::Payments => &self.payments_da_service,
+    //Checks the aggregated batch committed in the journal was produced by nexus.
+    fn verify_aggregated_batch(&self, batch_header: &BatchHeader) -> Result<(), NexusError> {
+        let proof_number = batch_header.aggregated_proof_number;
+        let aggregated_batch = match self.get_aggregated_batch(proof_number)? {
+            Some(i) => i,
+            None => return Err(NexusError::UnknownAggregatedBatch(proof_number)),
         };
 
-        println!(
-            "{}",
-            match pointer.chain {
-                AppChain::Nft => "NFT",
-                AppChain::Payments => "Payments",
-            }
-        );
+        if aggregated_batch.receipts_root != batch_header.aggregated_receipts_root
+            || aggregated_batch.hash() != batch_header.aggregated_batch_hash
+        {
+            return Err(NexusError::AggregatedBatchMismatch(proof_number));
+        }
 
-        let block = match da_service.get_block_with_hash(pointer.block_hash).await {
-            Ok(i) => i,
-            Err(e) => {
-                println!("Error getting block: {:?}", e);
+        Ok(())
+    }
 
-                return Err(anyhow!("Error fetching data {:?}", e)); // Retry the same height on error
-            }
-        };
-        let hash = SubstrateH256::from(pointer.hash);
-        println!("Da hash: {:?}, {:?}", hash, &block);
-
-        match block.find_tx(&hash) {
-            Some(i) => Ok(i),
-            None => {
-                println!("Could not find tx");
-                Err(anyhow!("DA Transaction not found in block."))
-            }
+    pub async fn submit_batch(
+        &self,
+        param: SubmitProofParam,
+    ) -> Result<BatchAcceptance, NexusError> {
+        if param.da_tx_pointer.chain_id != param.chain_id {
+            return Err(NexusError::InvalidDaPointer);
         }
-    }
 
-    pub async fn submit_batch(&self, param: SubmitProofParam) -> Result<(), Error> {
         let tx = self.get_da_tx(param.da_tx_pointer.clone()).await?;
         let blob = tx.blob();
 
         //TODO: Check if all transactions are available and complete.
 
-        match param.chain {
-            AppChain::Nft => self.verify_nft_batch(param, blob),
-            AppChain::Payments => self.verify_payments_batch(param, blob),
-        }
+        self.verify_batch(param, tx.sender(), blob)
     }
 
-    pub fn verify_nft_batch(&self, param: SubmitProofParam, blob: &[u8]) -> Result<(), Error> {
-        let mut app_state = self.app_state.lock().unwrap();
-        let _da_batch: DABatch<NftTransaction> = match bincode::deserialize(blob) {
+    pub fn verify_batch(
+        &self,
+        param: SubmitProofParam,
+        sender: AvailAddress,
+        blob: &[u8],
+    ) -> Result<BatchAcceptance, NexusError> {
+        //Transactions are specific to each chain, so only the header is decoded.
+        let da_header: BatchHeader = match bincode::deserialize(blob) {
             Ok(i) => i,
-            Err(e) => {
-                return Err(anyhow!(
-                    "Da batch deserialization failed due to error: {:?}",
-                    e
-                ))
-            }
-        };
-        let session_receipt: Receipt = match bincode::deserialize(&param.session_receipt) {
-            Ok(i) => i,
-            Err(e) => {
-                return Err(anyhow!(
-                    "proof deserialization failed due to error: {:?}",
-                    e
-                ))
-            }
+            Err(e) => return Err(NexusError::InvalidDaBatch(e.to_string())),
         };
 
-        // if da_batch.header != batch.header {
-        //     return Err(anyhow!("Provided batch header does not match header posted to DA."));
-        // }
+        self.verify_proof(
+            ProofEnvelope {
+                chain_id: param.chain_id,
+                session_receipt: param.session_receipt,
+                receipts: param.receipts,
+            },
+            sender,
+            &da_header,
+        )
+    }
 
-        //TODO: Da validity check.
+    //Verifies a batch proof, either submitted or found on DA, against the header
+    //of the batch posted to DA, and queues the batch for aggregation, or buffers
+    //it if the batch before it is missing.
+    pub fn verify_proof(
+        &self,
+        envelope: ProofEnvelope,
+        sender: AvailAddress,
+        da_header: &BatchHeader,
+    ) -> Result<BatchAcceptance, NexusError> {
+        let mut app_state = self.app_state.lock().unwrap();
+        let chain = match app_state.registry.get(envelope.chain_id) {
+            Some(i) => i.config.clone(),
+            None => return Err(NexusError::ChainNotRegistered(envelope.chain_id)),
+        };
 
-        println!("verifying NFT batch.");
-        match session_receipt.verify(NFT_ID) {
-            Ok(_i) => Ok::<(), Error>(()),
-            //TODO: Simplify this chaining.
-            Err(_e) => return Err(anyhow!("Unable to verify proof.")),
+        if let Some(da_sender) = chain.da_sender {
+            if sender.0 != da_sender {
+                return Err(NexusError::InvalidSender(chain.chain_id));
+            }
+        }
+
+        let session_receipt: Receipt = match bincode::deserialize(&envelope.session_receipt) {
+            Ok(i) => i,
+            Err(e) => return Err(NexusError::InvalidProofEncoding(e.to_string())),
         };
 
-        println!("Verified NFT batch. Will be aggregated in the next cycle.");
+        println!("verifying batch of chain {}.", chain.chain_id);
+        if session_receipt.verify(chain.image_id).is_err() {
+            return Err(NexusError::InvalidProof);
+        }
+
         //Doing it this way to compare public parameters to submitted batch.
-        let batch_header: BatchHeader = from_slice(&session_receipt.journal).unwrap();
-        let last_batch_header: BatchHeader = app_state.get_last_nft_verified_batch();
-        //TODO: change this to calculate root of all receipts, currently we assume
-        //there is only one receipt; per batch.
-        let receipts_root: H256 = param.receipts[0].to_h256();
-
-        //TODO: Seperate the check for better error response.
-        if receipts_root == batch_header.receipts_root
-            && last_batch_header.state_root == batch_header.pre_state_root
-        {
-            app_state.verified_nft_batches.push(BatchWithReceipts {
-                header: batch_header,
-                receipts: param.receipts,
-            });
+        let batch_header: BatchHeader = match from_slice(&session_receipt.journal) {
+            Ok(i) => i,
+            Err(e) => return Err(NexusError::InvalidProofEncoding(e.to_string())),
+        };
 
+        //The proof has to be of the batch posted to DA, so its transactions are
+        //available. The header hash covers its roots and batch number.
+        if batch_header.hash() != da_header.hash() {
             println!(
-                "Added nft batch, total count: {:?}",
-                app_state.verified_nft_batches.len()
+                "Proven batch {} does not match DA batch {}.",
+                batch_header.batch_number, da_header.batch_number
             );
 
-            Ok(())
-        } else {
-            println!(
-                "Invalid proof receipts root: {:?} {:?}",
-                &receipts_root, &batch_header.receipts_root
-            );
+            return Err(NexusError::DaBatchMismatch(da_header.batch_number));
+        }
+        self.verify_aggregated_batch(&batch_header)?;
+        let last_batch_header: BatchHeader = match app_state.get_last_verified_batch(chain.chain_id)
+        {
+            Some(i) => i,
+            None => return Err(NexusError::ChainNotRegistered(chain.chain_id)),
+        };
+        if envelope.receipts.is_empty() {
+            return Err(NexusError::MissingReceipts);
+        }
+
+        if !receipts_match(&batch_header, &envelope.receipts) {
             println!(
-                "pre_state root: {:?} {:?}",
-                &last_batch_header.state_root, &batch_header.pre_state_root
+                "Receipts of batch {} do not match receipts root {:?}",
+                batch_header.batch_number, &batch_header.receipts_root
             );
-            Err(anyhow!("Invalid proof."))
+
+            return Err(NexusError::ReceiptsRootMismatch);
         }
-    }
 
-    pub fn verify_payments_batch(&self, param: SubmitProofParam, blob: &[u8]) -> Result<(), Error> {
-        let mut app_state = self.app_state.lock().unwrap();
+        //The chain ID is an input of the app guest, so a proof is only bound to its
+        //chain through the receipts it commits to.
+        if envelope
+            .receipts
+            .iter()
+            .any(|i| i.chain_id != chain.chain_id)
+        {
+            return Err(NexusError::ReceiptChainMismatch(chain.chain_id));
+        }
 
-        let session_receipt: Receipt = match bincode::deserialize(&param.session_receipt) {
-            Ok(i) => i,
-            Err(e) => {
-                return Err(anyhow!(
-                    "proof deserialization failed due to error: {:?}",
-                    e
-                ))
-            }
+        let batch_number = batch_header.batch_number;
+        let batch = BatchWithReceipts {
+            header: batch_header,
+            receipts: envelope.receipts,
         };
-        let _da_batch: DABatch<PaymentsTransaction> = match bincode::deserialize(blob) {
-            Ok(i) => i,
-            Err(e) => {
-                return Err(anyhow!(
-                    "Da batch deserialization failed due to error: {:?}",
-                    e
-                ))
+        let db = self.db.lock().unwrap();
+
+        if batch_number <= last_batch_header.batch_number {
+            //A batch submitted again, for example after the response to it was lost,
+            //or found on DA after being submitted, is accepted.
+            if app_state.is_verified(&db, chain.chain_id, &batch.header)? {
+                return Ok(BatchAcceptance::Verified);
             }
-        };
 
-        //TODO: Da validity check.
+            return Err(NexusError::AlreadyVerified(batch_number));
+        }
 
-        println!("verifying payments batch.");
-        match session_receipt.verify(PAYMENTS_ID) {
-            Ok(_i) => Ok::<(), Error>(()),
-            //TODO: Simplify this chaining.
-            Err(_e) => return Err(anyhow!("Unable to verify proof.")),
-        };
+        if is_stale(&batch.header, app_state.last_aggregated_batch.proof_number) {
+            return Err(NexusError::StaleAggregatedBatch(
+                batch.header.aggregated_proof_number,
+            ));
+        }
 
-        let batch_header: BatchHeader = from_slice(&session_receipt.journal).unwrap();
-        let last_batch_header: BatchHeader = app_state.get_last_payments_verified_batch();
-        //TODO: change this to calculate root of all receipts, currently we assume
-        //there is only one receipt per batch.
-        let receipts_root: H256 = param.receipts[0].to_h256();
+        if batch_number > last_batch_header.batch_number + 1 {
+            //Proof is valid, but the batches before it have not arrived yet.
+            app_state.buffer_batch(&db, chain.chain_id, batch)?;
 
-        //TODO: Seperate the check for better error response.
-        if receipts_root == batch_header.receipts_root
-            && last_batch_header.state_root == batch_header.pre_state_root
-        {
-            app_state.verified_payments_batches.push(BatchWithReceipts {
-                header: batch_header,
-                receipts: param.receipts,
+            println!(
+                "Buffered batch {} of chain {}, waiting for batch {}.",
+                batch_number,
+                chain.chain_id,
+                last_batch_header.batch_number + 1
+            );
+            self.publish_event(NexusEvent::BatchBuffered {
+                chain_id: chain.chain_id,
+                batch_number,
             });
 
-            println!("Verified and added payments batch. Will be aggregated in the next cycle.");
-
-            Ok(())
-        } else {
-            Err(anyhow!("Invalid proof."))
+            return Ok(BatchAcceptance::Buffered);
         }
-    }
-}
 
-async fn submit_batch(
-    service: web::Data<NexusApp>,
-    call: web::Json<SubmitProofParam>,
-) -> impl Responder {
-    let deserialized_call: SubmitProofParam = call.into_inner();
-
-    match service.submit_batch(deserialized_call).await {
-        Ok(()) => HttpResponse::Ok().json("Proof verified and submitted successfully."),
-        Err(e) => HttpResponse::InternalServerError().body("Internal error."),
-    }
-}
-
-fn hex_string_to_u8_array(hex_string: &str) -> Result<[u8; 32], Error> {
-    let bytes = hex::decode(hex_string).unwrap();
-
-    if bytes.len() != 32 {
-        return Err(anyhow!(
-            "Hexadecimal string must represent exactly 32 bytes"
-        ));
-    }
-
-    let mut array = [0u8; 32];
-    array.copy_from_slice(&bytes);
-
-    Ok(array)
-}
+        if last_batch_header.state_root != batch.header.pre_state_root {
+            println!(
+                "pre_state root: {:?} {:?}",
+                &last_batch_header.state_root, &batch.header.pre_state_root
+            );
 
-async fn get_receipt_with_proof(
-    service: web::Data<NexusApp>,
-    call: web::Query<ReceiptQuery>,
-) -> impl Responder {
-    let deserialized_call: ReceiptQuery = call.into_inner();
-    let u8_array: [u8; 32] = match hex_string_to_u8_array(&deserialized_call.key) {
-        Ok(i) => i,
-        Err(e) => return HttpResponse::BadRequest().json("Invalid receipt hash."),
-    };
-
-    let key: H256 = H256::from(u8_array);
-    let tree_state = service.tree_state.lock().unwrap();
-
-    let receipt_with_proof = match tree_state.get_with_proof(&key) {
-        Ok(i) => i,
-        Err(_e) => return HttpResponse::InternalServerError().body("Internal error."),
-    };
-
-    HttpResponse::Ok().json(receipt_with_proof)
-}
+            return Err(NexusError::PreStateRootMismatch(chain.chain_id));
+        }
 
-async fn get_current_batch(service: web::Data<NexusApp>) -> impl Responder {
-    let app_state = service.app_state.lock().unwrap();
+        let events = app_state.add_verified_batch(&db, chain.chain_id, batch)?;
+        let added = events
+            .iter()
+            .filter(|i| matches!(i, NexusEvent::BatchVerified { .. }))
+            .count();
 
-    //TODO: Create method on App or app state to get this.
-    let current_batch = app_state.last_aggregated_batch.clone();
+        for event in events {
+            self.publish_event(event);
+        }
 
-    HttpResponse::Ok().json(current_batch)
-}
+        println!(
+            "Verified and added {} batches of chain {}, total count: {:?}. Will be aggregated in the next cycle.",
+            added,
+            chain.chain_id,
+            app_state
+                .verified_batches
+                .get(&chain.chain_id)
+                .map(|i| i.proof_count())
+                .unwrap_or(0)
+        );
 
-pub async fn start_rpc_server(shared_service: NexusApp) -> impl Send {
-    let json_cfg = web::JsonConfig::default()
-        // limit request payload size
-        .limit(1800000000);
-
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(shared_service.clone()))
-            .app_data(json_cfg.clone())
-            .route("/submit-batch", web::post().to(submit_batch))
-            .route("/current-batch", web::get().to(get_current_batch))
-            .route("/receipt", web::get().to(get_receipt_with_proof))
-    })
-    .bind(("127.0.0.1", 8080))
-    .unwrap()
-    .run()
-    .await;
+        Ok(BatchAcceptance::Verified)
+    }
 }
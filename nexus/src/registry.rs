@@ -0,0 +1,136 @@
+use crate::types::ChainConfig;
+use anyhow::{anyhow, Error};
+use nft_core::{
+    db::NodeDB,
+    receipts::{DecodedReceipt, ReceiptRegistry},
+    types::{BatchHeader, TransactionReceipt},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RegisteredChain {
+    pub config: ChainConfig,
+    //Last header of the chain included in an aggregated batch.
+    pub last_header: BatchHeader,
+}
+
+//App chains nexus accepts batches from, persisted in the nexus db so chains can
+//be added at runtime.
+pub struct ChainRegistry {
+    chains: BTreeMap<u64, RegisteredChain>,
+    //Receipt schemas of the registered chains.
+    receipts: ReceiptRegistry,
+}
+
+impl ChainRegistry {
+    pub fn load(db: &NodeDB) -> Result<Self, Error> {
+        let chain_ids: Vec<u64> = match db.get::<Vec<u64>>(b"chains")? {
+            Some(i) => i,
+            None => vec![],
+        };
+        let mut chains = BTreeMap::new();
+
+        for chain_id in chain_ids {
+            match db.get::<RegisteredChain>(&chain_key(chain_id))? {
+                Some(i) => chains.insert(chain_id, i),
+                None => return Err(anyhow!("Registered chain {} not found in db.", chain_id)),
+            };
+        }
+
+        let receipts = receipt_registry(&chains);
+
+        Ok(ChainRegistry { chains, receipts })
+    }
+
+    //Registers a chain, or updates the config of an already registered one. The
+    //image ID of a registered chain is only changed through update_image_id.
+    pub fn register(&mut self, db: &NodeDB, config: ChainConfig) -> Result<(), Error> {
+        let chain_id = config.chain_id;
+        let last_header = match self.chains.get(&chain_id) {
+            Some(i) if i.config.image_id != config.image_id => {
+                return Err(anyhow!(
+                    "Chain {} is registered with a different image ID.",
+                    chain_id
+                ))
+            }
+            Some(i) => i.last_header.clone(),
+            None => BatchHeader::default(),
+        };
+        let chain = RegisteredChain {
+            config,
+            last_header,
+        };
+
+        db.put(&chain_key(chain_id), &chain)?;
+        self.chains.insert(chain_id, chain);
+        self.receipts = receipt_registry(&self.chains);
+        Ok(db.put(b"chains", &self.chains.keys().cloned().collect::<Vec<u64>>())?)
+    }
+
+    //Callers have to make sure no batches of the chain are pending, as they were
+    //verified against the previous image ID.
+    pub fn update_image_id(
+        &mut self,
+        db: &NodeDB,
+        chain_id: u64,
+        image_id: [u32; 8],
+    ) -> Result<(), Error> {
+        let chain = match self.chains.get_mut(&chain_id) {
+            Some(i) => i,
+            None => return Err(anyhow!("Chain {} not registered.", chain_id)),
+        };
+
+        chain.config.image_id = image_id;
+
+        Ok(db.put(&chain_key(chain_id), chain)?)
+    }
+
+    pub fn set_last_header(
+        &mut self,
+        db: &NodeDB,
+        chain_id: u64,
+        header: BatchHeader,
+    ) -> Result<(), Error> {
+        let chain = match self.chains.get_mut(&chain_id) {
+            Some(i) => i,
+            None => return Err(anyhow!("Chain {} not registered.", chain_id)),
+        };
+
+        chain.last_header = header;
+
+        Ok(db.put(&chain_key(chain_id), chain)?)
+    }
+
+    pub fn get(&self, chain_id: u64) -> Option<&RegisteredChain> {
+        self.chains.get(&chain_id)
+    }
+
+    pub fn chains(&self) -> Vec<RegisteredChain> {
+        self.chains.values().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    pub fn decode_receipt(&self, receipt: &TransactionReceipt) -> Result<DecodedReceipt, Error> {
+        self.receipts.decode(receipt)
+    }
+}
+
+fn receipt_registry(chains: &BTreeMap<u64, RegisteredChain>) -> ReceiptRegistry {
+    let mut registry = ReceiptRegistry::new();
+
+    for (chain_id, chain) in chains {
+        if let Some(kind) = chain.config.kind {
+            registry.register_chain(*chain_id, kind);
+        }
+    }
+
+    registry
+}
+
+fn chain_key(chain_id: u64) -> Vec<u8> {
+    [b"chain-".as_slice(), &chain_id.to_be_bytes()].concat()
+}
@@ -1,28 +1,75 @@
 
 use serde::{Deserialize, Serialize};
-use nft_core::types::TransactionReceipt;
+use nft_core::{aggregation::AggregationJournal, receipts::ChainKind, types::TransactionReceipt};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DaTxPointer {
   pub block_hash: [u8; 32],
   pub hash: [u8; 32], 
-  pub chain: AppChain,
+  pub chain_id: u64,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub enum AppChain {
-    Nft,
-    Payments,
-}
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SubmitProofParam {
     pub session_receipt: Vec<u8>,
     pub receipts: Vec<TransactionReceipt>,
-    pub chain: AppChain,
+    pub chain_id: u64,
     pub da_tx_pointer: DaTxPointer,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ChainConfig {
+    pub chain_id: u64,
+    //zkVM image ID of the chain's batch guest.
+    pub image_id: [u32; 8],
+    pub da_app_id: u32,
+    //Avail address posting the chain's batches. Batches from any sender are
+    //accepted if not set.
+    pub da_sender: Option<[u8; 32]>,
+    pub light_client_url: String,
+    //Decides the receipt schemas nexus decodes for the chain. Receipts of chains
+    //without a kind are not decoded.
+    #[serde(default)]
+    pub kind: Option<ChainKind>,
+}
+
+//Outcome of verifying a valid batch proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchAcceptance {
+    Verified,
+    //Buffered until the batch before it is verified.
+    Buffered,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ImageIdUpdate {
+    pub image_id: [u32; 8],
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
 pub struct ReceiptQuery {
     pub key: String,
+    //Aggregated batch to prove against, latest if not given.
+    pub at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
+pub struct ChainStateQuery {
+    pub chain_id: u64,
+    //Aggregated batch to prove against, latest if not given.
+    pub at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, Default)]
+pub struct EventsQuery {
+    //Comma separated receipt hashes to be notified of inclusion for.
+    pub watch: Option<String>,
+}
+
+//Aggregation guest output for an aggregated batch. The receipt is empty when
+//nexus runs in execute only mode.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AggregationProof {
+    pub journal: AggregationJournal,
+    pub receipt: Option<Vec<u8>>,
 }
@@ -6,14 +6,15 @@ use nft_core::{
         state_machine::NftStateMachine,
         types::{Nft, NftTransaction},
     },
+    receipts::ChainKind,
     traits::StateMachine,
-    types::{Address, AppChain, ClientReply},
+    types::{Address, ClientReply},
 };
 use nft_methods::{TRANSFER_ELF, TRANSFER_ID};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use crate::rpc_endpoints::nft_routes;
+use crate::rpc_endpoints::{nft_routes, settle_payments};
 use ed25519_consensus::Signature;
 use ed25519_consensus::SigningKey;
 use serde::{Deserialize, Serialize};
@@ -43,10 +44,12 @@ fn main() {
                     "clock network cage hen enough climb pencil visual spike eye marriage globe",
                 ),
                 app_id: 7,
+                post_proofs_to_da: std::env::var("POST_PROOFS_TO_DA").is_ok(),
+                chain_kind: ChainKind::Nft,
             },
             TRANSFER_ELF,
             TRANSFER_ID,
-            AppChain::Nft,
+            7000,
         )
         .await
     });
@@ -83,6 +86,15 @@ fn main() {
         });
 
         let mutex_app = Arc::new(Mutex::new(app_clone.clone()));
+        let events_app = app_clone.clone();
+        let events = tokio::spawn(async move {
+            events_app.follow_nexus().await;
+        });
+        let settlement_app = mutex_app.clone();
+        let settlement_key = signing_key.clone();
+        let settlement = tokio::spawn(async move {
+            settle_payments(settlement_key, settlement_app).await;
+        });
         let nft_routes = routes(mutex_app.clone()).or(nft_routes(mutex_app.clone(), signing_key));
         let cors = warp::cors()
             .allow_any_origin()
@@ -98,10 +110,10 @@ fn main() {
                 .await;
         });
 
-        let result = tokio::try_join!(execution_engine, rpc,);
+        let result = tokio::try_join!(execution_engine, rpc, events, settlement);
 
         match result {
-            Ok((_, _)) => {
+            Ok((_, _, _, _)) => {
                 println!("Exiting node, should not have happened.");
             }
             Err(e) => {
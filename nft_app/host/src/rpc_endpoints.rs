@@ -4,12 +4,20 @@ use core::convert::Infallible;
 use ed25519_consensus::{Signature, SigningKey};
 use nft_core::{
     app_node::AppNode,
+    events::{subscribe, NexusEvent},
     nft::{
         state_machine::NftStateMachine,
-        types::{Future, Nft, NftId, NftTransaction, NftTransactionMessage, Transfer, Trigger},
+        types::{
+            Future, FutureReceiptData, Nft, NftId, NftTransaction, NftTransactionMessage, Swap,
+            SwapLock, Transfer, Trigger,
+        },
+    },
+    payments::types::{
+        Account, CallType, EscrowRelease, Offer, PaymentReceiptData, SignedOffer,
+        Transaction as PaymentsTransaction, TransactionMessage,
     },
-    payments::types::{Account, CallType, PaymentReceiptData},
-    types::{Address, ClientReply, ShaHasher, TransactionReceipt, TxSignature},
+    receipts::{ReceiptData, ReceiptWithProof},
+    types::{AggregatedBatch, Address, ClientReply, ShaHasher, TransactionReceipt, TxSignature},
     utils::{hex_string_to_u8_array, u8_array_to_hex_string},
 };
 use primitive_types::U256;
@@ -21,7 +29,14 @@ use tokio::sync::Mutex;
 use warp::{reply::Reply, Filter, Rejection};
 
 const NFT_PRICE: u64 = 10;
+//Aggregated batches a buyer has to pay for an NFT held for them.
+const PAYMENT_WINDOW: u64 = 10;
 const NEXUS_RECEIPT_URL: &str = "http://127.0.0.1:8080/receipt";
+const NEXUS_LATEST_BATCH_URL: &str = "http://127.0.0.1:8080/current-batch";
+const NEXUS_EVENTS_URL: &str = "http://127.0.0.1:8080/events";
+const PAYMENTS_TX_URL: &str = "http://127.0.0.1:7001/tx";
+const NFT_CHAIN_ID: u64 = 7000;
+const PAYMENTS_CHAIN_ID: u64 = 7001;
 
 pub async fn get_listed_nfts(
     service: Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
@@ -99,7 +114,6 @@ pub async fn check_payment(
     id: String,
 ) -> Result<ClientReply<CheckPaymentReply>, Infallible> {
     println!("check status is called... 🫣🫣🫣🫣🫣🫣\n");
-    let verifying_key = Address(key.verification_key().to_bytes());
     let mut bytes = [0u8; 32];
     U256::from_dec_str(&id).unwrap().to_big_endian(&mut bytes);
     let nft_id = NftId(bytes);
@@ -127,9 +141,6 @@ pub async fn check_payment(
                 };
 
                 match tx_message {
-                    NftTransactionMessage::Burn(_) => continue,
-                    NftTransactionMessage::Mint(_) => continue,
-                    NftTransactionMessage::Trigger(_) => continue,
                     NftTransactionMessage::Transfer(i) => {
                         println!("Hold in progress 😎😎 \n");
 
@@ -142,6 +153,7 @@ pub async fn check_payment(
 
                         continue;
                     }
+                    _ => continue,
                 }
             }
 
@@ -170,8 +182,8 @@ pub async fn check_payment(
         Ok(i) => i,
         Err(e) => return Ok(ClientReply::Error(e.into())),
     };
-    let (receipt, proof): (TransactionReceipt, MerkleProof) = match response.json().await {
-        Ok(i) => i,
+    let (receipt, proof) = match response.json::<ReceiptWithProof>().await {
+        Ok(i) => (i.receipt, i.proof),
         Err(e) => return Ok(ClientReply::Error(e.into())),
     };
 
@@ -185,39 +197,92 @@ pub async fn check_payment(
     }
 
     //Check if there is no trigger already
-    {
-        let pool = app.get_tx_pool().lock().await;
+    if is_trigger_pending(&app, &nft_id).await {
+        return Ok(ClientReply::Ok(CheckPaymentReply {
+            nft_id: id,
+            status: TransferStatus::TransferInProgress,
+        }));
+    }
 
-        for tx in pool.iter() {
-            let tx_message = match NftTransactionMessage::try_from(tx.clone()) {
-                Ok(i) => i,
-                Err(e) => continue,
-            };
+    add_trigger(&app, &key, nft_id, receipt, proof).await;
 
-            match tx_message {
-                NftTransactionMessage::Burn(_) => continue,
-                NftTransactionMessage::Mint(_) => continue,
-                NftTransactionMessage::Transfer(i) => continue,
-                NftTransactionMessage::Trigger(i) => {
-                    if i.id == nft_id {
-                        return Ok(ClientReply::Ok(CheckPaymentReply {
-                            nft_id: id,
-                            status: TransferStatus::TransferInProgress,
-                        }));
-                    }
+    Ok(ClientReply::Ok(CheckPaymentReply {
+        nft_id: id,
+        status: TransferStatus::TransferInProgress,
+    }))
+}
+
+//Settles NFT purchases as soon as nexus includes their payment receipt, rather
+//than when the UI next checks the payment.
+pub async fn settle_payments(
+    key: SigningKey,
+    service: Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
+) {
+    let mut events = subscribe(String::from(NEXUS_EVENTS_URL));
+
+    while let Some(event) = events.recv().await {
+        let receipt_key = match event {
+            NexusEvent::ReceiptIncluded { key, .. } => key,
+            _ => continue,
+        };
+        let nfts: Vec<Nft> = {
+            let app = service.lock().await;
+            let state_machine = app.state_machine.lock().await;
+
+            match state_machine.get_listed_nfts() {
+                Ok(i) => i,
+                Err(e) => {
+                    println!("Could not get listed NFTs. {:?}", e);
 
                     continue;
                 }
             }
+        };
+
+        for nft in nfts {
+            match &nft.future {
+                Some(i) if i.commitment == receipt_key => (),
+                _ => continue,
+            }
+
+            let id = U256::from_big_endian(&nft.id.0).to_string();
+
+            match check_payment(key.clone(), service.clone(), id.clone()).await {
+                Ok(ClientReply::Ok(i)) => {
+                    println!("Payment for NFT {} received, status: {:?}", id, i.status)
+                }
+                Ok(ClientReply::Error(e)) => println!("Settling NFT {} failed. {:?}", id, e),
+                _ => (),
+            }
         }
     }
+}
 
+async fn is_trigger_pending(
+    app: &AppNode<Nft, NftTransaction, NftStateMachine>,
+    nft_id: &NftId,
+) -> bool {
+    let pool = app.get_tx_pool().lock().await;
+
+    pool.iter().any(|tx| match NftTransactionMessage::try_from(tx.clone()) {
+        Ok(NftTransactionMessage::Trigger(i)) => &i.id == nft_id,
+        _ => false,
+    })
+}
+
+async fn add_trigger(
+    app: &AppNode<Nft, NftTransaction, NftStateMachine>,
+    key: &SigningKey,
+    nft_id: NftId,
+    receipt: TransactionReceipt,
+    proof: MerkleProof,
+) {
     let trigger = Trigger {
         id: nft_id,
-        from: verifying_key,
+        from: Address(key.verification_key().to_bytes()),
         data: None,
         merkle_proof: proof,
-        receipt: receipt.clone(),
+        receipt,
     };
 
     let tx_message: NftTransactionMessage = NftTransactionMessage::Trigger(trigger);
@@ -230,11 +295,6 @@ pub async fn check_payment(
 
     println!("Adding to pool");
     app.add_to_tx_pool(tx).await;
-
-    Ok(ClientReply::Ok(CheckPaymentReply {
-        nft_id: id,
-        status: TransferStatus::TransferInProgress,
-    }))
 }
 
 async fn get_nonce(key: &str) -> Result<u64, Error> {
@@ -307,6 +367,10 @@ pub async fn buy_listed_nft(
     };
 
     let commitment_hash = transaction_receipt.to_h256();
+    let current_batch = match get_current_batch().await {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
 
     let transfer = Transfer {
         id: nft_id.clone(),
@@ -314,6 +378,7 @@ pub async fn buy_listed_nft(
         to: nft_to,
         data: None,
         future_commitment: Some(commitment_hash.clone()),
+        future_deadline: current_batch.proof_number + PAYMENT_WINDOW,
     };
 
     let nft_tx = NftTransactionMessage::Transfer(transfer.clone());
@@ -345,6 +410,487 @@ pub async fn buy_listed_nft(
     Ok(ClientReply::Ok(String::from("Transaction added to batch.")))
 }
 
+async fn get_payments_account(key: &str) -> Result<Account, Error> {
+    //TODO: Make this url configurable.
+    let url = format!("http://localhost:7001/state/{}", &key);
+    let response = reqwest::get(url).await?;
+
+    if response.status().is_success() {
+        let parsed_response: (Account, MerkleProof) = response.json().await?;
+
+        Ok(parsed_response.0)
+    } else {
+        Err(anyhow!("Could not get payments account."))
+    }
+}
+
+async fn get_receipt_with_proof(key: &H256) -> Result<(TransactionReceipt, MerkleProof), Error> {
+    let url = reqwest::Url::parse_with_params(
+        NEXUS_RECEIPT_URL,
+        &[("key", u8_array_to_hex_string(key.as_slice()))],
+    )?;
+    let response = reqwest::get(url.as_str()).await?;
+    let receipt_with_proof: ReceiptWithProof = response.json().await?;
+
+    Ok((receipt_with_proof.receipt, receipt_with_proof.proof))
+}
+
+async fn get_current_batch() -> Result<AggregatedBatch, Error> {
+    let response = reqwest::get(NEXUS_LATEST_BATCH_URL).await?;
+
+    Ok(response.json().await?)
+}
+
+fn parse_nft_id(id: &str) -> Result<NftId, Error> {
+    let mut bytes = [0u8; 32];
+    U256::from_dec_str(id)
+        .map_err(|e| anyhow!("{:?}", e))?
+        .to_big_endian(&mut bytes);
+
+    Ok(NftId(bytes))
+}
+
+pub async fn place_offer(
+    service: Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
+    signed_offer: SignedOffer,
+) -> Result<ClientReply<String>, Infallible> {
+    if !signed_offer.verify() {
+        return Ok(ClientReply::Error(anyhow!("Offer not signed by the buyer.")));
+    }
+
+    let offer = signed_offer.offer;
+
+    if offer.nft_chain_id != NFT_CHAIN_ID {
+        return Ok(ClientReply::Error(anyhow!("Offer is for a different chain.")));
+    }
+
+    //Offers are only accepted once the funds are locked on the payments chain.
+    let escrow_key = u8_array_to_hex_string(&offer.escrow_address().0);
+    let escrow_account = match get_payments_account(&escrow_key).await {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+
+    //Plain transfers to the escrow address leave its nonce at zero, only escrow
+    //funding marks it.
+    if escrow_account.nonce != 1 || escrow_account.balance < offer.amount {
+        return Ok(ClientReply::Error(anyhow!("Escrow not funded.")));
+    }
+
+    let app = service.lock().await;
+    let state_machine = app.state_machine.lock().await;
+
+    match state_machine.add_offer(offer) {
+        Ok(()) => Ok(ClientReply::Ok(String::from("Offer placed."))),
+        Err(e) => Ok(ClientReply::Error(e)),
+    }
+}
+
+pub async fn get_offers(
+    service: Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
+    id: String,
+) -> Result<ClientReply<Vec<Offer>>, Infallible> {
+    let nft_id = match parse_nft_id(&id) {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+    let app = service.lock().await;
+    let state_machine = app.state_machine.lock().await;
+
+    match state_machine.get_offers(&nft_id) {
+        Ok(i) => Ok(ClientReply::Ok(i)),
+        Err(e) => Ok(ClientReply::Error(e)),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AcceptOfferQuery {
+    nft_id: String,
+    escrow_address: String,
+}
+
+//Accepts an offer on an NFT held by the custodian, by putting it under a future
+//committing to the escrow release receipt.
+pub async fn accept_offer(
+    key_service: (
+        SigningKey,
+        Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
+    ),
+    params: AcceptOfferQuery,
+) -> Result<ClientReply<String>, Infallible> {
+    let service = key_service.1;
+    let signing_key = key_service.0;
+    let verifying_key = Address(signing_key.verification_key().to_bytes());
+    let nft_id = match parse_nft_id(&params.nft_id) {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+    let escrow_address = Address(match hex_string_to_u8_array(&params.escrow_address) {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    });
+
+    let app = service.lock().await;
+
+    let offer = {
+        let state_machine = app.state_machine.lock().await;
+
+        match state_machine.get_offers(&nft_id) {
+            Ok(offers) => match offers.into_iter().find(|i| i.escrow_address() == escrow_address) {
+                Some(i) => i,
+                None => return Ok(ClientReply::Error(anyhow!("Offer not found."))),
+            },
+            Err(e) => return Ok(ClientReply::Error(e)),
+        }
+    };
+
+    match get_current_batch().await {
+        Ok(i) if i.proof_number >= offer.expiry => {
+            return Ok(ClientReply::Error(anyhow!("Offer expired.")))
+        }
+        Ok(_) => (),
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+
+    match app.get_state(&nft_id.get_key()).await {
+        Ok(Some(nft)) => {
+            if nft.owner != verifying_key || nft.future.is_some() {
+                return Ok(ClientReply::Error(anyhow!("NFT not held by custodian.")));
+            }
+        }
+        Ok(None) => return Ok(ClientReply::Error(anyhow!("Nft not minted."))),
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+
+    let transfer = Transfer {
+        id: nft_id,
+        from: verifying_key.clone(),
+        to: offer.buyer.clone(),
+        data: None,
+        future_commitment: Some(
+            offer
+                .release_receipt(PAYMENTS_CHAIN_ID, &verifying_key)
+                .to_h256(),
+        ),
+        //Escrow can only be released until the offer expires.
+        future_deadline: offer.expiry,
+    };
+
+    let encoded_message = NftTransactionMessage::Transfer(transfer).to_encoded();
+    let signature: Signature = signing_key.sign(&encoded_message);
+
+    app.add_to_tx_pool(NftTransaction {
+        message: encoded_message,
+        signature: TxSignature::from(signature),
+    })
+    .await;
+
+    Ok(ClientReply::Ok(String::from("Transaction added to batch.")))
+}
+
+//Releases the escrow of an accepted offer, once the NFT future is aggregated by
+//nexus. The NFT is then transferred through the usual payment check.
+pub async fn settle_offer(
+    service: Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
+    id: String,
+) -> Result<ClientReply<String>, Infallible> {
+    let nft_id = match parse_nft_id(&id) {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+    let app = service.lock().await;
+
+    let nft = match app.get_state(&nft_id.get_key()).await {
+        Ok(Some(i)) => i,
+        Ok(None) => return Ok(ClientReply::Error(anyhow!("Nft not minted."))),
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+
+    let future = match nft.future.clone() {
+        Some(i) => i,
+        None => return Ok(ClientReply::Error(anyhow!("No offer accepted."))),
+    };
+
+    let offer = {
+        let state_machine = app.state_machine.lock().await;
+        let offers = match state_machine.get_offers(&nft_id) {
+            Ok(i) => i,
+            Err(e) => return Ok(ClientReply::Error(e)),
+        };
+
+        //Winning auction bids are not placed as offers, they are kept in the auction.
+        let auction_bid = nft.auction.clone().and_then(|i| i.highest_bid);
+
+        match offers.into_iter().chain(auction_bid).find(|i| {
+            i.buyer == future.to
+                && i.release_receipt(PAYMENTS_CHAIN_ID, &nft.owner).to_h256() == future.commitment
+        }) {
+            Some(i) => i,
+            None => return Ok(ClientReply::Error(anyhow!("Future is not for an offer."))),
+        }
+    };
+
+    let future_receipt = TransactionReceipt {
+        chain_id: NFT_CHAIN_ID,
+        data: (FutureReceiptData {
+            id: nft_id,
+            from: nft.owner.clone(),
+            to: future.to,
+            future_commitment: future.commitment,
+            deadline: future.deadline,
+            data: None,
+            nonce: nft.nonce,
+        })
+        .to_encoded(),
+    };
+
+    let (receipt, proof) = match get_receipt_with_proof(&future_receipt.to_h256()).await {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+
+    if receipt == TransactionReceipt::zero() {
+        return Ok(ClientReply::Error(anyhow!("Future not aggregated yet.")));
+    }
+
+    let message = TransactionMessage {
+        from: offer.escrow_address(),
+        to: nft.owner,
+        amount: offer.amount,
+        call_type: CallType::Release(EscrowRelease {
+            offer,
+            future_receipt: receipt,
+            merkle_proof: proof,
+        }),
+        data: None,
+    };
+    let client = reqwest::Client::new();
+    match client
+        .post(PAYMENTS_TX_URL)
+        .json(&PaymentsTransaction {
+            message: message.to_encoded(),
+            //Releases are authorised by the proof, signature is not checked.
+            signature: TxSignature::from([0u8; 64]),
+        })
+        .send()
+        .await
+    {
+        Ok(_) => Ok(ClientReply::Ok(String::from("Escrow release submitted."))),
+        Err(e) => Ok(ClientReply::Error(e.into())),
+    }
+}
+
+pub async fn propose_swap(
+    service: Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
+    swap: Swap,
+) -> Result<ClientReply<String>, Infallible> {
+    let app = service.lock().await;
+    let local_sides: Vec<_> = [&swap.first, &swap.second]
+        .into_iter()
+        .filter(|i| i.chain_id == NFT_CHAIN_ID)
+        .collect();
+
+    if local_sides.is_empty() {
+        return Ok(ClientReply::Error(anyhow!("Swap has no NFT on this chain.")));
+    }
+
+    for side in local_sides {
+        match app.get_state(&side.id.get_key()).await {
+            Ok(Some(nft)) => {
+                if nft.owner != side.owner || nft.nonce != side.nonce {
+                    return Ok(ClientReply::Error(anyhow!("Swap does not match NFT state.")));
+                }
+            }
+            Ok(None) => return Ok(ClientReply::Error(anyhow!("Nft not minted."))),
+            Err(e) => return Ok(ClientReply::Error(e)),
+        }
+    }
+
+    let state_machine = app.state_machine.lock().await;
+
+    match state_machine.add_swap(&swap) {
+        Ok(i) => Ok(ClientReply::Ok(u8_array_to_hex_string(i.as_slice()))),
+        Err(e) => Ok(ClientReply::Error(e)),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SwapQuery {
+    swap_id: String,
+}
+
+async fn get_swap(
+    app: &AppNode<Nft, NftTransaction, NftStateMachine>,
+    swap_id: &str,
+) -> Result<Swap, Error> {
+    let swap_id = H256::from(hex_string_to_u8_array(swap_id)?);
+    let state_machine = app.state_machine.lock().await;
+
+    match state_machine.get_swap(&swap_id)? {
+        Some(i) => Ok(i),
+        None => Err(anyhow!("Swap not found.")),
+    }
+}
+
+//Locks the side of a proposed swap that is held by the custodian.
+pub async fn accept_swap(
+    key_service: (
+        SigningKey,
+        Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
+    ),
+    params: SwapQuery,
+) -> Result<ClientReply<String>, Infallible> {
+    let service = key_service.1;
+    let signing_key = key_service.0;
+    let verifying_key = Address(signing_key.verification_key().to_bytes());
+    let app = service.lock().await;
+
+    let swap = match get_swap(&app, &params.swap_id).await {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+
+    let side = match [&swap.first, &swap.second]
+        .into_iter()
+        .find(|i| i.chain_id == NFT_CHAIN_ID && i.owner == verifying_key)
+    {
+        Some(i) => i.clone(),
+        None => return Ok(ClientReply::Error(anyhow!("No side held by custodian."))),
+    };
+
+    let encoded_message = NftTransactionMessage::SwapLock(SwapLock {
+        id: side.id,
+        from: verifying_key,
+        swap: swap.clone(),
+    })
+    .to_encoded();
+    let signature: Signature = signing_key.sign(&encoded_message);
+
+    app.add_to_tx_pool(NftTransaction {
+        message: encoded_message,
+        signature: TxSignature::from(signature),
+    })
+    .await;
+
+    Ok(ClientReply::Ok(String::from("Transaction added to batch.")))
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SwapSideStatus {
+    //NFT lives on another chain, only its lock receipt is tracked.
+    Remote,
+    NotLocked,
+    Locked,
+    Completed,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SwapSideReply {
+    pub chain_id: u64,
+    pub nft_id: NftId,
+    pub status: SwapSideStatus,
+    //Whether the lock receipt of this side is aggregated by nexus.
+    pub lock_included: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SwapStatusReply {
+    pub swap_id: String,
+    pub deadline: u64,
+    pub sides: Vec<SwapSideReply>,
+}
+
+//Reports the status of both sides of a swap, and triggers completion of the
+//local sides once the counterparty lock is aggregated, or reverts them after
+//the deadline.
+pub async fn check_swap(
+    key: SigningKey,
+    service: Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
+    swap_id: String,
+) -> Result<ClientReply<SwapStatusReply>, Infallible> {
+    let app = service.lock().await;
+
+    let swap = match get_swap(&app, &swap_id).await {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+
+    let current_batch = match get_current_batch().await {
+        Ok(i) => i,
+        Err(e) => return Ok(ClientReply::Error(e)),
+    };
+
+    let mut sides: Vec<SwapSideReply> = vec![];
+
+    for (side, counterparty) in [(&swap.first, &swap.second), (&swap.second, &swap.first)] {
+        let (lock_receipt, _) = match get_receipt_with_proof(&swap.lock_receipt(side).to_h256()).await
+        {
+            Ok(i) => i,
+            Err(e) => return Ok(ClientReply::Error(e)),
+        };
+        let lock_included = lock_receipt != TransactionReceipt::zero();
+
+        if side.chain_id != NFT_CHAIN_ID {
+            sides.push(SwapSideReply {
+                chain_id: side.chain_id,
+                nft_id: side.id.clone(),
+                status: SwapSideStatus::Remote,
+                lock_included,
+            });
+
+            continue;
+        }
+
+        let nft = match app.get_state(&side.id.get_key()).await {
+            Ok(Some(i)) => i,
+            Ok(None) => return Ok(ClientReply::Error(anyhow!("Nft not minted."))),
+            Err(e) => return Ok(ClientReply::Error(e)),
+        };
+        let counterparty_commitment = swap.lock_receipt(counterparty).to_h256();
+
+        let status = match &nft.future {
+            Some(i) if i.commitment == counterparty_commitment => SwapSideStatus::Locked,
+            _ if nft.nonce == side.nonce && nft.owner == side.owner => SwapSideStatus::NotLocked,
+            _ if nft.nonce > side.nonce && nft.owner == counterparty.owner => {
+                SwapSideStatus::Completed
+            }
+            _ => SwapSideStatus::Cancelled,
+        };
+
+        if status == SwapSideStatus::Locked && !is_trigger_pending(&app, &side.id).await {
+            let (receipt, proof) = match get_receipt_with_proof(&counterparty_commitment).await {
+                Ok(i) => i,
+                Err(e) => return Ok(ClientReply::Error(e)),
+            };
+
+            //A lock made before the deadline is aggregated within the settlement lag,
+            //so only revert once its absence is final.
+            let can_revert = match &nft.future {
+                Some(i) => i.can_revert(current_batch.proof_number),
+                None => false,
+            };
+
+            if receipt != TransactionReceipt::zero() || can_revert {
+                add_trigger(&app, &key, side.id.clone(), receipt, proof).await;
+            }
+        }
+
+        sides.push(SwapSideReply {
+            chain_id: side.chain_id,
+            nft_id: side.id.clone(),
+            status,
+            lock_included,
+        });
+    }
+
+    Ok(ClientReply::Ok(SwapStatusReply {
+        swap_id,
+        deadline: swap.deadline,
+        sides,
+    }))
+}
+
 pub fn nft_routes(
     service: Arc<Mutex<AppNode<Nft, NftTransaction, NftStateMachine>>>,
     signing_key: SigningKey,
@@ -353,6 +899,16 @@ pub fn nft_routes(
     let listed_nfts_app = service.clone();
     let check_payment_app = service.clone();
     let check_payment_signing_key = signing_key.clone();
+    let place_offer_app = service.clone();
+    let offers_app = service.clone();
+    let accept_offer_app = service.clone();
+    let accept_offer_signing_key = signing_key.clone();
+    let settle_offer_app = service.clone();
+    let propose_swap_app = service.clone();
+    let accept_swap_app = service.clone();
+    let accept_swap_signing_key = signing_key.clone();
+    let check_swap_app = service.clone();
+    let check_swap_signing_key = signing_key.clone();
 
     let listed_nfts = warp::get()
         .and(warp::path("listed-nfts"))
@@ -372,5 +928,57 @@ pub fn nft_routes(
         .and(warp::path::param::<String>())
         .and_then(check_payment);
 
-    listed_nfts.or(buy_nft).or(check_payment)
+    let place_offer = warp::post()
+        .and(warp::path("offer"))
+        .and(warp::any().map(move || place_offer_app.clone()))
+        .and(warp::body::json())
+        .and_then(place_offer);
+
+    let offers = warp::get()
+        .and(warp::path("offers"))
+        .and(warp::any().map(move || offers_app.clone()))
+        .and(warp::path::param::<String>())
+        .and_then(get_offers);
+
+    let accept_offer = warp::post()
+        .and(warp::path("accept-offer"))
+        .and(warp::any().map(move || (accept_offer_signing_key.clone(), accept_offer_app.clone())))
+        .and(warp::body::json())
+        .and_then(accept_offer);
+
+    let settle_offer = warp::get()
+        .and(warp::path("settle-offer"))
+        .and(warp::any().map(move || settle_offer_app.clone()))
+        .and(warp::path::param::<String>())
+        .and_then(settle_offer);
+
+    let propose_swap = warp::post()
+        .and(warp::path!("swap" / "propose"))
+        .and(warp::any().map(move || propose_swap_app.clone()))
+        .and(warp::body::json())
+        .and_then(propose_swap);
+
+    let accept_swap = warp::post()
+        .and(warp::path!("swap" / "accept"))
+        .and(warp::any().map(move || (accept_swap_signing_key.clone(), accept_swap_app.clone())))
+        .and(warp::body::json())
+        .and_then(accept_swap);
+
+    let check_swap = warp::get()
+        .and(warp::path("swap"))
+        .and(warp::any().map(move || check_swap_signing_key.clone()))
+        .and(warp::any().map(move || check_swap_app.clone()))
+        .and(warp::path::param::<String>())
+        .and_then(check_swap);
+
+    listed_nfts
+        .or(buy_nft)
+        .or(check_payment)
+        .or(place_offer)
+        .or(offers)
+        .or(accept_offer)
+        .or(settle_offer)
+        .or(propose_swap)
+        .or(accept_swap)
+        .or(check_swap)
 }
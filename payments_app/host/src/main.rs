@@ -5,8 +5,9 @@ use nft_core::{
         state_machine::PaymentsStateMachine,
         types::{Account, CallType, Transaction},
     },
+    receipts::ChainKind,
     traits::StateMachine,
-    types::{AppChain, ClientReply},
+    types::ClientReply,
 };
 use payments_methods::{TRANSFER_ELF, TRANSFER_ID};
 use primitive_types::U256;
@@ -45,10 +46,12 @@ fn main() {
                     "clock network cage hen enough climb pencil visual spike eye marriage globe",
                 ),
                 app_id: 8,
+                post_proofs_to_da: std::env::var("POST_PROOFS_TO_DA").is_ok(),
+                chain_kind: ChainKind::Payments,
             },
             TRANSFER_ELF,
             TRANSFER_ID,
-            AppChain::Payments,
+            7001,
         )
         .await
     });
@@ -77,6 +80,10 @@ fn main() {
         });
 
         let mutex_app = Arc::new(Mutex::new(app_clone.clone()));
+        let events_app = app_clone.clone();
+        let events = tokio::spawn(async move {
+            events_app.follow_nexus().await;
+        });
         let nft_routes = routes(mutex_app.clone());
         let cors = warp::cors()
             .allow_any_origin()
@@ -91,10 +98,10 @@ fn main() {
                 .await;
         });
 
-        let result = tokio::try_join!(execution_engine, rpc,);
+        let result = tokio::try_join!(execution_engine, rpc, events);
 
         match result {
-            Ok((_, _)) => {
+            Ok((_, _, _)) => {
                 println!("Exiting node, should not have happened.");
             }
             Err(e) => {
@@ -5,7 +5,7 @@ use nft_core::{
       types::{Account, CallType, Transaction},
   },
   traits::StateMachine,
-  types::{AppChain, ClientReply},
+  types::ClientReply,
 };
 use tokio::sync::Mutex;
 use std::sync::Arc;
@@ -16,7 +16,9 @@ pub fn main() {
     let state_update: StateUpdate<Account> = env::read();
     let batch_number: u64 = env::read();
     let aggregated_proof: AggregatedBatch = env::read();
-    let state_machine = ZKStateMachine::new(PaymentsStateTransition::new());
+    //Receipts commit to the chain ID, and nexus checks it against the chain of the proof.
+    let chain_id: u64 = env::read();
+    let state_machine = ZKStateMachine::new(PaymentsStateTransition::new(chain_id));
 
     let journal = match state_machine.execute_tx(payments_call_params, state_update.clone(), batch_number, aggregated_proof) {
         Ok(i) => i,